@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use windows::Win32::{Foundation::CloseHandle, System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Module32FirstW, Module32NextW, MODULEENTRY32W, TH32CS_SNAPMODULE, TH32CS_SNAPMODULE32}};
+
+use crate::watch::is_readable;
+
+/// An address written the Cheat Engine way: numbers are hexadecimal (decimal after `#`), `"module.exe"+offset` is relative
+/// to a loaded module, `[expr]` reads the pointer stored at `expr` and names refer to symbols.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Address {
+    Number(usize),
+    Module(String),
+    Symbol(String), // Symbol, or module when no symbol has that name
+    Deref(Box<Address>),
+    Add(Box<Address>, Box<Address>),
+    Sub(Box<Address>, Box<Address>),
+}
+
+#[derive(Clone, Copy)]
+pub struct Module {
+    pub base: usize,
+    pub size: usize,
+}
+
+/// Loaded modules of the game process, by lowercase name.
+#[derive(Default)]
+pub struct Modules {
+    modules: HashMap<String, Module>,
+}
+
+pub struct Resolver<'a> {
+    pub symbols: &'a HashMap<String, Address>,
+    pub modules: &'a Modules,
+}
+
+impl Address {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+        let address = parser.expr()?;
+        parser.skip_spaces();
+        if parser.pos != parser.text.len() {
+            return Err(format!("Unexpected \"{}\" in \"{text}\"", &text[parser.pos..]));
+        }
+        Ok(address)
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{n:X}"),
+            Self::Module(name) => write!(f, "\"{name}\""),
+            Self::Symbol(name) => write!(f, "{name}"),
+            Self::Deref(a) => write!(f, "[{a}]"),
+            Self::Add(a, b) => write!(f, "{a}+{b}"),
+            Self::Sub(a, b) => write!(f, "{a}-{b}"),
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_spaces(&mut self) {
+        while self.text.get(self.pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expr(&mut self) -> Result<Address, String> {
+        let mut left = self.term()?;
+        loop {
+            self.skip_spaces();
+            match self.text.get(self.pos) {
+                Some(b'+') => {
+                    self.pos += 1;
+                    left = Address::Add(Box::new(left), Box::new(self.term()?));
+                }
+                Some(b'-') => {
+                    self.pos += 1;
+                    left = Address::Sub(Box::new(left), Box::new(self.term()?));
+                }
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Address, String> {
+        self.skip_spaces();
+        match self.text.get(self.pos) {
+            Some(b'[') => {
+                self.pos += 1;
+                let inner = self.expr()?;
+                self.skip_spaces();
+                if self.text.get(self.pos) != Some(&b']') {
+                    return Err(format!("Missing ']' at position {}", self.pos));
+                }
+                self.pos += 1;
+                Ok(Address::Deref(Box::new(inner)))
+            }
+            Some(b'"') => {
+                let start = self.pos + 1;
+                let Some(len) = self.text[start..].iter().position(|&c| c == b'"') else {
+                    return Err("Missing closing '\"'".to_string());
+                };
+                self.pos = start + len + 1;
+                Ok(Address::Module(String::from_utf8_lossy(&self.text[start..start + len]).to_string()))
+            }
+            Some(b'#') => {
+                let start = self.pos + 1;
+                self.pos = start + self.text[start..].iter().take_while(|c| c.is_ascii_digit()).count();
+                let digits = String::from_utf8_lossy(&self.text[start..self.pos]).to_string();
+                digits.parse().map(Address::Number).map_err(|_| format!("Invalid decimal number at position {start}"))
+            }
+            Some(c) if c.is_ascii_alphanumeric() || *c == b'_' => {
+                let start = self.pos;
+                while self.text.get(self.pos).is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'.')) {
+                    self.pos += 1;
+                }
+                let word = String::from_utf8_lossy(&self.text[start..self.pos]).to_string();
+                let hex = word.strip_prefix("0x").or(word.strip_prefix("0X")).unwrap_or(&word);
+                if word.as_bytes()[0].is_ascii_digit() {
+                    usize::from_str_radix(hex, 16).map(Address::Number).map_err(|_| format!("Invalid number \"{word}\""))
+                } else {
+                    // Plain names like "deadbeef" are only numbers when nothing else has that name
+                    Ok(Address::Symbol(word))
+                }
+            }
+            Some(c) => Err(format!("Unexpected '{}' at position {}", *c as char, self.pos)),
+            None => Err("Unexpected end of the address".to_string()),
+        }
+    }
+}
+
+impl Modules {
+    pub fn refresh(&mut self) {
+        self.modules.clear();
+        unsafe {
+            let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPMODULE | TH32CS_SNAPMODULE32, 0) else {
+                println!("========== Could not list the game modules ==========");
+                return;
+            };
+            let mut entry = MODULEENTRY32W {
+                dwSize: std::mem::size_of::<MODULEENTRY32W>() as u32,
+                ..Default::default()
+            };
+            let mut next = Module32FirstW(snapshot, &mut entry);
+            while next.is_ok() {
+                let len = entry.szModule.iter().position(|&c| c == 0).unwrap_or(entry.szModule.len());
+                self.modules.insert(String::from_utf16_lossy(&entry.szModule[..len]).to_lowercase(), Module {
+                    base: entry.modBaseAddr.addr(),
+                    size: entry.modBaseSize as usize,
+                });
+                next = Module32NextW(snapshot, &mut entry);
+            }
+            let _ = CloseHandle(snapshot);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Module> {
+        self.modules.get(&name.to_lowercase()).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Module)> {
+        self.modules.iter()
+    }
+}
+
+impl Resolver<'_> {
+    pub fn resolve(&self, address: &Address) -> Result<usize, String> {
+        let mut links = 0;
+        self.resolve_inner(address, &mut links, 0)
+    }
+
+    fn resolve_inner(&self, address: &Address, links: &mut usize, depth: usize) -> Result<usize, String> {
+        if depth > 32 {
+            return Err("Symbols reference each other in a loop".to_string());
+        }
+        Ok(match address {
+            Address::Number(n) => *n,
+            Address::Module(name) => self.modules.get(name).ok_or(format!("Module \"{name}\" is not loaded"))?.base,
+            Address::Symbol(name) => {
+                if let Some(symbol) = self.symbols.get(name) {
+                    self.resolve_inner(symbol, links, depth + 1)?
+                } else if let Some(module) = self.modules.get(name) {
+                    module.base
+                } else {
+                    usize::from_str_radix(name, 16).map_err(|_| format!("Unknown symbol or module \"{name}\""))?
+                }
+            }
+            Address::Deref(inner) => {
+                let ptr = self.resolve_inner(inner, links, depth)?;
+                *links += 1;
+                if !is_readable(ptr, std::mem::size_of::<usize>()) {
+                    return Err(format!("Pointer link {links} ([{inner}]) points to unreadable memory at 0x{ptr:x}"));
+                }
+                let value = unsafe { (ptr as *const usize).read_unaligned() };
+                if value == 0 {
+                    return Err(format!("Pointer link {links} ([{inner}]) is null"));
+                }
+                value
+            }
+            Address::Add(a, b) => self.resolve_inner(a, links, depth)?.wrapping_add(self.resolve_inner(b, links, depth)?),
+            Address::Sub(a, b) => self.resolve_inner(a, links, depth)?.wrapping_sub(self.resolve_inner(b, links, depth)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(n: usize) -> Box<Address> {
+        Box::new(Address::Number(n))
+    }
+
+    #[test]
+    fn module_offset() {
+        assert_eq!(Address::parse("\"Game.exe\"+1A2B").unwrap(), Address::Add(Box::new(Address::Module("Game.exe".to_string())), number(0x1a2b)));
+        assert_eq!(Address::parse("Game.exe + 10").unwrap(), Address::Add(Box::new(Address::Symbol("Game.exe".to_string())), number(0x10)));
+    }
+
+    #[test]
+    fn nested_pointers() {
+        let address = Address::parse("[[\"Game.exe\"+10]+ 4C ]-8").unwrap();
+        let inner = Address::Deref(Box::new(Address::Add(Box::new(Address::Module("Game.exe".to_string())), number(0x10))));
+        assert_eq!(address, Address::Sub(Box::new(Address::Deref(Box::new(Address::Add(Box::new(inner), number(0x4c))))), number(8)));
+        assert_eq!(address.to_string(), "[[\"Game.exe\"+10]+4C]-8");
+        assert_eq!(Address::parse(&address.to_string()).unwrap(), address);
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(Address::parse("10").unwrap(), Address::Number(0x10));
+        assert_eq!(Address::parse("0x7fF0").unwrap(), Address::Number(0x7ff0));
+        assert_eq!(Address::parse("#100").unwrap(), Address::Number(100));
+        assert_eq!(Address::parse("base+#16").unwrap(), Address::Add(Box::new(Address::Symbol("base".to_string())), number(16)));
+        // Names are symbols until they are resolved, even when they read as hexadecimal
+        assert_eq!(Address::parse("deadbeef").unwrap(), Address::Symbol("deadbeef".to_string()));
+    }
+
+    #[test]
+    fn malformed() {
+        for text in ["", "[10", "10]", "\"Game.exe+10", "10+", "1G", "#", "#1A", "10 20", "+10", "[]"] {
+            assert!(Address::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn resolve_symbols() {
+        let value = Box::new(0x1234usize);
+        let symbols = HashMap::from([
+            ("ptr".to_string(), Address::Number(&*value as *const usize as usize)),
+            ("loop".to_string(), Address::Symbol("loop".to_string())),
+        ]);
+        let modules = Modules::default();
+        let resolver = Resolver { symbols: &symbols, modules: &modules };
+        assert_eq!(resolver.resolve(&Address::parse("[ptr]+#6").unwrap()), Ok(0x123a));
+        assert_eq!(resolver.resolve(&Address::parse("deadbeef").unwrap()), Ok(0xdeadbeef));
+        assert!(resolver.resolve(&Address::parse("loop").unwrap()).is_err());
+        assert!(resolver.resolve(&Address::parse("\"missing.dll\"").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+/// Owns every buffer handed to the game and remembers which game slots point at each one.
+/// A buffer is only released once no slot references it anymore.
+#[derive(Default)]
+pub struct Arena {
+    buffers: HashMap<usize, Box<[u8]>>, // Address -> buffer
+    slots: HashMap<usize, usize>, // Game slot -> buffer address
+}
+
+impl Arena {
+    /// Keeps `data` alive at a fixed address until every slot pointing at it is restored.
+    pub fn alloc(&mut self, data: Vec<u8>) -> usize {
+        let data = data.into_boxed_slice();
+        let addr = data.as_ptr().addr();
+        self.buffers.insert(addr, data);
+        addr
+    }
+
+    pub fn get(&self, addr: usize) -> Option<&[u8]> {
+        self.buffers.get(&addr).map(|b| &b[..])
+    }
+
+    /// For buffers that point into themselves, which can only be filled once their address is known.
+    pub fn get_mut(&mut self, addr: usize) -> Option<&mut [u8]> {
+        self.buffers.get_mut(&addr).map(|b| &mut b[..])
+    }
+
+    /// Writes `value` to the game `slot`, recording that it now references `buffer` (or nothing of ours with `None`).
+    /// # Safety
+    /// `slot` must be a writable `u32` in the game memory.
+    pub unsafe fn set(&mut self, slot: usize, value: u32, buffer: Option<usize>) {
+        (slot as *mut u32).write_unaligned(value);
+        let old = match buffer {
+            Some(buffer) => self.slots.insert(slot, buffer),
+            None => self.slots.remove(&slot),
+        };
+        if let Some(old) = old {
+            self.release(old);
+        }
+    }
+
+    /// Frees a buffer that no slot references, buffers still in use are kept.
+    pub fn release(&mut self, addr: usize) {
+        if !self.slots.values().any(|&b| b == addr) && self.buffers.remove(&addr).is_some() {
+            println!("Released buffer at 0x{addr:x}");
+        }
+    }
+
+    pub fn references(&self, addr: usize) -> usize {
+        self.slots.values().filter(|&&b| b == addr).count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    /// Total size of the buffers injected into the game.
+    pub fn size(&self) -> usize {
+        self.buffers.values().map(|b| b.len()).sum()
+    }
+}
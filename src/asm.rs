@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+
+use crate::code::*;
+use crate::disasm::{instance_name, Disassembler};
+use crate::form::{append, put_u32, Form};
+
+/// Bytecode built from disassembly text, with the references still to be linked.
+#[derive(Default)]
+pub struct Assembled {
+    pub bytes: Vec<u8>,
+    pub variables: Vec<(usize, usize)>, // Instruction offset and VARI index
+    pub functions: Vec<(usize, usize)>, // Instruction offset and FUNC index
+}
+
+// Reference kinds stored in the top bits of variable operands
+const REF_KINDS: [(&str, u32); 6] = [("[array]", 0x00), ("[stacktop]", 0x80), ("[instance]", 0xe0), ("[arraypushaf]", 0x10), ("[arraypopaf]", 0x90), ("", 0xa0)];
+
+enum Operand {
+    None,
+    Bytes(Vec<u8>),
+    Variable(usize, u32), // VARI index and reference kind
+    Function(usize),
+    Raw(u32),
+}
+
+struct Line {
+    opcode: u8,
+    type1: u8,
+    type2: u8,
+    value: u16,
+    operand: Operand,
+    target: Option<String>, // Label of a branch
+}
+
+fn parse_type(c: &str) -> Result<u8, String> {
+    Ok(match c {
+        "d" => TYPE_DOUBLE,
+        "f" => TYPE_FLOAT,
+        "i" => TYPE_INT32,
+        "l" => TYPE_INT64,
+        "b" => TYPE_BOOL,
+        "v" => TYPE_VARIABLE,
+        "s" => TYPE_STRING,
+        "e" => TYPE_INT16,
+        _ => return Err(format!("Unknown type \".{c}\"")),
+    })
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.trim().parse().map_err(|_| format!("Invalid number \"{}\"", text.trim()))
+}
+
+/// Reverses [`escape`](crate::strings::escape) for the `"text"@index` string operands.
+fn parse_string(text: &str) -> Result<(Vec<u8>, Option<usize>), String> {
+    let (quoted, index) = match text.rsplit_once('@') {
+        Some((quoted, index)) if quoted.ends_with('"') => (quoted, Some(parse_number(index)?)),
+        _ => (text, None),
+    };
+    let inner = quoted.strip_prefix('"').and_then(|q| q.strip_suffix('"')).ok_or(format!("Invalid string {text}"))?;
+    Ok((crate::strings::unescape(&inner.replace("\\\"", "\""), true), index))
+}
+
+impl Disassembler {
+    fn instance(&self, name: &str) -> Result<i16, String> {
+        if let Some(i) = (-16..0).find(|&i| instance_name(i, &self.objects) == name) {
+            return Ok(i);
+        }
+        if let Some(i) = self.objects.iter().position(|o| o == name) {
+            return Ok(i as i16);
+        }
+        parse_number(name).map_err(|_| format!("Unknown instance \"{name}\""))
+    }
+
+    /// Variables are looked up by name, preferring the VARI entry of the same scope.
+    fn find_variable(&self, name: &str, instance: i16) -> Result<usize, String> {
+        let scope = match instance {
+            -5 | -7 => instance as i32,
+            _ => -1,
+        };
+        let candidates = self.variables.iter().enumerate().filter(|(_, v)| v.name == name).map(|(i, _)| i).collect::<Vec<usize>>();
+        candidates.iter().find(|&&i| self.variables[i].instance_type == scope).or(candidates.first()).copied()
+            .ok_or(format!("Unknown variable \"{name}\", new variables can't be added"))
+    }
+
+    fn find_string(&self, string: &[u8], index: Option<usize>) -> Result<usize, String> {
+        if let Some(index) = index.filter(|&i| self.strings.get(i).is_some_and(|s| s == string)) {
+            return Ok(index);
+        }
+        self.strings.iter().position(|s| s == string)
+            .ok_or(format!("\"{}\" is not in STRG, edit an existing string instead", String::from_utf8_lossy(string)))
+    }
+
+    fn parse_variable(&self, text: &str) -> Result<(i16, Operand), String> {
+        let (kind, rest) = REF_KINDS.iter().find_map(|(prefix, kind)| text.strip_prefix(prefix).map(|r| (*kind, r))).unwrap();
+        let (instance, name) = rest.split_once('.').ok_or(format!("Invalid variable \"{text}\""))?;
+        let instance = self.instance(instance)?;
+        if let Some(raw) = name.strip_prefix("<var 0x").and_then(|n| n.strip_suffix('>')) {
+            let raw = u32::from_str_radix(raw, 16).map_err(|_| format!("Invalid variable \"{text}\""))?;
+            return Ok((instance, Operand::Raw(kind << 24 | raw)));
+        }
+        Ok((instance, Operand::Variable(self.find_variable(name, instance)?, kind)))
+    }
+
+    fn parse_line(&self, text: &str) -> Result<Line, String> {
+        let (mnemonic, operand) = text.split_once(' ').unwrap_or((text, ""));
+        let operand = operand.trim();
+        let mut parts = mnemonic.split('.');
+        let name = parts.next().unwrap();
+        let opcode = (0..=255u8).find(|&o| opcode_name(o) == name).filter(|_| name != "???").ok_or(format!("Unknown instruction \"{name}\""))?;
+        let type1 = parts.next().map(parse_type).transpose()?.unwrap_or(0);
+        let type2 = parts.next().map(parse_type).transpose()?.unwrap_or(0);
+        let mut line = Line { opcode, type1, type2, value: 0, operand: Operand::None, target: None };
+        match opcode {
+            OP_POP if type1 == TYPE_INT16 => line.value = parse_number::<i16>(operand)? as u16,
+            OP_POP => (line.value, line.operand) = self.parse_variable(operand).map(|(i, o)| (i as u16, o))?,
+            OP_PUSH | OP_PUSHLOC | OP_PUSHGLB | OP_PUSHBLTN | OP_PUSHI => match type1 {
+                TYPE_VARIABLE => (line.value, line.operand) = self.parse_variable(operand).map(|(i, o)| (i as u16, o))?,
+                TYPE_DOUBLE => line.operand = Operand::Bytes(parse_number::<f64>(operand)?.to_le_bytes().to_vec()),
+                TYPE_FLOAT => line.operand = Operand::Bytes(parse_number::<f32>(operand)?.to_le_bytes().to_vec()),
+                TYPE_INT32 => line.operand = Operand::Bytes(parse_number::<i32>(operand)?.to_le_bytes().to_vec()),
+                TYPE_INT64 => line.operand = Operand::Bytes(parse_number::<i64>(operand)?.to_le_bytes().to_vec()),
+                TYPE_BOOL => line.operand = Operand::Bytes((parse_number::<bool>(operand)? as u32).to_le_bytes().to_vec()),
+                TYPE_STRING => {
+                    let (string, index) = parse_string(operand)?;
+                    line.operand = Operand::Bytes((self.find_string(&string, index)? as u32).to_le_bytes().to_vec());
+                }
+                TYPE_INT16 => line.value = parse_number::<i16>(operand)? as u16,
+                _ => return Err(format!("{name} can't push this type")),
+            },
+            OP_CALL => {
+                let (function, argc) = operand.strip_suffix(')').and_then(|o| o.split_once("(argc="))
+                    .ok_or(format!("Expected name(argc=N), found \"{operand}\""))?;
+                line.value = parse_number(argc)?;
+                line.operand = match function.strip_prefix("<func 0x").and_then(|f| f.strip_suffix('>')) {
+                    Some(raw) => Operand::Raw(u32::from_str_radix(raw, 16).map_err(|_| format!("Invalid function \"{function}\""))?),
+                    None => Operand::Function(self.functions.iter().position(|f| f.name == function)
+                        .ok_or(format!("Unknown function \"{function}\", new functions can't be added"))?),
+                };
+            }
+            OP_CALLV | OP_DUP => line.value = parse_number(operand)?,
+            OP_CMP => line.value = (CMP_KINDS.iter().skip(1).position(|&k| k == operand).ok_or(format!("Unknown comparison \"{operand}\""))? as u16 + 1) << 8,
+            OP_POPENV if operand == "<exit>" => {
+                line.type2 = (POPENV_EXIT >> 20) as u8;
+            }
+            OP_B | OP_BT | OP_BF | OP_PUSHENV | OP_POPENV => {
+                line.target = Some(operand.strip_prefix('[').and_then(|o| o.strip_suffix(']')).ok_or(format!("Expected [label], found \"{operand}\""))?.to_string());
+            }
+            OP_BREAK => {
+                let mut values = operand.split_whitespace();
+                line.value = parse_number::<i16>(values.next().unwrap_or("0"))? as u16;
+                if let Some(argument) = values.next() {
+                    line.type1 = TYPE_INT32;
+                    line.operand = Operand::Bytes(parse_number::<i32>(argument)?.to_le_bytes().to_vec());
+                }
+            }
+            _ if !operand.is_empty() => return Err(format!("{name} takes no operand")),
+            _ => {}
+        }
+        Ok(line)
+    }
+
+    /// Builds bytecode from the text of [`Disassembler::disassemble`]. Branches refer to `:[label]` lines,
+    /// or to an offset from the start of the entry when no label has that name.
+    pub fn assemble(&self, text: &str) -> Result<Assembled, String> {
+        if self.version < 15 {
+            return Err(format!("Assembling bytecode {} is not supported", self.version));
+        }
+        let mut lines = Vec::new();
+        let mut labels = HashMap::new();
+        let mut size = 0;
+        for (number, text) in text.lines().enumerate() {
+            let text = text.trim();
+            if text.starts_with(';') {
+                continue;
+            }
+            if let Some(label) = text.strip_prefix(":[").and_then(|t| t.strip_suffix(']')) {
+                labels.insert(label.to_string(), size);
+                continue;
+            }
+            // Skip the address printed by the disassembler
+            let text = match text.split_once(": ") {
+                Some((address, rest)) if address.chars().all(|c| c.is_ascii_hexdigit()) => rest.trim(),
+                _ => text,
+            };
+            if text.is_empty() {
+                continue;
+            }
+            let line = self.parse_line(text).map_err(|e| format!("Line {}: {e}", number + 1))?;
+            let operand_len = match &line.operand {
+                Operand::None => 0,
+                Operand::Bytes(b) => b.len(),
+                Operand::Variable(..) | Operand::Function(_) | Operand::Raw(_) => 4,
+            };
+            if operand_len != operand_size(line.opcode, line.type1) {
+                return Err(format!("Line {}: wrong operand size for {text}", number + 1));
+            }
+            lines.push((number, size, line));
+            size += 4 + operand_len;
+        }
+        let mut assembled = Assembled::default();
+        for (number, address, line) in lines {
+            let mut word = (line.opcode as u32) << 24 | (line.type2 as u32) << 20 | (line.type1 as u32) << 16 | line.value as u32;
+            if let Some(target) = &line.target {
+                let target = match labels.get(target) {
+                    Some(&t) => t,
+                    None => usize::from_str_radix(target, 16).map_err(|_| format!("Line {}: unknown label [{target}]", number + 1))?,
+                };
+                // 23 bit signed offset in instructions
+                let offset = (target as isize - address as isize) / 4;
+                if !(-0x400000..0x400000).contains(&offset) {
+                    return Err(format!("Line {}: branch too far", number + 1));
+                }
+                word = (line.opcode as u32) << 24 | (offset as u32 & 0x7fffff);
+            }
+            assembled.bytes.extend(word.to_le_bytes());
+            match line.operand {
+                Operand::None => {}
+                Operand::Bytes(bytes) => assembled.bytes.extend(bytes),
+                Operand::Raw(raw) => assembled.bytes.extend(raw.to_le_bytes()),
+                Operand::Variable(index, kind) => {
+                    assembled.variables.push((address, index));
+                    assembled.bytes.extend((kind << 24).to_le_bytes());
+                }
+                Operand::Function(index) => {
+                    assembled.functions.push((address, index));
+                    assembled.bytes.extend(0u32.to_le_bytes());
+                }
+            }
+        }
+        Ok(assembled)
+    }
+
+    /// Operands the runner wrote over the reference chains when it linked the code in `live`,
+    /// read back at the occurrences of each VARI and FUNC entry.
+    pub fn live_links(&self, live: &Form) -> (HashMap<usize, u32>, HashMap<usize, u32>) {
+        let read = |refs: &HashMap<usize, usize>| refs.iter().filter_map(|(&address, &index)| Some((index, live.u32(address + 4)?))).collect();
+        (read(&self.variable_refs), read(&self.function_refs))
+    }
+
+    /// Fills the references with the values the runner uses once linked, for code patched in memory.
+    pub fn link_live(&self, assembled: &mut Assembled, live: &Form) -> Result<(), String> {
+        let (variables, functions) = self.live_links(live);
+        for &(address, index) in &assembled.variables {
+            let linked = variables.get(&index).ok_or(format!("{} is never used by the game code, its runtime slot is unknown", self.variables[index].name))?;
+            let operand = &mut assembled.bytes[address + 4..address + 8];
+            let kind = u32::from_le_bytes(operand.try_into().unwrap()) & 0xf8000000;
+            operand.copy_from_slice(&(kind | linked & 0x07ffffff).to_le_bytes());
+        }
+        for &(address, index) in &assembled.functions {
+            let linked = functions.get(&index).ok_or(format!("{} is never called by the game code, its runtime index is unknown", self.functions[index].name))?;
+            assembled.bytes[address + 4..address + 8].copy_from_slice(&linked.to_le_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the bytecode of `entry` in a data.win loaded in `data`. The new code overwrites the old one
+/// when it fits and is not shared with other entries, otherwise it is appended to the last chunk.
+/// The reference chains of VARI and FUNC are rebuilt to include the new occurrences.
+pub fn patch_file(data: &mut Vec<u8>, disassembler: &Disassembler, entry: &CodeEntry, assembled: &Assembled) -> Result<(), String> {
+    let form = Form::new(data).ok_or("Not a GameMaker data file")?;
+    // The last occurrence keeps the value that ends its chain
+    let chain_ends = |refs: &HashMap<usize, usize>| {
+        let mut ends: HashMap<usize, usize> = HashMap::new();
+        for (&address, &index) in refs {
+            let end = ends.entry(index).or_insert(address);
+            *end = address.max(*end);
+        }
+        ends.into_iter().map(|(index, address)| (index, form.u32(address + 4).unwrap() & 0x07ffffff)).collect::<HashMap<usize, u32>>()
+    };
+    let variable_ends = chain_ends(&disassembler.variable_refs);
+    let function_ends = chain_ends(&disassembler.function_refs);
+
+    let shared = disassembler.code.iter().any(|e| e.bytecode == entry.bytecode && e.ptr != entry.ptr);
+    let (start, old_range) = if entry.offset == 0 && !shared && assembled.bytes.len() <= entry.length {
+        (entry.bytecode, entry.bytecode..entry.bytecode + entry.length)
+    } else {
+        (append(data, &assembled.bytes)?, 0..0)
+    };
+    let chains = |refs: &HashMap<usize, usize>, new: &[(usize, usize)], count: usize| {
+        let mut chains = vec![Vec::new(); count];
+        for (&address, &index) in refs {
+            if !old_range.contains(&address) {
+                chains[index].push(address);
+            }
+        }
+        for &(address, index) in new {
+            chains[index].push(start + address);
+        }
+        for chain in &mut chains {
+            chain.sort();
+        }
+        chains
+    };
+    let variable_chains = chains(&disassembler.variable_refs, &assembled.variables, disassembler.variables.len());
+    let function_chains = chains(&disassembler.function_refs, &assembled.functions, disassembler.functions.len());
+    data[start..start + assembled.bytes.len()].copy_from_slice(&assembled.bytes);
+    let mut link = |chain: &[usize], end: u32, entry: usize, occurrences: usize| {
+        for (i, &address) in chain.iter().enumerate() {
+            let next = chain.get(i + 1).map_or(end, |&n| (n - address) as u32);
+            let operand = u32::from_le_bytes(data[address + 4..address + 8].try_into().unwrap());
+            put_u32(data, address + 4, operand & 0xf8000000 | next & 0x07ffffff);
+        }
+        put_u32(data, entry + occurrences, chain.len() as u32);
+        put_u32(data, entry + occurrences + 4, chain.first().map_or(0, |&a| (a + disassembler.first_address_offset) as u32));
+    };
+    // Names used for the first time end their chain with the STRG index of the name
+    let name_index = |name: &str| disassembler.strings.iter().position(|s| s == name.as_bytes()).unwrap_or(0) as u32;
+    for (i, chain) in variable_chains.iter().enumerate() {
+        let end = variable_ends.get(&i).copied().unwrap_or_else(|| name_index(&disassembler.variables[i].name));
+        link(chain, end, disassembler.variables[i].ptr, 12);
+    }
+    for (i, chain) in function_chains.iter().enumerate() {
+        let end = function_ends.get(&i).copied().unwrap_or_else(|| name_index(&disassembler.functions[i].name));
+        link(chain, end, disassembler.functions[i].ptr, 4);
+    }
+    put_u32(data, entry.ptr + 4, assembled.bytes.len() as u32);
+    put_u32(data, entry.ptr + 12, (start as isize - (entry.ptr + 12) as isize) as u32);
+    put_u32(data, entry.ptr + 16, 0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::form::builder::{word, words, FormBuilder};
+
+    #[test]
+    fn loop_round_trip() {
+        let bytecode = words(&[
+            word(OP_PUSHI, 0, TYPE_INT16, 1), // 00
+            word(OP_CONV, TYPE_BOOL, TYPE_INT16, 0), // 04
+            word(OP_BF, 0, 0, 3), // 08: to 14
+            word(OP_PUSHI, 0, TYPE_INT16, 2), // 0C
+            word(OP_B, 0x7, 0xf, 0xfffc), // 10: back to 00
+            word(OP_PUSHI, 0, TYPE_INT16, 5), // 14
+            word(OP_PUSHENV, 0, 0, 2), // 18: to 20
+            word(OP_POPENV, 0xf, 0, 0), // 1C: exit
+            word(OP_POPENV, 0x7, 0xf, 0xffff), // 20: back to 1C
+            word(OP_EXIT, 0, TYPE_INT32, 0), // 24
+        ]);
+        let mut builder = FormBuilder::new(17, &["gml_Script_loop"]);
+        builder.code(&[(0, &bytecode)]);
+        let data = builder.build();
+        let form = Form::new(&data).unwrap();
+        let disassembler = Disassembler::new(&form);
+        let text = disassembler.disassemble(&form, &disassembler.code[0]);
+        assert!(text.contains("b [00000]"), "{text}");
+        assert!(text.contains("popenv <exit>"), "{text}");
+        assert_eq!(disassembler.assemble(&text).unwrap().bytes, bytecode);
+    }
+}
@@ -0,0 +1,514 @@
+use std::{collections::{BTreeSet, HashMap, HashSet}, fs, path::Path};
+
+use crate::form::{append, put_u16, put_u32, Form};
+use crate::sprite::{page_item, parse_page_items, parse_sprites, PageCache, Sprite};
+use crate::texture::{decode_png, encode_texture, parse_textures, Image};
+
+/// Space kept between packed regions, so filtering doesn't bleed into the neighbours.
+const PADDING: u32 = 2;
+/// Size of the new pages when none of the replaced sprites was on a page.
+const DEFAULT_PAGE: (u32, u32) = (2048, 2048);
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct Placement {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+}
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    width: u32, // Used so far
+}
+
+/// Shelf packing: regions go left to right in rows, tallest first, and a row is as tall as its first
+/// region. A new page starts when no row has room left.
+pub fn pack(sizes: &[(u32, u32)], (page_width, page_height): (u32, u32)) -> Result<Vec<Placement>, String> {
+    let mut order = (0..sizes.len()).collect::<Vec<usize>>();
+    order.sort_by(|&a, &b| sizes[b].1.cmp(&sizes[a].1).then(sizes[b].0.cmp(&sizes[a].0)));
+    let mut pages: Vec<Vec<Shelf>> = Vec::new();
+    let mut placements = vec![Placement::default(); sizes.len()];
+    for i in order {
+        // Padding goes after each region, the last one of a row or page can touch the edge
+        let (width, height) = sizes[i];
+        if width > page_width || height > page_height {
+            return Err(format!("A {width}x{height} region doesn't fit in a {page_width}x{page_height} page"));
+        }
+        let shelf = pages.iter().enumerate().find_map(|(page, shelves)| {
+            shelves.iter().position(|s| height <= s.height && s.width + width <= page_width).map(|s| (page, s))
+                .or_else(|| {
+                    let bottom = shelves.last().map_or(0, |s| s.y + s.height + PADDING);
+                    (bottom + height <= page_height).then_some((page, shelves.len()))
+                })
+        });
+        let (page, shelf) = shelf.unwrap_or_else(|| {
+            pages.push(Vec::new());
+            (pages.len() - 1, 0)
+        });
+        let shelves = &mut pages[page];
+        if shelf == shelves.len() {
+            let y = shelves.last().map_or(0, |s| s.y + s.height + PADDING);
+            shelves.push(Shelf { y, height, width: 0 });
+        }
+        let shelf = &mut shelves[shelf];
+        placements[i] = Placement { page, x: shelf.width, y: shelf.y };
+        shelf.width += width + PADDING;
+    }
+    Ok(placements)
+}
+
+/// Bounds of the visible pixels of an image as x, y, width, height, a single pixel when it is empty.
+fn trim(image: &Image) -> [u32; 4] {
+    let (mut left, mut top, mut right, mut bottom) = (u32::MAX, u32::MAX, 0, 0);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            if image.pixels[((y * image.width + x) * 4 + 3) as usize] != 0 {
+                (left, top, right, bottom) = (left.min(x), top.min(y), right.max(x), bottom.max(y));
+            }
+        }
+    }
+    if left == u32::MAX {
+        return [0, 0, 1, 1];
+    }
+    [left, top, right - left + 1, bottom - top + 1]
+}
+
+/// Collision mask of a set of frames, one bit per pixel and rows padded to a byte.
+fn mask(frames: &[&Image]) -> Vec<u8> {
+    let (width, height) = (frames[0].width, frames[0].height);
+    let stride = width.div_ceil(8);
+    let mut mask = vec![0; (stride * height) as usize];
+    for frame in frames {
+        for y in 0..height {
+            for x in 0..width {
+                if frame.pixels[((y * width + x) * 4 + 3) as usize] != 0 {
+                    mask[(y * stride + x / 8) as usize] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+    }
+    mask
+}
+
+/// New frames for the sprites in `dir`, from `name_stripN.png` or from `name_0.png`, `name_1.png`...
+pub fn read_frames(dir: &Path, sprites: &[Sprite]) -> Result<HashMap<String, Vec<Image>>, String> {
+    let mut frames = HashMap::new();
+    let files = fs::read_dir(dir).map_err(|e| e.to_string())?.flatten().map(|f| f.file_name().to_string_lossy().to_string()).collect::<HashSet<String>>();
+    for sprite in sprites {
+        let strip = files.iter().find_map(|f| f.strip_prefix(&format!("{}_strip", sprite.name))?.strip_suffix(".png")?.parse::<u32>().ok().map(|n| (f, n)));
+        let images = match strip {
+            Some((file, count)) => {
+                let image = decode_png(&fs::read(dir.join(file)).map_err(|e| e.to_string())?).map_err(|e| format!("{file}: {e}"))?;
+                if count == 0 || image.width % count != 0 {
+                    return Err(format!("{file} is not {count} frames wide"));
+                }
+                let width = image.width / count;
+                (0..count).map(|i| {
+                    let mut frame = Image::new(width, image.height);
+                    frame.blit(&image, (i * width, 0), (width, image.height), (0, 0));
+                    frame
+                }).collect()
+            }
+            None => {
+                let mut images = Vec::new();
+                while let Some(file) = Some(format!("{}_{}.png", sprite.name, images.len())).filter(|f| files.contains(f)) {
+                    images.push(decode_png(&fs::read(dir.join(&file)).map_err(|e| e.to_string())?).map_err(|e| format!("{file}: {e}"))?);
+                }
+                images
+            }
+        };
+        if images.is_empty() {
+            continue;
+        }
+        if images.iter().any(|i| (i.width, i.height) != (images[0].width, images[0].height)) {
+            return Err(format!("The frames of {} don't have the same size", sprite.name));
+        }
+        frames.insert(sprite.name.clone(), images);
+    }
+    Ok(frames)
+}
+
+/// Images taking the place of TPAG entries, `images[i]` reuses `items[i]` when there is one.
+pub struct RegionSet<'a> {
+    pub items: Vec<usize>,
+    pub images: Vec<&'a Image>,
+    pub trim: bool, // Pack only the visible pixels, like sprite frames
+}
+
+/// A region to pack, an existing TPAG entry or a new image.
+enum Region<'a> {
+    Kept(usize, Image), // TPAG offset
+    New(usize, usize, [u32; 4], &'a Image), // Set, image, packed bounds
+}
+
+/// Replaces TPAG regions in a data.win loaded in `data`. The pages holding the old regions are repacked
+/// with everything else they hold and the new images, and more pages are added when needed.
+/// Returns the TPAG entries of every set, the old ones are reused and the missing ones appended.
+pub fn repack(data: &mut Vec<u8>, sets: &[RegionSet]) -> Result<Vec<Vec<usize>>, String> {
+    let form = Form::new(data).ok_or("Not a GameMaker data file")?;
+    let pages = parse_textures(&form);
+    let txtr = form.chunk(b"TXTR").ok_or("The data file has no TXTR chunk")?;
+    let entries = form.pointer_list(txtr.offset).ok_or("Invalid TXTR chunk")?;
+    let first = pages.first().ok_or("The data file has no embedded texture pages")?;
+    let field = first.field - first.entry;
+    let stride = if entries.len() > 1 { entries[1] - entries[0] } else { field + 4 };
+    // Shifting TXTR only breaks pointers into the chunks after it, the audio list is the only one known
+    let after = form.chunks().into_iter().filter(|c| c.offset > txtr.offset).collect::<Vec<_>>();
+    if let Some(chunk) = after.iter().find(|c| &c.name != b"AUDO") {
+        return Err(format!("The {} chunk after TXTR can't be moved", chunk.name()));
+    }
+
+    let replaced = sets.iter().flat_map(|s| s.items.iter().copied()).collect::<HashSet<usize>>();
+    let affected = replaced.iter().filter_map(|&f| Some(page_item(&form, f)?.page)).collect::<BTreeSet<i16>>();
+    let page_size = pages.iter().filter(|p| affected.contains(&(p.index as i16)))
+        .map(|p| (p.width, p.height))
+        .max()
+        .unwrap_or(DEFAULT_PAGE);
+    let mut cache = PageCache::new(&form, &pages);
+    let mut regions = Vec::new();
+    for item in parse_page_items(&form) {
+        if affected.contains(&item.page) && !replaced.contains(&item.offset) {
+            let [x, y, width, height] = item.source.map(u32::from);
+            let mut image = Image::new(width, height);
+            image.blit(cache.page(item.page)?, (x, y), (width, height), (0, 0));
+            regions.push(Region::Kept(item.offset, image));
+        }
+    }
+    for (set, regions_set) in sets.iter().enumerate() {
+        for (i, &image) in regions_set.images.iter().enumerate() {
+            let bounds = if regions_set.trim { trim(image) } else { [0, 0, image.width, image.height] };
+            regions.push(Region::New(set, i, bounds, image));
+        }
+    }
+    let sizes = regions.iter().map(|r| match r {
+        Region::Kept(_, image) => (image.width, image.height),
+        Region::New(_, _, bounds, _) => (bounds[2], bounds[3]),
+    }).collect::<Vec<(u32, u32)>>();
+    // Pages grow to the next power of two when a region is bigger than them
+    let page_size = sizes.iter().fold(page_size, |(w, h), &(rw, rh)| {
+        (if rw > w { rw.next_power_of_two() } else { w }, if rh > h { rh.next_power_of_two() } else { h })
+    });
+    let placements = pack(&sizes, page_size)?;
+    let page_count = placements.iter().map(|p| p.page + 1).max().unwrap_or(0);
+    // Repacked pages take the place of the affected ones, the rest are added after the last page
+    let affected = affected.into_iter().map(|p| p as usize).collect::<Vec<usize>>();
+    let page_index = |page: usize| if page < affected.len() { affected[page] } else { entries.len() + page - affected.len() };
+    let mut images = vec![Image::new(page_size.0, page_size.1); page_count];
+    let mut new_items: HashMap<(usize, usize), Vec<u8>> = HashMap::new();
+    let mut kept = Vec::new();
+    for (region, placement) in regions.iter().zip(&placements) {
+        let page = &mut images[placement.page];
+        match region {
+            Region::Kept(offset, image) => {
+                page.blit(image, (0, 0), (image.width, image.height), (placement.x, placement.y));
+                kept.push((*offset, placement));
+            }
+            Region::New(set, i, [x, y, width, height], image) => {
+                page.blit(image, (*x, *y), (*width, *height), (placement.x, placement.y));
+                let mut item = Vec::new();
+                for value in [placement.x, placement.y, *width, *height, *x, *y, *width, *height, image.width, image.height] {
+                    item.extend((value as u16).to_le_bytes());
+                }
+                item.extend((page_index(placement.page) as i16).to_le_bytes());
+                item.extend([0, 0]);
+                new_items.insert((*set, *i), item);
+            }
+        }
+    }
+    let blobs = images.iter().map(|i| encode_texture(i, first.format)).collect::<Result<Vec<Vec<u8>>, String>>()?;
+    let (txtr_offset, txtr_end) = (txtr.offset, txtr.end());
+    let entry_template = form.bytes(entries[0], stride).ok_or("Invalid TXTR entry")?.to_vec();
+    let old_entries = entries.iter().map(|&e| form.bytes(e, stride).map(|b| b.to_vec())).collect::<Option<Vec<Vec<u8>>>>().ok_or("Invalid TXTR entry")?;
+    let old_blobs = pages.iter().map(|p| Some((p.index, form.bytes(p.data, p.size)?.to_vec())))
+        .collect::<Option<HashMap<usize, Vec<u8>>>>().ok_or("A texture page is out of bounds")?;
+    let audio = after.first().map(|c| (c.offset, form.pointer_list(c.offset).unwrap_or_default()));
+
+    // Kept regions only move, the replaced entries are reused as far as they go
+    for (offset, placement) in kept {
+        put_u16(data, offset, placement.x as u16);
+        put_u16(data, offset + 2, placement.y as u16);
+        put_u16(data, offset + 20, page_index(placement.page) as u16);
+    }
+    let mut set_items = Vec::new();
+    for (set, regions_set) in sets.iter().enumerate() {
+        let mut items = Vec::new();
+        for i in 0..regions_set.images.len() {
+            let item = &new_items[&(set, i)];
+            items.push(match regions_set.items.get(i) {
+                Some(&offset) => {
+                    data[offset..offset + 22].copy_from_slice(&item[..22]);
+                    offset
+                }
+                None => usize::MAX,
+            });
+        }
+        set_items.push(items);
+    }
+
+    // Rebuild TXTR with the new pages, blobs aligned to 128 bytes like GameMaker writes them
+    let count = entries.len() + page_count.saturating_sub(affected.len());
+    let mut body = Vec::new();
+    body.extend((count as u32).to_le_bytes());
+    let entries_start = txtr_offset + 4 + count * 4;
+    for i in 0..count {
+        body.extend(((entries_start + i * stride) as u32).to_le_bytes());
+    }
+    let mut blob_fields = Vec::new();
+    for i in 0..count {
+        blob_fields.push(body.len() + field);
+        body.extend(old_entries.get(i).unwrap_or(&entry_template));
+    }
+    let empty_image = Image::new(1, 1);
+    let empty = encode_texture(&empty_image, first.format)?;
+    for (i, blob_field) in blob_fields.into_iter().enumerate() {
+        let (blob, image) = match affected.iter().position(|&p| p == i) {
+            // An affected page left empty still needs an image
+            Some(page) => (blobs.get(page).unwrap_or(&empty), Some(images.get(page).unwrap_or(&empty_image))),
+            None if i >= entries.len() => (&blobs[affected.len() + i - entries.len()], images.get(affected.len() + i - entries.len())),
+            None => match old_blobs.get(&i) {
+                Some(blob) => (blob, None),
+                // External pages have no blob
+                None if u32::from_le_bytes(body[blob_field..blob_field + 4].try_into().unwrap()) == 0 => continue,
+                None => return Err(format!("Texture page {i} is in an unknown format")),
+            },
+        };
+        // 2022.3 stores the blob size before the pointer, 2022.9 the page size too
+        let entry = blob_field - field;
+        if field >= 12 {
+            put_u32(&mut body, entry + 8, blob.len() as u32);
+        }
+        if let Some(image) = image.filter(|_| field >= 24) {
+            put_u32(&mut body, entry + 12, image.width);
+            put_u32(&mut body, entry + 16, image.height);
+        }
+        while (txtr_offset + body.len()) % 128 != 0 {
+            body.push(0);
+        }
+        let offset = (txtr_offset + body.len()) as u32;
+        put_u32(&mut body, blob_field, offset);
+        body.extend(blob);
+    }
+    while (body.len() as isize - (txtr_end - txtr_offset) as isize) % 16 != 0 {
+        body.push(0);
+    }
+    let delta = body.len() as isize - (txtr_end - txtr_offset) as isize;
+    data.splice(txtr_offset..txtr_end, body);
+    put_u32(data, txtr_offset - 4, (txtr_end as isize + delta - txtr_offset as isize) as u32);
+    let size = data.len() - 8;
+    put_u32(data, 4, size as u32);
+    if let Some((offset, pointers)) = audio {
+        let offset = (offset as isize + delta) as usize;
+        for (i, pointer) in pointers.into_iter().enumerate() {
+            put_u32(data, offset + 4 + i * 4, (pointer as isize + delta) as u32);
+        }
+    }
+    for (set, items) in set_items.iter_mut().enumerate() {
+        for (i, item) in items.iter_mut().enumerate() {
+            if *item == usize::MAX {
+                *item = append(data, &new_items[&(set, i)])?;
+            }
+        }
+    }
+    Ok(set_items)
+}
+
+/// Replaces the frames of sprites in a data.win loaded in `data`, see [`repack`].
+/// The sprites get new SPRT entries at the end of the file.
+pub fn import_sprites(data: &mut Vec<u8>, frames: &HashMap<String, Vec<Image>>) -> Result<usize, String> {
+    let form = Form::new(data).ok_or("Not a GameMaker data file")?;
+    let sprites = parse_sprites(&form).into_iter().filter(|s| frames.contains_key(&s.name)).collect::<Vec<Sprite>>();
+    let sets = sprites.iter().map(|s| RegionSet { items: s.frames.clone(), images: frames[&s.name].iter().collect(), trim: true }).collect::<Vec<RegionSet>>();
+    let sprite_items = repack(data, &sets)?;
+
+    // New SPRT entries, with the frame list and the masks sized for the new frames
+    for (sprite, items) in sprites.iter().zip(sprite_items) {
+        let images = &frames[&sprite.name];
+        let form = Form::new(data).ok_or("Not a GameMaker data file")?;
+        let sprite_list = form.chunk(b"SPRT").map(|c| c.offset).ok_or("The data file has no SPRT chunk")?;
+        let slot = form.pointer_list(sprite_list).unwrap_or_default().iter().position(|&s| s == sprite.ptr)
+            .ok_or(format!("Sprite {} is not in the SPRT list", sprite.name))?;
+        let (width, height) = (images[0].width, images[0].height);
+        let mut entry = form.bytes(sprite.ptr, sprite.frame_list - sprite.ptr).ok_or(format!("Sprite {} is out of bounds", sprite.name))?.to_vec();
+        put_u32(&mut entry, 4, width);
+        put_u32(&mut entry, 8, height);
+        let margins = match form.u32(sprite.ptr + 40) {
+            Some(0) => {
+                let bounds = images.iter().map(trim).collect::<Vec<[u32; 4]>>();
+                let left = bounds.iter().map(|b| b[0]).min().unwrap();
+                let top = bounds.iter().map(|b| b[1]).min().unwrap();
+                let right = bounds.iter().map(|b| b[0] + b[2] - 1).max().unwrap();
+                let bottom = bounds.iter().map(|b| b[1] + b[3] - 1).max().unwrap();
+                Some([left, right, bottom, top])
+            }
+            Some(1) => Some([0, width - 1, height - 1, 0]),
+            _ => None, // Manual
+        };
+        if let Some(margins) = margins {
+            for (i, margin) in margins.into_iter().enumerate() {
+                put_u32(&mut entry, 12 + i * 4, margin);
+            }
+        }
+        entry.extend((items.len() as u32).to_le_bytes());
+        for item in &items {
+            entry.extend((*item as u32).to_le_bytes());
+        }
+        let masks = form.u32(sprite.frame_list + 4 + sprite.frames.len() * 4).unwrap_or(0);
+        let separate = form.u32(sprite.ptr + 44).unwrap_or(0) != 0;
+        let frame_refs = images.iter().collect::<Vec<&Image>>();
+        let masks = match (masks, separate) {
+            (0, _) => Vec::new(),
+            (_, true) => frame_refs.iter().map(|&f| mask(&[f])).collect(),
+            (_, false) => vec![mask(&frame_refs)],
+        };
+        entry.extend((masks.len() as u32).to_le_bytes());
+        for mask in &masks {
+            entry.extend(mask);
+        }
+        while entry.len() % 4 != 0 {
+            entry.push(0);
+        }
+        let offset = append(data, &entry)?;
+        put_u32(data, sprite_list + 4 + slot * 4, offset as u32);
+    }
+    Ok(sprites.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::form::builder::{put, FormBuilder};
+    use crate::texture::encode_png;
+
+    fn image(width: u32, height: u32, color: impl Fn(u32, u32) -> [u8; 4]) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let i = ((y * width + x) * 4) as usize;
+                image.pixels[i..i + 4].copy_from_slice(&color(x, y));
+            }
+        }
+        image
+    }
+
+    fn assert_same(a: &Image, b: &Image) {
+        assert_eq!((a.width, a.height), (b.width, b.height));
+        assert!(a.pixels == b.pixels);
+    }
+
+    #[test]
+    fn pack_without_overlaps() {
+        // Sizes from a small linear congruential generator
+        let mut seed = 7u32;
+        let sizes = (0..60).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (1 + (seed >> 8) % 20, 1 + (seed >> 16) % 20)
+        }).collect::<Vec<(u32, u32)>>();
+        let page = (64, 64);
+        let placements = pack(&sizes, page).unwrap();
+        assert!(placements.iter().any(|p| p.page > 0), "everything fit on one page");
+        for (i, (a, &(aw, ah))) in placements.iter().zip(&sizes).enumerate() {
+            assert!(a.x + aw <= page.0 && a.y + ah <= page.1, "region {i} is outside the page");
+            for (b, &(bw, bh)) in placements.iter().zip(&sizes).skip(i + 1) {
+                let apart = a.page != b.page || a.x + aw <= b.x || b.x + bw <= a.x || a.y + ah <= b.y || b.y + bh <= a.y;
+                assert!(apart, "{a:?} {aw}x{ah} overlaps {b:?} {bw}x{bh}");
+            }
+        }
+        // Every page is used
+        let pages = placements.iter().map(|p| p.page).collect::<BTreeSet<usize>>();
+        assert_eq!(pages.len(), pages.last().unwrap() + 1);
+    }
+
+    #[test]
+    fn pack_spills_and_refuses() {
+        let placements = pack(&[(40, 40); 3], (64, 64)).unwrap();
+        assert_eq!(placements.iter().map(|p| p.page).collect::<Vec<usize>>(), [0, 1, 2]);
+        let placements = pack(&[(30, 30); 4], (64, 64)).unwrap();
+        assert!(placements.iter().all(|p| p.page == 0));
+        assert!(pack(&[(10, 10), (65, 1)], (64, 64)).is_err());
+    }
+
+    /// spr_a has two 4x4 frames shown at 1, 1 in 6x6, spr_b one 6x6 frame, all on one 16x8 page.
+    fn sprite_form(page: &Image) -> Vec<u8> {
+        let mut builder = FormBuilder::new(17, &["spr_a", "spr_b"]);
+        let names = [builder.string(0), builder.string(1)];
+        // Source, target, bounding size and page of each TPAG entry
+        let items: [[u16; 11]; 3] = [[0, 0, 4, 4, 1, 1, 4, 4, 6, 6, 0], [4, 0, 4, 4, 1, 1, 4, 4, 6, 6, 0], [8, 0, 6, 6, 0, 0, 6, 6, 6, 6, 0]];
+        let mut tpag = Vec::new();
+        builder.chunk(b"TPAG", |d| {
+            put(d, &[items.len() as u32]);
+            let table = d.len();
+            put(d, &[0; 3]);
+            for (i, item) in items.iter().enumerate() {
+                let ptr = d.len() as u32;
+                put_u32(d, table + i * 4, ptr);
+                tpag.push(ptr);
+                d.extend(item.iter().flat_map(|v| v.to_le_bytes()));
+                d.extend([0, 0]);
+            }
+        });
+        builder.chunk(b"SPRT", |d| {
+            put(d, &[2]);
+            let table = d.len();
+            put(d, &[0, 0]);
+            for (i, (name, frames)) in [(names[0], &tpag[..2]), (names[1], &tpag[2..])].into_iter().enumerate() {
+                let ptr = d.len() as u32;
+                put_u32(d, table + i * 4, ptr);
+                // Size, margins, transparent, smooth, preload, automatic bounding box, shared mask, origin
+                put(d, &[name, 6, 6, 0, 5, 5, 0, 0, 0, 0, 0, 0, 3, 2]);
+                put(d, &[frames.len() as u32]);
+                put(d, frames);
+                put(d, &[0]); // No masks
+            }
+        });
+        let png = encode_png(page).unwrap();
+        builder.chunk(b"TXTR", |d| {
+            let entry = d.len() + 8;
+            put(d, &[1, entry as u32, 1, 0, 0]);
+            while d.len() % 128 != 0 {
+                d.push(0);
+            }
+            let blob = d.len() as u32;
+            put_u32(d, entry + 8, blob);
+            d.extend(png);
+        });
+        builder.build()
+    }
+
+    #[test]
+    fn import_round_trip() {
+        let page = image(16, 8, |x, y| match (x, y) {
+            (0..4, 0..4) => [255, 0, 0, 255],
+            (4..8, 0..4) => [0, 255, 0, 255],
+            (8..14, 0..6) => [x as u8 * 10, y as u8 * 10, 255, 255],
+            _ => [0; 4],
+        });
+        let mut data = sprite_form(&page);
+        let form = Form::new(&data).unwrap();
+        let pages = parse_textures(&form);
+        let old_b = PageCache::new(&form, &pages).frames(&parse_sprites(&form)[1]).unwrap();
+
+        // Three 10x10 frames, each one row shorter than the previous
+        let frames = (0..3).map(|i| image(10, 10, |x, y| match (2..8).contains(&x) && (1..9 - i).contains(&y) {
+            true => [0, 200, 50 * i as u8, 255],
+            false => [0; 4],
+        })).collect::<Vec<Image>>();
+        assert_eq!(import_sprites(&mut data, &HashMap::from([("spr_a".to_string(), frames.clone())])), Ok(1));
+
+        let form = Form::new(&data).unwrap();
+        let sprites = parse_sprites(&form);
+        let pages = parse_textures(&form);
+        let mut cache = PageCache::new(&form, &pages);
+        let a = &sprites[0];
+        assert_eq!((a.name.as_str(), a.width, a.height, a.frames.len()), ("spr_a", 10, 10, 3));
+        assert_eq!(a.margins, [2, 7, 8, 1]);
+        assert_eq!(a.origin, [3, 2]);
+        for (new, old) in cache.frames(a).unwrap().iter().zip(&frames) {
+            assert_same(new, old);
+        }
+        // spr_b was on the repacked page and moved with it
+        let b = cache.frames(&sprites[1]).unwrap();
+        assert_eq!(b.len(), 1);
+        assert_same(&b[0], &old_b[0]);
+    }
+}
@@ -0,0 +1,266 @@
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::address::Address;
+use crate::watch::{Format, Hotkey, HotkeyAction, ValueType, WatchEntry};
+
+/// Result of converting a Cheat Engine table, with everything that had no equivalent.
+#[derive(Default)]
+pub struct CheatTable {
+    pub entries: Vec<WatchEntry>,
+    pub symbols: Vec<(String, Address)>,
+    pub unsupported: Vec<String>,
+}
+
+#[derive(Default)]
+struct Node {
+    name: String,
+    text: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Node> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    fn text_of(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|c| c.text.trim())
+    }
+}
+
+fn parse_xml(data: &[u8]) -> Result<Node, String> {
+    let mut stack = vec![Node::default()];
+    for event in EventReader::new(data) {
+        match event.map_err(|e| e.to_string())? {
+            XmlEvent::StartElement { name, .. } => stack.push(Node {
+                name: name.local_name,
+                ..Default::default()
+            }),
+            XmlEvent::EndElement { .. } => {
+                let node = stack.pop().unwrap();
+                stack.last_mut().ok_or("Unbalanced XML")?.children.push(node);
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => stack.last_mut().unwrap().text += &text,
+            _ => {}
+        }
+    }
+    let root = stack.pop().ok_or("Empty XML")?;
+    root.children.into_iter().find(|c| c.name == "CheatTable").ok_or("Not a Cheat Engine table".to_string())
+}
+
+/// Converts the entries of a `.CT` file, groups are flattened into `Group/Entry` names.
+pub fn import(data: &[u8]) -> Result<CheatTable, String> {
+    let root = parse_xml(data)?;
+    let mut table = CheatTable::default();
+    if let Some(entries) = root.child("CheatEntries") {
+        import_entries(entries, "", &mut table);
+    }
+    if let Some(symbols) = root.child("UserdefinedSymbols") {
+        for symbol in symbols.children("SymbolEntry") {
+            let (Some(name), Some(address)) = (symbol.text_of("Name"), symbol.text_of("Address")) else {
+                continue;
+            };
+            match Address::parse(address) {
+                Ok(address) => table.symbols.push((name.to_string(), address)),
+                Err(e) => table.unsupported.push(format!("Symbol {name}: {e}")),
+            }
+        }
+    }
+    if root.child("LuaScript").is_some_and(|l| !l.text.trim().is_empty()) {
+        table.unsupported.push("The table Lua script was not imported".to_string());
+    }
+    for name in ["Structures", "Forms", "CheatCodes", "Comments"] {
+        if root.child(name).is_some_and(|c| !c.children.is_empty()) {
+            table.unsupported.push(format!("The table {name} were not imported"));
+        }
+    }
+    Ok(table)
+}
+
+fn import_entries(entries: &Node, group: &str, table: &mut CheatTable) {
+    for entry in entries.children("CheatEntry") {
+        let description = entry.text_of("Description").unwrap_or_default().trim_matches('"');
+        let name = if group.is_empty() { description.to_string() } else { format!("{group}/{description}") };
+        if let Some(children) = entry.child("CheatEntries") {
+            import_entries(children, &name, table);
+        }
+        let variable_type = entry.text_of("VariableType").unwrap_or_default();
+        if variable_type == "Auto Assembler Script" || entry.child("AssemblerScript").is_some() {
+            table.unsupported.push(format!("{name}: Auto Assembler scripts can't be imported"));
+            continue;
+        }
+        if entry.text_of("GroupHeader") == Some("1") && entry.child("Address").is_none() {
+            continue;
+        }
+        if entry.child("LuaScript").is_some() {
+            table.unsupported.push(format!("{name}: the Lua script of this entry was ignored"));
+        }
+        let value_type = match variable_type {
+            "Byte" => ValueType::I8,
+            "2 Bytes" => ValueType::I16,
+            "4 Bytes" => ValueType::I32,
+            "8 Bytes" => ValueType::I64,
+            "Float" => ValueType::F32,
+            "Double" => ValueType::F64,
+            "String" if entry.text_of("Unicode") != Some("1") => {
+                ValueType::String(entry.text_of("Length").and_then(|l| l.parse().ok()).unwrap_or(63) + 1)
+            }
+            other => {
+                table.unsupported.push(format!("{name}: the {other} type is not supported"));
+                continue;
+            }
+        };
+        let Some(base) = entry.text_of("Address") else {
+            table.unsupported.push(format!("{name}: has no address"));
+            continue;
+        };
+        // Offsets are listed from the last one applied to the first one
+        let offsets = entry.child("Offsets").map(|o| o.children("Offset").map(|o| o.text.trim().to_string()).collect::<Vec<String>>()).unwrap_or_default();
+        let mut address = if offsets.is_empty() { base.to_string() } else { format!("[{base}]") };
+        for (i, offset) in offsets.iter().rev().enumerate() {
+            address = if i + 1 == offsets.len() { format!("{address}+{offset}") } else { format!("[{address}+{offset}]") };
+        }
+        if let Err(e) = Address::parse(&address) {
+            table.unsupported.push(format!("{name}: {e}"));
+            continue;
+        }
+        let mut hotkeys = Vec::new();
+        if let Some(keys) = entry.child("Hotkeys") {
+            for hotkey in keys.children("Hotkey") {
+                let action = match hotkey.text_of("Action").unwrap_or_default() {
+                    "Toggle Activation" => HotkeyAction::ToggleFreeze,
+                    "Activate" => HotkeyAction::Freeze,
+                    "Deactivate" => HotkeyAction::Unfreeze,
+                    "Set Value" => HotkeyAction::Set(hotkey.text_of("Value").unwrap_or_default().to_string()),
+                    other => {
+                        table.unsupported.push(format!("{name}: the \"{other}\" hotkey action is not supported"));
+                        continue;
+                    }
+                };
+                let keys = hotkey.child("Keys").map(|k| k.children("Key").filter_map(|k| k.text.trim().parse().ok()).collect::<Vec<u16>>()).unwrap_or_default();
+                if !keys.is_empty() {
+                    hotkeys.push(Hotkey {
+                        keys,
+                        action,
+                        down: false,
+                    });
+                }
+            }
+        }
+        table.entries.push(WatchEntry {
+            name,
+            address,
+            value_type,
+            format: if entry.text_of("ShowAsHex") == Some("1") { Format::Hex } else { Format::Decimal },
+            frozen: None,
+            hotkeys,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<CheatTable CheatEngineTableVersion="45">
+  <CheatEntries>
+    <CheatEntry>
+      <ID>0</ID>
+      <Description>"Player"</Description>
+      <GroupHeader>1</GroupHeader>
+      <CheatEntries>
+        <CheatEntry>
+          <ID>1</ID>
+          <Description>"HP"</Description>
+          <ShowAsHex>1</ShowAsHex>
+          <VariableType>Double</VariableType>
+          <Address>"Game.exe"+00123450</Address>
+          <Offsets>
+            <Offset>C</Offset>
+            <Offset>10</Offset>
+            <Offset>4</Offset>
+          </Offsets>
+          <Hotkeys>
+            <Hotkey>
+              <Action>Set Value</Action>
+              <Keys>
+                <Key>17</Key>
+                <Key>72</Key>
+              </Keys>
+              <Value>100</Value>
+            </Hotkey>
+          </Hotkeys>
+        </CheatEntry>
+        <CheatEntry>
+          <ID>2</ID>
+          <Description>"Stats"</Description>
+          <GroupHeader>1</GroupHeader>
+          <CheatEntries>
+            <CheatEntry>
+              <ID>3</ID>
+              <Description>"Name"</Description>
+              <VariableType>String</VariableType>
+              <Length>15</Length>
+              <Address>player_base+#8</Address>
+            </CheatEntry>
+          </CheatEntries>
+        </CheatEntry>
+      </CheatEntries>
+    </CheatEntry>
+    <CheatEntry>
+      <ID>4</ID>
+      <Description>"Infinite money"</Description>
+      <VariableType>Auto Assembler Script</VariableType>
+      <AssemblerScript>[ENABLE]</AssemblerScript>
+    </CheatEntry>
+  </CheatEntries>
+  <UserdefinedSymbols>
+    <SymbolEntry>
+      <Name>player_base</Name>
+      <Address>[Game.exe+2000]</Address>
+    </SymbolEntry>
+    <SymbolEntry>
+      <Name>broken</Name>
+      <Address>[10</Address>
+    </SymbolEntry>
+  </UserdefinedSymbols>
+</CheatTable>
+"#;
+
+    #[test]
+    fn import_table() {
+        let table = import(TABLE.as_bytes()).unwrap();
+        let names = table.entries.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, ["Player/HP", "Player/Stats/Name"]);
+
+        // The last offset is applied first
+        let hp = &table.entries[0];
+        assert_eq!(hp.address, "[[[\"Game.exe\"+00123450]+4]+10]+C");
+        assert!(hp.value_type == ValueType::F64 && hp.format == Format::Hex);
+        assert_eq!(hp.hotkeys.len(), 1);
+        assert_eq!(hp.hotkeys[0].keys, [17, 72]);
+        assert!(hp.hotkeys[0].action == HotkeyAction::Set("100".to_string()));
+
+        let name = &table.entries[1];
+        assert_eq!(name.address, "player_base+#8");
+        assert!(name.value_type == ValueType::String(16) && name.format == Format::Decimal);
+
+        assert_eq!(table.symbols.len(), 1);
+        assert_eq!(table.symbols[0].0, "player_base");
+        assert_eq!(table.symbols[0].1.to_string(), "[Game.exe+2000]");
+        assert_eq!(table.unsupported.len(), 2, "{:?}", table.unsupported);
+        assert!(table.unsupported[0].starts_with("Infinite money:"));
+        assert!(table.unsupported[1].starts_with("Symbol broken:"));
+    }
+
+    #[test]
+    fn not_a_table() {
+        assert!(import(b"<Other/>").is_err());
+        assert!(import(b"<CheatTable>").is_err());
+    }
+}
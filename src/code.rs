@@ -0,0 +1,394 @@
+use crate::form::Form;
+
+// GameMaker VM opcodes (bytecode 15 and newer)
+pub const OP_CONV: u8 = 0x07;
+pub const OP_MUL: u8 = 0x08;
+pub const OP_DIV: u8 = 0x09;
+pub const OP_REM: u8 = 0x0a;
+pub const OP_MOD: u8 = 0x0b;
+pub const OP_ADD: u8 = 0x0c;
+pub const OP_SUB: u8 = 0x0d;
+pub const OP_AND: u8 = 0x0e;
+pub const OP_OR: u8 = 0x0f;
+pub const OP_XOR: u8 = 0x10;
+pub const OP_NEG: u8 = 0x11;
+pub const OP_NOT: u8 = 0x12;
+pub const OP_SHL: u8 = 0x13;
+pub const OP_SHR: u8 = 0x14;
+pub const OP_CMP: u8 = 0x15;
+pub const OP_POP: u8 = 0x45;
+pub const OP_PUSHI: u8 = 0x84;
+pub const OP_DUP: u8 = 0x86;
+pub const OP_CALLV: u8 = 0x99;
+pub const OP_RET: u8 = 0x9c;
+pub const OP_EXIT: u8 = 0x9d;
+pub const OP_POPZ: u8 = 0x9e;
+pub const OP_B: u8 = 0xb6;
+pub const OP_BT: u8 = 0xb7;
+pub const OP_BF: u8 = 0xb8;
+pub const OP_PUSHENV: u8 = 0xba;
+pub const OP_POPENV: u8 = 0xbb;
+pub const OP_PUSH: u8 = 0xc0;
+pub const OP_PUSHLOC: u8 = 0xc1;
+pub const OP_PUSHGLB: u8 = 0xc2;
+pub const OP_PUSHBLTN: u8 = 0xc3;
+pub const OP_CALL: u8 = 0xd9;
+pub const OP_BREAK: u8 = 0xff;
+
+// Operand data types
+pub const TYPE_DOUBLE: u8 = 0x0;
+pub const TYPE_FLOAT: u8 = 0x1;
+pub const TYPE_INT32: u8 = 0x2;
+pub const TYPE_INT64: u8 = 0x3;
+pub const TYPE_BOOL: u8 = 0x4;
+pub const TYPE_VARIABLE: u8 = 0x5;
+pub const TYPE_STRING: u8 = 0x6;
+pub const TYPE_INT16: u8 = 0xf;
+
+// `popenv` offset that leaves a `with` block early instead of looping back (bytecode 15 and newer)
+pub const POPENV_EXIT: u32 = 0xf00000;
+
+// Comparison kinds of `cmp`, stored in bits 8 to 15 of the instruction word
+pub const CMP_KINDS: [&str; 7] = ["", "LT", "LE", "EQ", "NE", "GE", "GT"];
+
+#[derive(Default, Clone)]
+pub struct CodeEntry {
+    pub name: String,
+    pub ptr: usize, // Offset of the entry in the CODE chunk
+    pub length: usize,
+    pub locals: u16,
+    pub arguments: u16,
+    pub bytecode: usize, // Offset of the bytecode blob, shared by the functions declared in the same script
+    pub offset: usize, // Start of this entry inside the blob
+}
+
+#[derive(Clone, Copy)]
+pub struct Instruction<'a> {
+    pub address: usize,
+    pub opcode: u8,
+    pub type1: u8,
+    pub type2: u8,
+    pub value: u16, // Low 16 bits of the instruction word (comparison kind, Int16 immediate, argument count...)
+    pub operand: &'a [u8],
+    pub version: u8,
+}
+
+#[derive(Default, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub ptr: usize,
+    pub instance_type: i32,
+    pub id: i32,
+    pub occurrences: u32,
+    pub first_address: u32,
+}
+
+#[derive(Default, Clone)]
+pub struct Function {
+    pub name: String,
+    pub ptr: usize,
+    pub occurrences: u32,
+    pub first_address: u32,
+}
+
+impl Instruction<'_> {
+    pub fn size(&self) -> usize {
+        4 + self.operand.len()
+    }
+
+    pub fn operand_u32(&self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.operand.get(0..4)?.try_into().unwrap()))
+    }
+
+    pub fn is_push(&self) -> bool {
+        matches!(self.opcode, OP_PUSH | OP_PUSHLOC | OP_PUSHGLB | OP_PUSHBLTN | OP_PUSHI)
+    }
+
+    /// Pushes and pops of a variable, their operand is a variable reference.
+    pub fn has_variable(&self) -> bool {
+        self.is_push() && self.type1 == TYPE_VARIABLE || self.opcode == OP_POP && self.type1 != TYPE_INT16
+    }
+
+    pub fn is_popenv_exit(&self) -> bool {
+        self.opcode == OP_POPENV && self.version >= 15 && self.word() & 0xffffff == POPENV_EXIT
+    }
+
+    /// Branches store a signed offset in instructions (4 bytes) in the low bits of the word, 23 bits
+    /// since bytecode 15 and 24 before. A `popenv` exit goes on to the next instruction.
+    pub fn branch_target(&self) -> usize {
+        if self.is_popenv_exit() {
+            return self.address + 4;
+        }
+        let word = self.word();
+        let offset = if self.version >= 15 { ((word << 9) as i32) >> 9 } else { ((word << 8) as i32) >> 8 };
+        (self.address as isize + offset as isize * 4) as usize
+    }
+
+    /// Instruction word without the opcode.
+    fn word(&self) -> u32 {
+        (self.type2 as u32) << 20 | (self.type1 as u32) << 16 | self.value as u32
+    }
+}
+
+pub fn opcode_name(opcode: u8) -> &'static str {
+    match opcode {
+        OP_CONV => "conv",
+        OP_MUL => "mul",
+        OP_DIV => "div",
+        OP_REM => "rem",
+        OP_MOD => "mod",
+        OP_ADD => "add",
+        OP_SUB => "sub",
+        OP_AND => "and",
+        OP_OR => "or",
+        OP_XOR => "xor",
+        OP_NEG => "neg",
+        OP_NOT => "not",
+        OP_SHL => "shl",
+        OP_SHR => "shr",
+        OP_CMP => "cmp",
+        OP_POP => "pop",
+        OP_PUSHI => "pushi",
+        OP_DUP => "dup",
+        OP_CALLV => "callv",
+        OP_RET => "ret",
+        OP_EXIT => "exit",
+        OP_POPZ => "popz",
+        OP_B => "b",
+        OP_BT => "bt",
+        OP_BF => "bf",
+        OP_PUSHENV => "pushenv",
+        OP_POPENV => "popenv",
+        OP_PUSH => "push",
+        OP_PUSHLOC => "pushloc",
+        OP_PUSHGLB => "pushglb",
+        OP_PUSHBLTN => "pushbltn",
+        OP_CALL => "call",
+        OP_BREAK => "break",
+        _ => "???",
+    }
+}
+
+pub fn type_suffix(data_type: u8) -> char {
+    match data_type {
+        TYPE_DOUBLE => 'd',
+        TYPE_FLOAT => 'f',
+        TYPE_INT32 => 'i',
+        TYPE_INT64 => 'l',
+        TYPE_BOOL => 'b',
+        TYPE_VARIABLE => 'v',
+        TYPE_STRING => 's',
+        TYPE_INT16 => 'e',
+        _ => 'u',
+    }
+}
+
+/// Maps the opcodes of bytecode 14 and older to the current ones, with the comparison kind of the
+/// `set` opcodes they had instead of `cmp`.
+fn translate_opcode(opcode: u8) -> (u8, u16) {
+    match opcode {
+        0x03..=0x10 => (opcode + 4, 0),
+        0x11..=0x16 => (OP_CMP, (opcode as u16 - 0x10) << 8),
+        0x41 => (OP_POP, 0),
+        0x82 => (OP_DUP, 0),
+        0x9d => (OP_RET, 0),
+        0x9e => (OP_EXIT, 0),
+        0x9f => (OP_POPZ, 0),
+        0xb7 => (OP_B, 0),
+        0xb8 => (OP_BT, 0),
+        0xb9 => (OP_BF, 0),
+        0xbb => (OP_PUSHENV, 0),
+        0xbc => (OP_POPENV, 0),
+        0xda => (OP_CALL, 0),
+        _ => (opcode, 0),
+    }
+}
+
+/// Size in bytes of the operand following an instruction word.
+pub fn operand_size(opcode: u8, type1: u8) -> usize {
+    match opcode {
+        OP_PUSH | OP_PUSHLOC | OP_PUSHGLB | OP_PUSHBLTN | OP_PUSHI => match type1 {
+            TYPE_DOUBLE | TYPE_INT64 => 8,
+            TYPE_INT16 => 0,
+            _ => 4,
+        },
+        // `pop.e.v` with an Int16 type is a stack swap without a variable
+        OP_POP if type1 == TYPE_INT16 => 0,
+        OP_POP | OP_CALL => 4,
+        // Extended opcodes carrying an Int32 argument (pushref and friends)
+        OP_BREAK if type1 == TYPE_INT32 => 4,
+        _ => 0,
+    }
+}
+
+pub fn decode(data: &[u8], address: usize, version: u8) -> Option<Instruction<'_>> {
+    let word = u32::from_le_bytes(data.get(address..address + 4)?.try_into().unwrap());
+    let (opcode, kind) = if version < 15 { translate_opcode((word >> 24) as u8) } else { ((word >> 24) as u8, 0) };
+    let type1 = ((word >> 16) & 0xf) as u8;
+    let size = operand_size(opcode, type1);
+    Some(Instruction {
+        address,
+        opcode,
+        type1,
+        type2: ((word >> 20) & 0xf) as u8,
+        value: word as u16 | kind,
+        operand: data.get(address + 4..address + 4 + size)?,
+        version,
+    })
+}
+
+/// Decodes `length` bytes of bytecode starting at the `start` offset of `data`.
+pub fn instructions(data: &[u8], start: usize, length: usize, version: u8) -> Vec<Instruction<'_>> {
+    let mut result = Vec::new();
+    let mut address = start;
+    while address < start + length {
+        let Some(instruction) = decode(data, address, version) else {
+            break;
+        };
+        address += instruction.size();
+        result.push(instruction);
+    }
+    result
+}
+
+pub fn bytecode_version(form: &Form) -> u8 {
+    form.chunk(b"GEN8").and_then(|c| form.u8(c.offset + 1)).unwrap_or(17)
+}
+
+pub fn parse_code(form: &Form) -> Vec<CodeEntry> {
+    let Some(chunk) = form.chunk(b"CODE") else {
+        return Vec::new();
+    };
+    let old = bytecode_version(form) < 15;
+    let mut entries = Vec::new();
+    for ptr in form.pointer_list(chunk.offset).unwrap_or_default() {
+        // Before bytecode 15 the bytecode directly follows the name and length
+        let entry = if old { (|| Some(CodeEntry {
+            name: form.string(form.u32(ptr)? as usize)?,
+            ptr,
+            length: form.u32(ptr + 4)? as usize,
+            bytecode: ptr + 8,
+            ..Default::default()
+        }))() } else { (|| Some(CodeEntry {
+            name: form.string(form.u32(ptr)? as usize)?,
+            ptr,
+            length: form.u32(ptr + 4)? as usize,
+            locals: form.u16(ptr + 8)?,
+            arguments: form.u16(ptr + 10)?,
+            // The bytecode address is relative to the field itself
+            bytecode: (ptr as isize + 12 + form.i32(ptr + 12)? as isize) as usize,
+            offset: form.u32(ptr + 16)? as usize,
+        }))() };
+        match entry {
+            Some(entry) => entries.push(entry),
+            None => println!("Invalid code entry at 0x{ptr:x}"),
+        }
+    }
+    entries
+}
+
+pub fn parse_variables(form: &Form) -> Vec<Variable> {
+    let Some(chunk) = form.chunk(b"VARI") else {
+        return Vec::new();
+    };
+    let mut variables = Vec::new();
+    if bytecode_version(form) < 15 {
+        // Name, occurrences and first address, the id is the index
+        let mut ptr = chunk.offset;
+        while chunk.contains(ptr, 12) {
+            variables.push(Variable {
+                name: form.string(form.u32(ptr).unwrap() as usize).unwrap_or_default(),
+                ptr,
+                instance_type: 0,
+                id: variables.len() as i32,
+                occurrences: form.u32(ptr + 4).unwrap(),
+                first_address: form.u32(ptr + 8).unwrap(),
+            });
+            ptr += 12;
+        }
+        return variables;
+    }
+    // Instance variable count, max instance variable count and max local variable count
+    let mut ptr = chunk.offset + 12;
+    while chunk.contains(ptr, 20) {
+        variables.push(Variable {
+            name: form.string(form.u32(ptr).unwrap() as usize).unwrap_or_default(),
+            ptr,
+            instance_type: form.i32(ptr + 4).unwrap(),
+            id: form.i32(ptr + 8).unwrap(),
+            occurrences: form.u32(ptr + 12).unwrap(),
+            first_address: form.u32(ptr + 16).unwrap(),
+        });
+        ptr += 20;
+    }
+    variables
+}
+
+pub fn parse_functions(form: &Form) -> Vec<Function> {
+    let Some(chunk) = form.chunk(b"FUNC") else {
+        return Vec::new();
+    };
+    // Before bytecode 15 the entries fill the chunk without a count or code locals
+    let (start, count) = match bytecode_version(form) {
+        0..=14 => (chunk.offset, chunk.data.len() / 12),
+        _ => (chunk.offset + 4, form.u32(chunk.offset).unwrap_or(0) as usize),
+    };
+    let mut functions = Vec::new();
+    for i in 0..count {
+        let ptr = start + i * 12;
+        if !chunk.contains(ptr, 12) {
+            break;
+        }
+        functions.push(Function {
+            name: form.string(form.u32(ptr).unwrap() as usize).unwrap_or_default(),
+            ptr,
+            occurrences: form.u32(ptr + 4).unwrap(),
+            first_address: form.u32(ptr + 8).unwrap(),
+        });
+    }
+    functions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::form::builder::{words, FormBuilder};
+
+    /// Target of a branch decoded 0x100 bytes into a code entry, relative to the branch.
+    fn decode_word(word: u32, version: u8) -> isize {
+        let mut builder = FormBuilder::new(version, &["gml_Script_branch"]);
+        let mut program = vec![0; 0x40];
+        program.push(word);
+        let start = builder.code(&[(0, &words(&program))])[0];
+        let data = builder.build();
+        let form = Form::new(&data).unwrap();
+        let address = start + 0x100;
+        decode(form.data, address, bytecode_version(&form)).unwrap().branch_target() as isize - address as isize
+    }
+
+    #[test]
+    fn backward_branches() {
+        // b and bt 3 instructions back, 23 bit offsets
+        assert_eq!(decode_word(0xb67ffffd, 17), -12);
+        assert_eq!(decode_word(0xb77ffffd, 15), -12);
+        // Bytecode 14 has 24 bit offsets and other opcodes
+        assert_eq!(decode_word(0xb7fffffd, 14), -12);
+        assert_eq!(decode_word(0xb8fffffd, 14), -12);
+    }
+
+    #[test]
+    fn forward_branches() {
+        assert_eq!(decode_word(0xb6000003, 17), 12);
+        assert_eq!(decode_word(0xb83fffff, 16), 0x3fffff * 4);
+        assert_eq!(decode_word(0xb7000003, 14), 12);
+    }
+
+    #[test]
+    fn popenv_exit() {
+        let data = 0xbbf00000u32.to_le_bytes();
+        let exit = decode(&data, 0, 17).unwrap();
+        assert!(exit.is_popenv_exit());
+        assert_eq!(exit.branch_target(), 4);
+        assert_eq!(decode_word(0xbb7ffffc, 17), -16);
+    }
+}
@@ -0,0 +1,715 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::code::*;
+use crate::disasm::{instance_name, Disassembler};
+use crate::form::Form;
+use crate::strings::escape;
+
+// Operator precedences, higher binds tighter
+const PREC_TERNARY: u8 = 1;
+const PREC_OR: u8 = 2;
+const PREC_AND: u8 = 3;
+const PREC_COMPARE: u8 = 4;
+const PREC_BITWISE: u8 = 5;
+const PREC_SHIFT: u8 = 6;
+const PREC_ADD: u8 = 7;
+const PREC_MUL: u8 = 8;
+const PREC_UNARY: u8 = 9;
+const PREC_ATOM: u8 = 10;
+
+#[derive(Clone)]
+struct Expr {
+    text: String,
+    prec: u8,
+    binary: Option<(String, &'static str, String)>, // For compound assignments
+}
+
+impl Expr {
+    fn atom(text: impl Into<String>) -> Self {
+        Self { text: text.into(), prec: PREC_ATOM, binary: None }
+    }
+
+    fn wrap(&self, prec: u8) -> String {
+        if self.prec < prec { format!("({})", self.text) } else { self.text.clone() }
+    }
+
+    fn binary(left: Expr, op: &'static str, right: Expr, prec: u8) -> Self {
+        // Left associative, the right side needs parentheses at the same level
+        let (left, right) = (left.wrap(prec), right.wrap(prec + 1));
+        Self { text: format!("{left} {op} {right}"), prec, binary: Some((left, op, right)) }
+    }
+
+    fn unary(op: &str, value: Expr) -> Self {
+        Self { text: format!("{op}{}", value.wrap(PREC_UNARY)), prec: PREC_UNARY, binary: None }
+    }
+
+    fn not(self) -> Self {
+        match self.text.strip_prefix('!') {
+            Some(inner) if self.prec == PREC_UNARY => Self { text: inner.to_string(), prec: PREC_ATOM, binary: None },
+            _ => Self::unary("!", self),
+        }
+    }
+}
+
+/// Rebuilds structured GML from the bytecode of a code entry. Patterns it can't structure are kept as
+/// `// goto` comments instead of failing.
+pub struct Decompiler<'a> {
+    disassembler: &'a Disassembler,
+    instructions: Vec<Instruction<'a>>,
+    index: HashMap<usize, usize>, // Address to instruction index
+    stack: Vec<Expr>,
+    loops: Vec<(usize, usize)>, // Continue and break addresses of the enclosing loops
+    do_loops: HashMap<usize, usize>, // Index of the first instruction of a `do` loop to its closing branch
+    locals: HashSet<String>,
+    children: HashMap<usize, String>, // Functions declared inside the entry, by address
+}
+
+/// Address range of the bytecode of an entry. Functions declared in a script are skipped over by a
+/// branch right before them, which gives their end.
+pub fn entry_range(form: &Form, disassembler: &Disassembler, entry: &CodeEntry) -> (usize, usize) {
+    let start = entry.bytecode + entry.offset;
+    if entry.offset != 0 {
+        if let Some(skip) = decode(form.data, start - 4, disassembler.version).filter(|i| i.opcode == OP_B && i.branch_target() > start) {
+            return (start, skip.branch_target());
+        }
+    }
+    (start, entry.bytecode + entry.length)
+}
+
+fn function_display(name: &str) -> &str {
+    name.strip_prefix("gml_Script_").unwrap_or(name)
+}
+
+impl<'a> Decompiler<'a> {
+    pub fn new(disassembler: &'a Disassembler, form: &Form<'a>, entry: &CodeEntry) -> Self {
+        let (start, end) = entry_range(form, disassembler, entry);
+        let instructions = instructions(form.data, start, end - start, disassembler.version);
+        let index = instructions.iter().enumerate().map(|(i, instruction)| (instruction.address, i)).collect::<HashMap<usize, usize>>();
+        let do_loops = instructions.iter().enumerate()
+            .filter(|(_, i)| matches!(i.opcode, OP_BT | OP_BF) && i.branch_target() <= i.address)
+            .filter_map(|(j, i)| Some((*index.get(&i.branch_target())?, j)))
+            .collect();
+        let children = disassembler.code.iter()
+            .filter(|e| e.bytecode == entry.bytecode && e.offset != 0 && e.bytecode + e.offset > start && e.bytecode + e.offset < end)
+            .map(|e| (e.bytecode + e.offset, e.name.clone()))
+            .collect();
+        Self {
+            disassembler,
+            instructions,
+            index,
+            stack: Vec::new(),
+            loops: Vec::new(),
+            do_loops,
+            locals: HashSet::new(),
+            children,
+        }
+    }
+
+    pub fn decompile(mut self) -> String {
+        let lines = self.block(0, self.instructions.len());
+        let mut text = String::new();
+        for line in lines {
+            text += &line;
+            text += "\n";
+        }
+        text
+    }
+
+    fn pop(&mut self) -> Expr {
+        self.stack.pop().unwrap_or(Expr::atom("/* ? */"))
+    }
+
+    /// Index of the instruction at `address`, the end of the code when it is past the last one.
+    fn at(&self, address: usize) -> usize {
+        self.index.get(&address).copied().unwrap_or(if self.instructions.first().is_some_and(|i| address < i.address) { 0 } else { self.instructions.len() })
+    }
+
+    fn address(&self, index: usize) -> usize {
+        self.instructions.get(index).map_or(usize::MAX, |i| i.address)
+    }
+
+    fn indent(lines: Vec<String>) -> impl Iterator<Item = String> {
+        lines.into_iter().map(|l| format!("    {l}"))
+    }
+
+    fn block(&mut self, from: usize, to: usize) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut i = from;
+        while i < to {
+            if let Some(j) = self.do_loops.get(&i).copied().filter(|&j| j < to) {
+                self.do_loops.remove(&i);
+                self.loops.push((self.address(j), self.address(j + 1)));
+                let body = self.block(i, j);
+                self.loops.pop();
+                let condition = self.pop();
+                let condition = if self.instructions[j].opcode == OP_BT { condition.not() } else { condition };
+                out.push("do".to_string());
+                out.push("{".to_string());
+                out.extend(Self::indent(body));
+                out.push(format!("}} until ({});", condition.text));
+                i = j + 1;
+                continue;
+            }
+            let instruction = self.instructions[i];
+            i = match instruction.opcode {
+                OP_BT | OP_BF if instruction.branch_target() > instruction.address => self.conditional(i, to, &mut out),
+                OP_DUP => match self.repeat(i, to, &mut out).or_else(|| self.switch(i, to, &mut out)) {
+                    Some(next) => next,
+                    None => {
+                        self.simple(&instruction, &mut out);
+                        i + 1
+                    }
+                },
+                OP_B | OP_BT | OP_BF => {
+                    let target = instruction.branch_target();
+                    if let Some(name) = self.children.get(&(instruction.address + 4)) {
+                        out.push(format!("// function {} is in {name}.gml", function_display(name)));
+                        self.at(target).max(i + 1)
+                    } else {
+                        if instruction.opcode != OP_B {
+                            self.pop();
+                        }
+                        match self.loops.last() {
+                            Some(&(_, end)) if target == end => out.push("break;".to_string()),
+                            Some(&(next, _)) if target == next => out.push("continue;".to_string()),
+                            _ => out.push(format!("// goto [{:05X}]", target.wrapping_sub(self.address(0)))),
+                        }
+                        i + 1
+                    }
+                }
+                OP_PUSHENV => {
+                    let end = self.at(instruction.branch_target()).clamp(i + 1, to);
+                    let target = self.pop();
+                    self.loops.push((self.address(end), self.address(end + 1)));
+                    let body = self.block(i + 1, end);
+                    self.loops.pop();
+                    out.push(format!("with ({})", target.text));
+                    out.push("{".to_string());
+                    out.extend(Self::indent(body));
+                    out.push("}".to_string());
+                    end + 1
+                }
+                _ => {
+                    self.simple(&instruction, &mut out);
+                    i + 1
+                }
+            };
+        }
+        out
+    }
+
+    /// `if`, `if`/`else`, `while`, ternaries and short-circuit `&&` and `||`.
+    fn conditional(&mut self, i: usize, to: usize, out: &mut Vec<String>) -> usize {
+        let instruction = self.instructions[i];
+        let t = self.at(instruction.branch_target());
+        let raw = self.pop();
+        let condition = if instruction.opcode == OP_BT { raw.clone().not() } else { raw.clone() };
+        if t > to || t <= i + 1 {
+            out.push(format!("// if ({}) goto [{:05X}]", condition.not().text, instruction.branch_target().wrapping_sub(self.address(0))));
+            return i + 1;
+        }
+        let before = self.instructions[t - 1];
+        // `a && b` pushes false when `a` fails, `a || b` pushes true when `a` succeeds
+        if before.opcode == OP_B && before.branch_target() == self.address(t + 1) && self.instructions[t].is_push() && t < to {
+            let depth = self.stack.len();
+            let lines = self.block(i + 1, t - 1);
+            if lines.is_empty() && self.stack.len() == depth + 1 {
+                let right = self.pop();
+                self.stack.push(if instruction.opcode == OP_BT { Expr::binary(raw, "||", right, PREC_OR) } else { Expr::binary(raw, "&&", right, PREC_AND) });
+                return t + 1;
+            }
+            out.extend(lines);
+        }
+        if before.opcode == OP_B && before.branch_target() < instruction.address {
+            // The condition was computed from the start of the loop
+            self.loops.push((before.branch_target(), self.address(t)));
+            let body = self.block(i + 1, t - 1);
+            self.loops.pop();
+            out.push(format!("while ({})", condition.text));
+            out.push("{".to_string());
+            out.extend(Self::indent(body));
+            out.push("}".to_string());
+            return t;
+        }
+        let is_loop_exit = self.loops.last().is_some_and(|&(next, end)| before.branch_target() == next || before.branch_target() == end);
+        if before.opcode == OP_B && before.branch_target() > self.address(t) && !is_loop_exit && self.at(before.branch_target()) <= to {
+            let e = self.at(before.branch_target());
+            let depth = self.stack.len();
+            let then = self.block(i + 1, t - 1);
+            if then.is_empty() && self.stack.len() == depth + 1 {
+                let yes = self.pop();
+                let no = self.block(t, e);
+                if no.is_empty() && self.stack.len() == depth + 1 {
+                    let no = self.pop();
+                    self.stack.push(Expr {
+                        text: format!("{} ? {} : {}", condition.wrap(PREC_TERNARY + 1), yes.wrap(PREC_TERNARY + 1), no.wrap(PREC_TERNARY)),
+                        prec: PREC_TERNARY,
+                        binary: None,
+                    });
+                    return e;
+                }
+                self.stack.push(yes);
+                out.push(format!("if ({})", condition.text));
+                out.push("{".to_string());
+                out.push("}".to_string());
+                out.push("else".to_string());
+                out.push("{".to_string());
+                out.extend(Self::indent(no));
+                out.push("}".to_string());
+                return e;
+            }
+            let no = self.block(t, e);
+            out.push(format!("if ({})", condition.text));
+            out.push("{".to_string());
+            out.extend(Self::indent(then));
+            out.push("}".to_string());
+            let is_chain = no.first().is_some_and(|l| l.starts_with("if ("))
+                && no.iter().skip(1).filter(|l| !l.starts_with(' ')).all(|l| matches!(l.as_str(), "{" | "}" | "else") || l.starts_with("else if ("));
+            if is_chain {
+                // A lone nested if becomes an `else if` chain
+                out.push(format!("else {}", no[0]));
+                out.extend(no.into_iter().skip(1));
+            } else {
+                out.push("else".to_string());
+                out.push("{".to_string());
+                out.extend(Self::indent(no));
+                out.push("}".to_string());
+            }
+            return e;
+        }
+        let then = self.block(i + 1, t);
+        out.push(format!("if ({})", condition.text));
+        out.push("{".to_string());
+        out.extend(Self::indent(then));
+        out.push("}".to_string());
+        t
+    }
+
+    /// `repeat (n)` keeps its counter on the stack: `dup; push 0; cmp LE; bt end; body; push 1; sub; dup; conv; bt body; end: popz`.
+    fn repeat(&mut self, i: usize, to: usize, out: &mut Vec<String>) -> Option<usize> {
+        let [zero, compare, exit] = [i + 1, i + 2, i + 3].map(|k| self.instructions.get(k).copied());
+        let (zero, compare, exit) = (zero?, compare?, exit?);
+        if !(zero.is_push() && compare.opcode == OP_CMP && compare.value >> 8 == 2 && exit.opcode == OP_BT) {
+            return None;
+        }
+        let end = self.at(exit.branch_target());
+        let back = (i + 4..end.min(to)).rev().find(|&j| self.instructions[j].opcode == OP_BT && self.instructions[j].branch_target() == self.address(i + 4))?;
+        let count = self.pop();
+        self.do_loops.remove(&(i + 4));
+        self.loops.push((self.address(back.saturating_sub(4)), self.address(end)));
+        let body = self.block(i + 4, back.saturating_sub(4).max(i + 4));
+        self.loops.pop();
+        out.push(format!("repeat ({})", count.text));
+        out.push("{".to_string());
+        out.extend(Self::indent(body));
+        out.push("}".to_string());
+        // Skip the popz of the counter
+        Some(if self.instructions.get(end).is_some_and(|e| e.opcode == OP_POPZ) { end + 1 } else { end })
+    }
+
+    /// `switch` compares a duplicate of the value with each case: `dup; push case; cmp EQ; bt label`,
+    /// then branches to the default, the cases break to a `popz` of the value.
+    fn switch(&mut self, i: usize, to: usize, out: &mut Vec<String>) -> Option<usize> {
+        let mut cases = Vec::new();
+        let mut k = i;
+        while k + 3 < to {
+            let [dup, value, compare, branch] = [k, k + 1, k + 2, k + 3].map(|k| self.instructions[k]);
+            if !(dup.opcode == OP_DUP && value.is_push() && compare.opcode == OP_CMP && compare.value >> 8 == 3 && branch.opcode == OP_BT) {
+                break;
+            }
+            let mut ignored = Vec::new();
+            self.simple(&value, &mut ignored);
+            cases.push((self.pop(), branch.branch_target()));
+            k += 4;
+        }
+        // The jump to the default must be inside the block, the cases can run up to its end
+        if cases.is_empty() || k >= to || self.instructions[k].opcode != OP_B {
+            return None;
+        }
+        let default = self.instructions[k].branch_target();
+        let end = self.instructions[k + 1..to].iter()
+            .filter(|b| b.opcode == OP_B && self.is_popz(b.branch_target()) && b.branch_target() >= default)
+            .map(|b| b.branch_target())
+            .max()
+            .unwrap_or(default);
+        let end_index = self.at(end).min(to);
+        let value = self.pop();
+        let mut labels = cases.iter().map(|c| c.1).collect::<Vec<usize>>();
+        if default != end {
+            labels.push(default);
+        }
+        labels.sort();
+        labels.dedup();
+        let next = self.loops.last().map_or(usize::MAX, |l| l.0);
+        self.loops.push((next, end));
+        out.push(format!("switch ({})", value.text));
+        out.push("{".to_string());
+        for (n, &label) in labels.iter().enumerate() {
+            for (case, _) in cases.iter().filter(|c| c.1 == label) {
+                out.push(format!("    case {}:", case.text));
+            }
+            if label == default && default != end {
+                out.push("    default:".to_string());
+            }
+            let stop = labels.get(n + 1).map_or(end_index, |&l| self.at(l)).min(end_index);
+            let body = self.block(self.at(label), stop);
+            out.extend(Self::indent(Self::indent(body).collect()));
+        }
+        self.loops.pop();
+        out.push("}".to_string());
+        Some(if self.is_popz(end) { end_index + 1 } else { end_index })
+    }
+
+    /// Whether the instruction at `address` drops the switch value, which ends a switch.
+    fn is_popz(&self, address: usize) -> bool {
+        self.index.get(&address).is_some_and(|&e| self.instructions[e].opcode == OP_POPZ)
+    }
+
+    fn scope(&self, instance: i16) -> String {
+        match instance {
+            -1 | -6 | -7 => String::new(),
+            i => format!("{}.", instance_name(i, &self.disassembler.objects)),
+        }
+    }
+
+    /// Scope from an instance pushed on the stack, numbers are instance types or object indices.
+    fn stack_scope(&self, instance: Expr) -> String {
+        match instance.text.parse::<i16>() {
+            Ok(i) => self.scope(i),
+            Err(_) => format!("{}.", instance.wrap(PREC_ATOM)),
+        }
+    }
+
+    fn variable(&mut self, instruction: &Instruction, value_first: bool) -> (String, Option<Expr>) {
+        let name = self.disassembler.variable_name(instruction).unwrap_or("/* unknown variable */").to_string();
+        let kind = instruction.operand_u32().unwrap_or_default() >> 24 & 0xf8;
+        let value = value_first.then(|| self.pop());
+        let target = match kind {
+            0x00 | 0x10 | 0x90 => {
+                let index = self.pop();
+                let instance = self.pop();
+                format!("{}{name}[{}]", self.stack_scope(instance), index.text)
+            }
+            0x80 => {
+                let instance = self.pop();
+                format!("{}{name}", self.stack_scope(instance))
+            }
+            _ => format!("{}{name}", self.scope(instruction.value as i16)),
+        };
+        (target, value)
+    }
+
+    fn literal(&self, instruction: &Instruction) -> Expr {
+        let bytes = |n: usize| instruction.operand.get(0..n).map(|b| b.to_vec()).unwrap_or(vec![0; n]);
+        Expr::atom(match instruction.type1 {
+            TYPE_DOUBLE => f64::from_le_bytes(bytes(8).try_into().unwrap()).to_string(),
+            TYPE_FLOAT => f32::from_le_bytes(bytes(4).try_into().unwrap()).to_string(),
+            TYPE_INT32 => i32::from_le_bytes(bytes(4).try_into().unwrap()).to_string(),
+            TYPE_INT64 => i64::from_le_bytes(bytes(8).try_into().unwrap()).to_string(),
+            TYPE_BOOL => (u32::from_le_bytes(bytes(4).try_into().unwrap()) != 0).to_string(),
+            TYPE_STRING => {
+                let index = instruction.operand_u32().unwrap_or_default() as usize;
+                match self.disassembler.strings.get(index) {
+                    Some(string) => format!("\"{}\"", escape(string, true).replace('"', "\\\"")),
+                    None => format!("/* string {index} */"),
+                }
+            }
+            TYPE_INT16 => (instruction.value as i16).to_string(),
+            _ => "/* ? */".to_string(),
+        })
+    }
+
+    fn simple(&mut self, instruction: &Instruction, out: &mut Vec<String>) {
+        let binary = |op: u8| -> Option<(&'static str, u8)> {
+            Some(match op {
+                OP_MUL => ("*", PREC_MUL),
+                OP_DIV => ("/", PREC_MUL),
+                OP_REM => ("div", PREC_MUL),
+                OP_MOD => ("mod", PREC_MUL),
+                OP_ADD => ("+", PREC_ADD),
+                OP_SUB => ("-", PREC_ADD),
+                OP_AND => (if instruction.type1 == TYPE_BOOL { "&&" } else { "&" }, PREC_BITWISE),
+                OP_OR => (if instruction.type1 == TYPE_BOOL { "||" } else { "|" }, PREC_BITWISE),
+                OP_XOR => (if instruction.type1 == TYPE_BOOL { "^^" } else { "^" }, PREC_BITWISE),
+                OP_SHL => ("<<", PREC_SHIFT),
+                OP_SHR => (">>", PREC_SHIFT),
+                _ => return None,
+            })
+        };
+        match instruction.opcode {
+            _ if instruction.is_push() && instruction.type1 == TYPE_VARIABLE => {
+                let (target, _) = self.variable(instruction, false);
+                self.stack.push(Expr::atom(target));
+            }
+            _ if instruction.is_push() => {
+                let literal = self.literal(instruction);
+                self.stack.push(literal);
+            }
+            OP_POP if instruction.type1 == TYPE_INT16 => {
+                // Stack swap used by some array assignments
+                if self.stack.len() >= 2 {
+                    let top = self.stack.len() - 1;
+                    self.stack.swap(top, top - 1);
+                }
+            }
+            OP_POP => {
+                let (target, value) = self.variable(instruction, instruction.type1 == TYPE_INT32);
+                let value = value.unwrap_or_else(|| self.pop());
+                let declare = instruction.value as i16 == -7 && self.locals.insert(target.clone());
+                let line = match &value.binary {
+                    Some((left, op, right)) if *left == target && matches!(*op, "+" | "-" | "*" | "/" | "|" | "&" | "^") && !declare => {
+                        format!("{target} {op}= {right};")
+                    }
+                    _ => format!("{}{target} = {};", if declare { "var " } else { "" }, value.text),
+                };
+                out.push(line);
+            }
+            OP_POPZ => {
+                let value = self.pop();
+                if value.text.ends_with(')') {
+                    out.push(format!("{};", value.text));
+                }
+            }
+            OP_DUP => {
+                // Swap mode of 2.3 uses the high byte
+                if instruction.value >> 8 == 0 {
+                    let count = ((instruction.value & 0xff) as usize + 1).min(self.stack.len());
+                    let top = self.stack.split_at(self.stack.len() - count).1.to_vec();
+                    self.stack.extend(top);
+                }
+            }
+            OP_CONV => {}
+            OP_NEG => {
+                let value = self.pop();
+                self.stack.push(Expr::unary("-", value));
+            }
+            OP_NOT => {
+                let value = self.pop();
+                self.stack.push(if instruction.type1 == TYPE_BOOL { value.not() } else { Expr::unary("~", value) });
+            }
+            OP_CMP => {
+                let right = self.pop();
+                let left = self.pop();
+                let op = ["", "<", "<=", "==", "!=", ">=", ">"].get((instruction.value >> 8) as usize).copied().unwrap_or("?");
+                self.stack.push(Expr::binary(left, op, right, PREC_COMPARE));
+            }
+            OP_CALL => {
+                let name = self.disassembler.function_name(instruction).map(function_display).unwrap_or("/* unknown function */").to_string();
+                let args = (0..instruction.value).map(|_| self.pop().text).collect::<Vec<String>>();
+                self.stack.push(Expr::atom(format!("{name}({})", args.join(", "))));
+            }
+            OP_CALLV => {
+                let function = self.pop();
+                let instance = self.pop();
+                let args = (0..instruction.value).map(|_| self.pop().text).collect::<Vec<String>>();
+                let scope = if matches!(instance.text.as_str(), "-1" | "self") { String::new() } else { self.stack_scope(instance) };
+                self.stack.push(Expr::atom(format!("{scope}{}({})", function.wrap(PREC_ATOM), args.join(", "))));
+            }
+            OP_RET => {
+                let value = self.pop();
+                out.push(format!("return {};", value.text));
+            }
+            OP_EXIT => out.push("exit;".to_string()),
+            OP_BREAK => match instruction.value as i16 {
+                // pushaf and pushac read an array element, popaf writes one
+                -2 | -4 => {
+                    let index = self.pop();
+                    let array = self.pop();
+                    self.stack.push(Expr::atom(format!("{}[{}]", array.wrap(PREC_ATOM), index.text)));
+                }
+                -3 => {
+                    let index = self.pop();
+                    let array = self.pop();
+                    let value = self.pop();
+                    out.push(format!("{}[{}] = {};", array.wrap(PREC_ATOM), index.text, value.text));
+                }
+                -5 => {
+                    self.pop();
+                }
+                _ => {}
+            },
+            op => match binary(op) {
+                Some((op, prec)) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Expr::binary(left, op, right, prec));
+                }
+                None => out.push(format!("// {}", opcode_name(op))),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::form::builder::FormBuilder;
+
+    /// Assembles `text` in a script using the self variables `x`, `y` and `z` and the function `show`, then decompiles it.
+    fn decompile(text: &str) -> String {
+        let strings = ["gml_Script_test", "x", "y", "z", "show"];
+        let build = |bytecode: &[u8], variables: &[(usize, usize)], functions: &[(usize, usize)]| {
+            let mut builder = FormBuilder::new(17, &strings);
+            let start = builder.code(&[(0, bytecode)])[0];
+            let refs = |list: &[(usize, usize)], index: usize| list.iter().filter(|r| r.1 == index).map(|r| start + r.0).collect::<Vec<usize>>();
+            let variables = (0..3).map(|i| refs(variables, i)).collect::<Vec<Vec<usize>>>();
+            builder.variables(&variables.iter().enumerate().map(|(i, r)| (i + 1, r.as_slice())).collect::<Vec<_>>());
+            builder.functions(&[(4, refs(functions, 0).as_slice())]);
+            builder.build()
+        };
+        let data = build(&[], &[], &[]);
+        let form = Form::new(&data).unwrap();
+        let assembled = Disassembler::new(&form).assemble(text).unwrap();
+        let data = build(&assembled.bytes, &assembled.variables, &assembled.functions);
+        let form = Form::new(&data).unwrap();
+        let disassembler = Disassembler::new(&form);
+        Decompiler::new(&disassembler, &form, &disassembler.code[0]).decompile()
+    }
+
+    fn gml(lines: &[&str]) -> String {
+        lines.iter().map(|l| format!("{l}\n")).collect()
+    }
+
+    #[test]
+    fn if_else() {
+        let text = "
+            push.v self.x
+            pushi.e 1
+            cmp.i.v LT
+            bf [else]
+            push.v self.y
+            call.i show(argc=1)
+            popz.v
+            b [end]
+            :[else]
+            pushi.e 2
+            pop.v.e self.y
+            :[end]
+        ";
+        assert_eq!(decompile(text), gml(&["if (x < 1)", "{", "    show(y);", "}", "else", "{", "    y = 2;", "}"]));
+    }
+
+    #[test]
+    fn while_loop() {
+        let text = "
+            :[top]
+            push.v self.x
+            pushi.e 10
+            cmp.i.v LT
+            bf [end]
+            push.v self.x
+            pushi.e 1
+            add.i.v
+            pop.v.v self.x
+            b [top]
+            :[end]
+        ";
+        assert_eq!(decompile(text), gml(&["while (x < 10)", "{", "    x += 1;", "}"]));
+    }
+
+    #[test]
+    fn do_until() {
+        let text = "
+            :[top]
+            push.v self.x
+            pushi.e 2
+            mul.i.v
+            pop.v.v self.x
+            push.v self.x
+            pushi.e 100
+            cmp.i.v GE
+            bf [top]
+        ";
+        assert_eq!(decompile(text), gml(&["do", "{", "    x *= 2;", "} until (x >= 100);"]));
+    }
+
+    #[test]
+    fn repeat_loop() {
+        let text = "
+            pushi.e 3
+            dup.i 0
+            pushi.e 0
+            cmp.i.i LE
+            bt [end]
+            :[body]
+            push.v self.y
+            call.i show(argc=1)
+            popz.v
+            pushi.e 1
+            sub.i.i
+            dup.i 0
+            conv.i.b
+            bt [body]
+            :[end]
+            popz.i
+        ";
+        assert_eq!(decompile(text), gml(&["repeat (3)", "{", "    show(y);", "}"]));
+    }
+
+    #[test]
+    fn switch_fallthrough() {
+        let text = "
+            push.v self.x
+            dup.v 0
+            pushi.e 1
+            cmp.i.v EQ
+            bt [one]
+            dup.v 0
+            pushi.e 2
+            cmp.i.v EQ
+            bt [two]
+            b [default]
+            :[one]
+            pushi.e 1
+            pop.v.e self.y
+            :[two]
+            pushi.e 2
+            pop.v.e self.y
+            b [end]
+            :[default]
+            pushi.e 3
+            pop.v.e self.y
+            :[end]
+            popz.v
+        ";
+        let expected = ["switch (x)", "{", "    case 1:", "        y = 1;", "    case 2:", "        y = 2;", "        break;", "    default:", "        y = 3;", "}"];
+        assert_eq!(decompile(text), gml(&expected));
+    }
+
+    #[test]
+    fn with_block() {
+        let text = "
+            push.v self.z
+            pushenv [next]
+            :[body]
+            pushi.e 1
+            pop.v.e self.x
+            :[next]
+            popenv [body]
+        ";
+        assert_eq!(decompile(text), gml(&["with (z)", "{", "    x = 1;", "}"]));
+    }
+
+    #[test]
+    fn short_circuit() {
+        let condition = |branch: &str, pushed: &str| format!("
+            push.v self.x
+            pushi.e 0
+            cmp.i.v GT
+            {branch} [short]
+            push.v self.y
+            pushi.e 0
+            cmp.i.v GT
+            b [test]
+            :[short]
+            push.e {pushed}
+            :[test]
+            bf [end]
+            pushi.e 1
+            pop.v.e self.z
+            :[end]
+        ");
+        assert_eq!(decompile(&condition("bf", "0")), gml(&["if (x > 0 && y > 0)", "{", "    z = 1;", "}"]));
+        assert_eq!(decompile(&condition("bt", "1")), gml(&["if (x > 0 || y > 0)", "{", "    z = 1;", "}"]));
+    }
+}
@@ -0,0 +1,307 @@
+use std::{collections::{BTreeSet, HashMap}, env, fs, path::PathBuf};
+use hudhook::imgui;
+use rfd::FileDialog;
+
+use crate::arena::Arena;
+use crate::asm::patch_file;
+
+use crate::code::*;
+use crate::form::Form;
+use crate::room::object_names;
+use crate::strings::escape;
+
+/// Names everything the bytecode of a container refers to.
+#[derive(Default)]
+pub struct Disassembler {
+    pub version: u8,
+    pub code: Vec<CodeEntry>,
+    pub strings: Vec<Vec<u8>>,
+    pub variables: Vec<Variable>,
+    pub functions: Vec<Function>,
+    pub objects: Vec<String>,
+    pub variable_refs: HashMap<usize, usize>, // Instruction address to VARI index
+    pub function_refs: HashMap<usize, usize>, // Instruction address to FUNC index
+    pub first_address_offset: usize, // 4 when VARI and FUNC point at the operand of the first occurrence
+}
+
+pub fn instance_name(instance: i16, objects: &[String]) -> String {
+    match instance {
+        -1 => "self".to_string(),
+        -2 => "other".to_string(),
+        -3 => "all".to_string(),
+        -4 => "noone".to_string(),
+        -5 => "global".to_string(),
+        -6 => "builtin".to_string(),
+        -7 => "local".to_string(),
+        -9 => "stacktop".to_string(),
+        -15 => "arg".to_string(),
+        -16 => "static".to_string(),
+        i if i >= 0 => objects.get(i as usize).cloned().unwrap_or(i.to_string()),
+        i => i.to_string(),
+    }
+}
+
+impl Disassembler {
+    pub fn new(form: &Form) -> Self {
+        let version = bytecode_version(form);
+        let strings = form.chunk(b"STRG").and_then(|c| form.pointer_list(c.offset)).unwrap_or_default().into_iter()
+            .map(|ptr| form.u32(ptr).and_then(|size| form.bytes(ptr + 4, size as usize)).unwrap_or_default().to_vec())
+            .collect();
+        let variables = parse_variables(form);
+        let functions = parse_functions(form);
+        // The first address may point at the operand instead of the instruction word
+        let first_address_offset = variables.iter().find(|v| v.occurrences > 0)
+            .filter(|v| !decode(form.data, v.first_address as usize, version).is_some_and(|i| i.has_variable()))
+            .map_or(0, |_| 4);
+        let chain = |first_address: u32, occurrences: u32, is_ref: &dyn Fn(&Instruction) -> bool| {
+            let mut address = (first_address as usize).wrapping_sub(first_address_offset);
+            let mut addresses = Vec::new();
+            for _ in 0..occurrences {
+                let Some(instruction) = decode(form.data, address, version).filter(|i| is_ref(i)) else {
+                    break;
+                };
+                addresses.push(address);
+                // Until the runner links them, references store the distance to the next occurrence
+                address += (instruction.operand_u32().unwrap() & 0x07ffffff) as usize;
+            }
+            addresses
+        };
+        let mut variable_refs = HashMap::new();
+        for (i, variable) in variables.iter().enumerate() {
+            for address in chain(variable.first_address, variable.occurrences, &|i| i.has_variable()) {
+                variable_refs.insert(address, i);
+            }
+        }
+        let mut function_refs = HashMap::new();
+        for (i, function) in functions.iter().enumerate() {
+            for address in chain(function.first_address, function.occurrences, &|i| i.opcode == OP_CALL) {
+                function_refs.insert(address, i);
+            }
+        }
+        Self {
+            version,
+            code: parse_code(form),
+            strings,
+            variables,
+            functions,
+            objects: object_names(form),
+            variable_refs,
+            function_refs,
+            first_address_offset,
+        }
+    }
+
+    /// Name of the VARI entry an instruction refers to, when the reference chains could be followed.
+    pub fn variable_name(&self, instruction: &Instruction) -> Option<&str> {
+        self.variable_refs.get(&instruction.address).map(|&i| self.variables[i].name.as_str())
+    }
+
+    pub fn function_name(&self, instruction: &Instruction) -> Option<&str> {
+        self.function_refs.get(&instruction.address).map(|&i| self.functions[i].name.as_str())
+    }
+
+    fn variable(&self, instruction: &Instruction) -> String {
+        let operand = instruction.operand_u32().unwrap_or_default();
+        let name = self.variable_name(instruction).map(|n| n.to_string()).unwrap_or(format!("<var 0x{:x}>", operand & 0x07ffffff));
+        let instance = instance_name(instruction.value as i16, &self.objects);
+        match operand >> 24 & 0xf8 {
+            0x00 => format!("[array]{instance}.{name}"),
+            0x80 => format!("[stacktop]{instance}.{name}"),
+            0xe0 => format!("[instance]{instance}.{name}"),
+            0x10 => format!("[arraypushaf]{instance}.{name}"),
+            0x90 => format!("[arraypopaf]{instance}.{name}"),
+            _ => format!("{instance}.{name}"),
+        }
+    }
+
+    fn operand(&self, instruction: &Instruction) -> String {
+        let bytes = |n: usize| instruction.operand.get(0..n).map(|b| b.to_vec()).unwrap_or(vec![0; n]);
+        match instruction.opcode {
+            _ if instruction.has_variable() => self.variable(instruction),
+            OP_POP => format!("{}", instruction.value as i16), // Swap
+            _ if instruction.is_push() => match instruction.type1 {
+                TYPE_DOUBLE => f64::from_le_bytes(bytes(8).try_into().unwrap()).to_string(),
+                TYPE_FLOAT => f32::from_le_bytes(bytes(4).try_into().unwrap()).to_string(),
+                TYPE_INT32 => i32::from_le_bytes(bytes(4).try_into().unwrap()).to_string(),
+                TYPE_INT64 => i64::from_le_bytes(bytes(8).try_into().unwrap()).to_string(),
+                TYPE_BOOL => (u32::from_le_bytes(bytes(4).try_into().unwrap()) != 0).to_string(),
+                TYPE_STRING => {
+                    let index = instruction.operand_u32().unwrap_or_default() as usize;
+                    match self.strings.get(index) {
+                        Some(string) => format!("\"{}\"@{index}", escape(string, true).replace('"', "\\\"")),
+                        None => format!("<string {index}>"),
+                    }
+                }
+                TYPE_INT16 => (instruction.value as i16).to_string(),
+                _ => String::new(),
+            },
+            OP_CALL => {
+                let name = self.function_name(instruction).map(|n| n.to_string())
+                    .unwrap_or(format!("<func 0x{:x}>", instruction.operand_u32().unwrap_or_default()));
+                format!("{name}(argc={})", instruction.value)
+            }
+            OP_CALLV => format!("{}", instruction.value),
+            OP_DUP => format!("{}", instruction.value),
+            OP_CMP => CMP_KINDS.get((instruction.value >> 8) as usize).unwrap_or(&"?").to_string(),
+            OP_BREAK => match instruction.operand_u32() {
+                Some(argument) => format!("{} {argument}", instruction.value as i16),
+                None => format!("{}", instruction.value as i16),
+            },
+            _ => String::new(),
+        }
+    }
+
+    /// One instruction per line, branch targets and the addresses are relative to the entry.
+    pub fn disassemble(&self, form: &Form, entry: &CodeEntry) -> String {
+        let start = entry.bytecode + entry.offset;
+        let length = entry.length.saturating_sub(entry.offset);
+        let instructions = instructions(form.data, start, length, self.version);
+        let targets = instructions.iter()
+            .filter(|i| matches!(i.opcode, OP_B | OP_BT | OP_BF | OP_PUSHENV | OP_POPENV) && !i.is_popenv_exit())
+            .map(|i| i.branch_target())
+            .collect::<BTreeSet<usize>>();
+        let mut text = format!("; {} (locals {}, arguments {}, bytecode {})\n", entry.name, entry.locals, entry.arguments & 0x7fff, self.version);
+        for instruction in &instructions {
+            if targets.contains(&instruction.address) {
+                text += &format!(":[{:05X}]\n", instruction.address - start);
+            }
+            let mut mnemonic = opcode_name(instruction.opcode).to_string();
+            let types = match instruction.opcode {
+                OP_CONV | OP_MUL | OP_DIV | OP_REM | OP_MOD | OP_ADD | OP_SUB | OP_AND | OP_OR | OP_XOR | OP_SHL | OP_SHR | OP_CMP | OP_POP => 2,
+                OP_B | OP_BT | OP_BF | OP_PUSHENV | OP_POPENV => 0,
+                _ => 1,
+            };
+            if types > 0 {
+                mnemonic += &format!(".{}", type_suffix(instruction.type1));
+            }
+            if types > 1 {
+                mnemonic += &format!(".{}", type_suffix(instruction.type2));
+            }
+            let operand = match instruction.opcode {
+                OP_POPENV if instruction.is_popenv_exit() => "<exit>".to_string(),
+                OP_B | OP_BT | OP_BF | OP_PUSHENV | OP_POPENV => format!("[{:05X}]", instruction.branch_target().wrapping_sub(start)),
+                _ => self.operand(instruction),
+            };
+            let line = format!("{mnemonic} {operand}");
+            text += &format!("{:05X}: {}\n", instruction.address - start, line.trim_end());
+        }
+        text
+    }
+}
+
+/// Lists the code entries, shows their disassembly and patches them back in memory or in a copy of data.win.
+/// Names come from the data.win next to the game, the runner overwrites the references in memory when linking.
+///
+/// The runner compiles the CODE entries into its own code objects when it loads the data file. "Apply Live"
+/// re-points the entry in memory, which only code objects created from it afterwards pick up. Code compiled
+/// at load keeps its bytecode until a data.win saved with the patch is loaded.
+#[derive(Default)]
+pub struct CodePanel {
+    disassembler: Disassembler,
+    disk: Vec<u8>,
+    filter: String,
+    selected: Option<usize>,
+    text: String,
+    patched: HashMap<usize, [u32; 3]>, // Entry pointer to its original length, bytecode address and offset
+}
+
+impl CodePanel {
+    pub fn load(&mut self, form: &Form) {
+        let mut path = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+        path.push("data.win");
+        self.disk = fs::read(path).unwrap_or_default();
+        self.disassembler = match Form::new(&self.disk) {
+            Some(disk) => Disassembler::new(&disk),
+            None => {
+                println!("========== Could not read data.win, code references can't be named ==========");
+                Disassembler::new(form)
+            }
+        };
+        println!("Found {} code entries (bytecode {})", self.disassembler.code.len(), self.disassembler.version);
+    }
+
+    unsafe fn restore(&mut self, form: &Form, arena: &mut Arena, ptr: usize) {
+        if let Some(original) = self.patched.remove(&ptr) {
+            arena.set(form.addr(ptr + 4), original[0], None);
+            arena.set(form.addr(ptr + 12), original[1], None);
+            arena.set(form.addr(ptr + 16), original[2], None);
+        }
+    }
+
+    /// Points `entry` at the assembled text, kept in `arena` until it is restored. Code objects the runner
+    /// compiled from the entry before aren't affected.
+    unsafe fn apply_live(&mut self, form: &Form, arena: &mut Arena, entry: &CodeEntry) -> Result<(), String> {
+        let mut assembled = self.disassembler.assemble(&self.text)?;
+        self.disassembler.link_live(&mut assembled, form)?;
+        self.restore(form, arena, entry.ptr);
+        self.patched.insert(entry.ptr, [4, 12, 16].map(|field| form.u32(entry.ptr + field).unwrap()));
+        let length = assembled.bytes.len() as u32;
+        let addr = arena.alloc(assembled.bytes);
+        let slot = form.addr(entry.ptr + 12);
+        arena.set(form.addr(entry.ptr + 4), length, None);
+        arena.set(slot, addr.wrapping_sub(slot) as u32, Some(addr));
+        arena.set(form.addr(entry.ptr + 16), 0, None);
+        Ok(())
+    }
+
+    fn save_patched(&self, entry: &CodeEntry) -> Result<Option<PathBuf>, String> {
+        let mut data = self.disk.clone();
+        if data.is_empty() {
+            return Err("data.win could not be read".to_string());
+        }
+        let assembled = self.disassembler.assemble(&self.text)?;
+        patch_file(&mut data, &self.disassembler, entry, &assembled)?;
+        let Some(file) = FileDialog::new().add_filter("GameMaker Data", &["win"]).set_file_name("data.win").save_file() else {
+            return Ok(None);
+        };
+        fs::write(&file, data).map_err(|e| e.to_string())?;
+        Ok(Some(file))
+    }
+
+    pub fn render(&mut self, ui: &imgui::Ui, form: Option<&Form>, arena: &mut Arena) {
+        ui.set_next_item_width(150.);
+        ui.input_text("Filter##code", &mut self.filter).build();
+        let filter = self.filter.to_lowercase();
+        let mut clicked = None;
+        ui.child_window("##code").size([0., 120.]).build(|| {
+            for (i, entry) in self.disassembler.code.iter().enumerate() {
+                let name = if self.patched.contains_key(&entry.ptr) { format!("{} (patched)", entry.name) } else { entry.name.clone() };
+                if entry.name.to_lowercase().contains(&filter)
+                    && ui.selectable_config(format!("{name}##code{i}")).selected(self.selected == Some(i)).build() {
+                    clicked = Some(i);
+                }
+            }
+        });
+        let disk = Form::new(&self.disk);
+        if let Some(i) = clicked {
+            if let Some(form) = disk.as_ref().or(form) {
+                self.selected = Some(i);
+                self.text = self.disassembler.disassemble(form, &self.disassembler.code[i]);
+            }
+        }
+        let (Some(selected), Some(form)) = (self.selected, form) else {
+            return;
+        };
+        let entry = self.disassembler.code[selected].clone();
+        ui.text_disabled("Live patches reach code compiled after them, save a patched data.win for code already loaded");
+        if ui.button("Apply Live##code") {
+            match unsafe { self.apply_live(form, arena, &entry) } {
+                Ok(()) => println!("========== Patched {} ==========", entry.name),
+                Err(e) => println!("Could not patch {}: {e}", entry.name),
+            }
+        }
+        ui.same_line();
+        if ui.button("Restore##code") {
+            unsafe { self.restore(form, arena, entry.ptr) };
+        }
+        ui.same_line();
+        if ui.button("Save Patched data.win") {
+            match self.save_patched(&entry) {
+                Ok(Some(file)) => println!("========== Saved {} ==========", file.display()),
+                Ok(None) => {}
+                Err(e) => println!("Could not patch {}: {e}", entry.name),
+            }
+        }
+        ui.input_text_multiline("##disasm", &mut self.text, [0., 250.]).build();
+    }
+}
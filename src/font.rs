@@ -0,0 +1,450 @@
+use std::{fs, path::Path};
+use serde::{Deserialize, Serialize};
+
+use crate::atlas::{repack, RegionSet};
+use crate::form::{append, put_u32, Form};
+use crate::sprite::{page_item, PageCache};
+use crate::texture::{decode_png, encode_png, Image, TexturePage};
+
+#[derive(Default, Clone)]
+pub struct Font {
+    pub ptr: usize,
+    pub glyph_list: usize, // Offset of the glyph pointer list
+    pub name: String,
+    pub display_name: String,
+    pub size: f32,
+    pub bold: bool,
+    pub italic: bool,
+    pub range_start: u16,
+    pub charset: u8,
+    pub antialias: u8,
+    pub range_end: u32,
+    pub tpag: usize, // Pointer to the TPAG entry holding the glyphs
+    pub scale: [f32; 2],
+    pub glyphs: Vec<Glyph>,
+}
+
+#[derive(Default, Clone)]
+pub struct Glyph {
+    pub character: u16,
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub shift: i16,
+    pub offset: i16,
+    pub kerning: Vec<(i16, i16)>, // Other character, amount
+}
+
+impl Font {
+    pub fn glyph(&self, character: char) -> Option<&Glyph> {
+        self.glyphs.iter().find(|g| g.character as u32 == character as u32)
+    }
+
+    pub fn has_glyph(&self, character: char) -> bool {
+        // Control characters (line breaks) are never drawn
+        character.is_control() || self.glyph(character).is_some()
+    }
+
+    /// Characters of `string` that this font can't draw, without duplicates.
+    pub fn missing(&self, string: &str) -> Vec<char> {
+        let mut missing = Vec::new();
+        for c in string.chars() {
+            if !self.has_glyph(c) && !missing.contains(&c) {
+                missing.push(c);
+            }
+        }
+        missing
+    }
+
+    /// Width of a line in pixels, the glyph advances with the kerning of each pair.
+    pub fn line_width(&self, line: &str) -> i32 {
+        let mut width = 0;
+        let mut previous = None;
+        for c in line.chars() {
+            if let Some(glyph) = self.glyph(c) {
+                width += glyph.shift as i32;
+                if let Some(previous) = previous {
+                    width += glyph.kerning.iter().find(|k| k.0 as u32 == previous as u32).map_or(0, |k| k.1 as i32);
+                }
+            }
+            previous = Some(c);
+        }
+        width
+    }
+
+    /// Width of the widest line of `text` in pixels.
+    pub fn text_width(&self, text: &str) -> i32 {
+        text.split('\n').map(|line| self.line_width(line.trim_end_matches('\r'))).max().unwrap_or(0)
+    }
+
+    /// Breaks the lines of `text` at spaces so they are at most `width` pixels wide. Words wider than
+    /// that get a line of their own.
+    pub fn wrap(&self, text: &str, width: i32) -> String {
+        let mut lines = Vec::new();
+        for line in text.split('\n') {
+            let mut current: Option<String> = None;
+            for word in line.split(' ') {
+                current = Some(match current {
+                    // The space the line is broken at is dropped
+                    Some(current) if !current.is_empty() && self.line_width(&format!("{current} {word}")) > width => {
+                        lines.push(current);
+                        word.to_string()
+                    }
+                    Some(current) => format!("{current} {word}"),
+                    None => word.to_string(),
+                });
+            }
+            lines.push(current.unwrap_or_default());
+        }
+        lines.join("\n")
+    }
+}
+
+/// Characters of `string` that none of the `fonts` can draw, without duplicates.
+pub fn missing_glyphs(fonts: &[Font], string: &str) -> Vec<char> {
+    let mut missing = Vec::new();
+    if fonts.is_empty() {
+        // Nothing to check against when the FONT chunk couldn't be read
+        return missing;
+    }
+    for c in string.chars() {
+        if !fonts.iter().any(|f| f.has_glyph(c)) && !missing.contains(&c) {
+            missing.push(c);
+        }
+    }
+    missing
+}
+
+pub fn parse_fonts(form: &Form) -> Vec<Font> {
+    let Some(chunk) = form.chunk(b"FONT") else {
+        println!("========== FONT chunk not found ==========");
+        return Vec::new();
+    };
+    let mut fonts = Vec::new();
+    for ptr in form.pointer_list(chunk.offset).unwrap_or_default() {
+        match parse_font(form, ptr) {
+            Some(font) => fonts.push(font),
+            None => println!("Invalid font entry at 0x{ptr:x}"),
+        }
+    }
+    fonts
+}
+
+fn parse_font(form: &Form, ptr: usize) -> Option<Font> {
+    let size = form.u32(ptr + 8)?;
+    // Newer runners add ascender, SDF and line height fields before the glyph list,
+    // the right one is the list whose first glyph is placed right after it.
+    let (glyph_list, glyphs) = (0..=4).map(|i| ptr + 40 + i * 4).find_map(|offset| {
+        let glyphs = form.pointer_list(offset)?;
+        (*glyphs.first()? == offset + 4 + glyphs.len() * 4).then_some((offset, glyphs))
+    }).unwrap_or((ptr + 40, Vec::new()));
+    let mut font = Font {
+        ptr,
+        glyph_list,
+        name: form.string(form.u32(ptr)? as usize)?,
+        display_name: form.string(form.u32(ptr + 4)? as usize).unwrap_or_default(),
+        // GMS 2.3 stores fractional sizes as a negated float
+        size: if size & (1 << 31) != 0 { -f32::from_bits(size) } else { size as f32 },
+        bold: form.u32(ptr + 12)? != 0,
+        italic: form.u32(ptr + 16)? != 0,
+        range_start: form.u16(ptr + 20)?,
+        charset: form.u8(ptr + 22)?,
+        antialias: form.u8(ptr + 23)?,
+        range_end: form.u32(ptr + 24)?,
+        tpag: form.u32(ptr + 28)? as usize,
+        scale: [form.f32(ptr + 32)?, form.f32(ptr + 36)?],
+        glyphs: Vec::new(),
+    };
+    for ptr in glyphs {
+        let mut glyph = Glyph {
+            character: form.u16(ptr)?,
+            x: form.u16(ptr + 2)?,
+            y: form.u16(ptr + 4)?,
+            width: form.u16(ptr + 6)?,
+            height: form.u16(ptr + 8)?,
+            shift: form.i16(ptr + 10)?,
+            offset: form.i16(ptr + 12)?,
+            kerning: Vec::new(),
+        };
+        for i in 0..form.u16(ptr + 14)? as usize {
+            glyph.kerning.push((form.i16(ptr + 16 + i * 4)?, form.i16(ptr + 18 + i * 4)?));
+        }
+        font.glyphs.push(glyph);
+    }
+    Some(font)
+}
+
+/// Glyph map of an exported font, positions are relative to the exported image.
+#[derive(Serialize, Deserialize)]
+pub struct FontMap {
+    pub name: String,
+    pub size: f32,
+    pub bold: bool,
+    pub italic: bool,
+    pub glyphs: Vec<GlyphMap>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GlyphMap {
+    pub character: String,
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub shift: i16,
+    pub offset: i16,
+    #[serde(default)]
+    pub kerning: Vec<(String, i16)>, // Other character, amount
+}
+
+fn character_string(character: u16) -> String {
+    char::from_u32(character as u32).map(String::from).unwrap_or_default()
+}
+
+fn character_code(character: &str) -> Result<u16, String> {
+    let mut chars = character.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => u16::try_from(c as u32).map_err(|_| format!("'{c}' is outside of the range fonts can store")),
+        _ => Err(format!("\"{character}\" is not a single character")),
+    }
+}
+
+impl FontMap {
+    pub fn new(font: &Font) -> Self {
+        Self {
+            name: font.name.clone(),
+            size: font.size,
+            bold: font.bold,
+            italic: font.italic,
+            glyphs: font.glyphs.iter().map(|g| GlyphMap {
+                character: character_string(g.character),
+                x: g.x,
+                y: g.y,
+                width: g.width,
+                height: g.height,
+                shift: g.shift,
+                offset: g.offset,
+                kerning: g.kerning.iter().map(|&(c, amount)| (character_string(c as u16), amount)).collect(),
+            }).collect(),
+        }
+    }
+
+    /// Glyphs of the map as the runner stores them.
+    pub fn glyphs(&self) -> Result<Vec<Glyph>, String> {
+        self.glyphs.iter().map(|g| Ok(Glyph {
+            character: character_code(&g.character)?,
+            x: g.x,
+            y: g.y,
+            width: g.width,
+            height: g.height,
+            shift: g.shift,
+            offset: g.offset,
+            kerning: g.kerning.iter().map(|(c, amount)| Ok((character_code(c)? as i16, *amount))).collect::<Result<Vec<(i16, i16)>, String>>()?,
+        })).collect()
+    }
+}
+
+/// Writes every font as `name.png` with its glyphs and `name.json` with the glyph map.
+pub fn export_fonts(form: &Form, pages: &[TexturePage], fonts: &[Font], dir: &Path) -> Result<usize, String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let mut cache = PageCache::new(form, pages);
+    let mut count = 0;
+    for font in fonts {
+        match export_font(form, &mut cache, font, dir) {
+            Ok(()) => count += 1,
+            Err(e) => println!("Could not export font {}: {e}", font.name),
+        }
+    }
+    Ok(count)
+}
+
+/// Writes the glyph image of a font as `name.png` and its glyphs as `name.json`.
+fn export_font(form: &Form, cache: &mut PageCache, font: &Font, dir: &Path) -> Result<(), String> {
+    let item = page_item(form, font.tpag).ok_or("Invalid TPAG entry")?;
+    let [x, y, width, height] = item.source.map(u32::from);
+    let mut image = Image::new(width, height);
+    image.blit(cache.page(item.page)?, (x, y), (width, height), (0, 0));
+    fs::write(dir.join(format!("{}.png", font.name)), encode_png(&image)?).map_err(|e| e.to_string())?;
+    let json = serde_json::to_string_pretty(&FontMap::new(font)).map_err(|e| e.to_string())?;
+    fs::write(dir.join(format!("{}.json", font.name)), json).map_err(|e| e.to_string())
+}
+
+/// FONT entry of `font` with the glyphs of `map` on the TPAG entry `tpag`. The header is copied from the
+/// original, `start` is the offset the entry will be at so the glyph pointers can follow it.
+pub fn font_entry(form: &Form, font: &Font, map: &FontMap, tpag: usize, start: usize) -> Result<Vec<u8>, String> {
+    let mut entry = form.bytes(font.ptr, font.glyph_list - font.ptr).ok_or(format!("Font {} is out of bounds", font.name))?.to_vec();
+    // Keep the size encoding of the original, fractional sizes need the float one
+    let size = if form.u32(font.ptr + 8).ok_or(format!("Font {} is out of bounds", font.name))? & (1 << 31) != 0 || map.size.fract() != 0. { (-map.size).to_bits() } else { map.size as u32 };
+    put_u32(&mut entry, 8, size);
+    put_u32(&mut entry, 12, map.bold as u32);
+    put_u32(&mut entry, 16, map.italic as u32);
+    put_u32(&mut entry, 28, tpag as u32);
+    let mut glyphs = Vec::new();
+    for glyph in &map.glyphs {
+        let mut bytes = character_code(&glyph.character)?.to_le_bytes().to_vec();
+        for value in [glyph.x, glyph.y, glyph.width, glyph.height, glyph.shift as u16, glyph.offset as u16, glyph.kerning.len() as u16] {
+            bytes.extend(value.to_le_bytes());
+        }
+        for (other, amount) in &glyph.kerning {
+            bytes.extend(character_code(other)?.to_le_bytes());
+            bytes.extend(amount.to_le_bytes());
+        }
+        glyphs.push(bytes);
+    }
+    let codes = map.glyphs.iter().map(|g| character_code(&g.character)).collect::<Result<Vec<u16>, String>>()?;
+    if let (Some(first), Some(last)) = (codes.iter().min(), codes.iter().max()) {
+        entry[20..22].copy_from_slice(&first.to_le_bytes());
+        put_u32(&mut entry, 24, *last as u32);
+    }
+    let mut glyph = start.wrapping_add(entry.len() + 4 + glyphs.len() * 4);
+    entry.extend((glyphs.len() as u32).to_le_bytes());
+    for bytes in &glyphs {
+        entry.extend((glyph as u32).to_le_bytes());
+        glyph = glyph.wrapping_add(bytes.len());
+    }
+    for bytes in glyphs {
+        entry.extend(bytes);
+    }
+    Ok(entry)
+}
+
+/// Replaces fonts in a data.win loaded in `data` with new glyph images and maps. The images are repacked
+/// into the texture pages and the fonts get new FONT entries at the end of the file.
+pub fn replace_fonts(data: &mut Vec<u8>, fonts: &[(Font, Image, FontMap)]) -> Result<(), String> {
+    for (font, image, map) in fonts {
+        if let Some(glyph) = map.glyphs.iter().find(|g| g.x as u32 + g.width as u32 > image.width || g.y as u32 + g.height as u32 > image.height) {
+            return Err(format!("Glyph {} of font {} is outside of its {}x{} image", glyph.character, font.name, image.width, image.height));
+        }
+    }
+    let sets = fonts.iter().map(|(font, image, _)| RegionSet { items: vec![font.tpag], images: vec![image], trim: false }).collect::<Vec<RegionSet>>();
+    let items = repack(data, &sets)?;
+    for ((font, _, map), items) in fonts.iter().zip(items) {
+        let form = Form::new(data).ok_or("Not a GameMaker data file")?;
+        let font_list = form.chunk(b"FONT").map(|c| c.offset).ok_or("The data file has no FONT chunk")?;
+        let slot = form.pointer_list(font_list).unwrap_or_default().iter().position(|&f| f == font.ptr)
+            .ok_or(format!("Font {} is not in the FONT list", font.name))?;
+        // The entry goes where the next append starts
+        let entry = font_entry(&form, font, map, items[0], data.len())?;
+        let offset = append(data, &entry)?;
+        put_u32(data, font_list + 4 + slot * 4, offset as u32);
+    }
+    Ok(())
+}
+
+/// Replaces the fonts that have a `name.png` and `name.json` in `dir` in a data.win loaded in `data`.
+pub fn import_fonts(data: &mut Vec<u8>, dir: &Path) -> Result<usize, String> {
+    let form = Form::new(data).ok_or("Not a GameMaker data file")?;
+    let mut fonts = Vec::new();
+    for font in parse_fonts(&form) {
+        let (image, json) = (dir.join(format!("{}.png", font.name)), dir.join(format!("{}.json", font.name)));
+        if !image.exists() || !json.exists() {
+            continue;
+        }
+        let image = decode_png(&fs::read(image).map_err(|e| e.to_string())?).map_err(|e| format!("{}.png: {e}", font.name))?;
+        let map = serde_json::from_slice::<FontMap>(&fs::read(json).map_err(|e| e.to_string())?).map_err(|e| format!("{}.json: {e}", font.name))?;
+        fonts.push((font, image, map));
+    }
+    replace_fonts(data, &fonts)?;
+    Ok(fonts.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::form::builder::{put, words, FormBuilder};
+
+    /// FORM with a FONT chunk holding a single entry, `entry` makes it for the offset it is written at.
+    fn font_form(entry: impl FnOnce(usize) -> Vec<u8>) -> Vec<u8> {
+        let mut builder = FormBuilder::new(17, &["fnt_test", "Arial"]);
+        let names = [builder.string(0), builder.string(1)];
+        builder.chunk(b"FONT", |d| {
+            let ptr = d.len() + 8;
+            put(d, &[1, ptr as u32]);
+            let mut bytes = entry(ptr);
+            put_u32(&mut bytes, 0, names[0]);
+            put_u32(&mut bytes, 4, names[1]);
+            d.extend(bytes);
+        });
+        builder.build()
+    }
+
+    fn glyph_map(character: &str, x: u16, kerning: &[(&str, i16)]) -> GlyphMap {
+        GlyphMap {
+            character: character.to_string(),
+            x,
+            y: 2,
+            width: 6,
+            height: 9,
+            shift: 7,
+            offset: -1,
+            kerning: kerning.iter().map(|&(c, amount)| (c.to_string(), amount)).collect(),
+        }
+    }
+
+    #[test]
+    fn map_round_trip() {
+        let map = FontMap {
+            name: "fnt_test".to_string(),
+            size: 12.5,
+            bold: true,
+            italic: false,
+            glyphs: vec![glyph_map("A", 0, &[]), glyph_map("\u{44f}", 8, &[("A", -2)]), glyph_map("\"", 16, &[])],
+        };
+        let json = serde_json::to_string_pretty(&map).unwrap();
+        let glyphs = serde_json::from_str::<FontMap>(&json).unwrap().glyphs().unwrap();
+        assert_eq!(glyphs.iter().map(|g| g.character).collect::<Vec<u16>>(), [65, 0x44f, 34]);
+        assert_eq!(glyphs[1].kerning, [(65, -2)]);
+        let font = Font { name: map.name.clone(), size: map.size, bold: true, glyphs, ..Default::default() };
+        assert_eq!(serde_json::to_string_pretty(&FontMap::new(&font)).unwrap(), json);
+
+        // Kerning is optional
+        let map = serde_json::from_str::<FontMap>(r#"{"name": "f", "size": 8, "bold": false, "italic": false,
+            "glyphs": [{"character": "x", "x": 0, "y": 0, "width": 1, "height": 1, "shift": 1, "offset": 0}]}"#).unwrap();
+        assert!(map.glyphs().unwrap()[0].kerning.is_empty());
+        // Characters must be single and fit in 16 bits
+        for character in ["", "ab", "\u{1f600}"] {
+            let map = FontMap { name: "f".to_string(), size: 8., bold: false, italic: false, glyphs: vec![glyph_map(character, 0, &[])] };
+            assert!(map.glyphs().is_err(), "{character:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn entry_layout() {
+        // 12 point font with one glyph for 'A'
+        let data = font_form(|ptr| {
+            let mut entry = words(&[0, 0, 12, 0, 0]);
+            entry.extend(32u16.to_le_bytes());
+            entry.extend([0, 1]);
+            entry.extend(words(&[127, 0x1234, 1f32.to_bits(), 1f32.to_bits(), 1, ptr as u32 + 48]));
+            entry.extend([65, 0, 0, 0, 0, 0, 5, 0, 8, 0, 6, 0, 0, 0, 0, 0]);
+            entry
+        });
+        let form = Form::new(&data).unwrap();
+        let font = &parse_fonts(&form)[0];
+        assert_eq!((font.name.as_str(), font.size, font.range_start, font.range_end, font.tpag), ("fnt_test", 12., 32, 127, 0x1234));
+        assert_eq!(font.glyphs.len(), 1);
+
+        let map = FontMap {
+            name: font.name.clone(),
+            size: 14.5,
+            bold: true,
+            italic: true,
+            glyphs: vec![glyph_map("B", 0, &[]), glyph_map("a", 8, &[("B", -1), ("a", 2)])],
+        };
+        let rebuilt = font_form(|ptr| font_entry(&form, font, &map, 0x40, ptr).unwrap());
+        let form = Form::new(&rebuilt).unwrap();
+        let new = &parse_fonts(&form)[0];
+        assert_eq!((new.name.as_str(), new.display_name.as_str()), ("fnt_test", "Arial"));
+        assert_eq!((new.size, new.bold, new.italic, new.tpag), (14.5, true, true, 0x40));
+        // The range covers the new glyphs, charset, antialiasing and scale are kept
+        assert_eq!((new.range_start, new.range_end, new.charset, new.antialias, new.scale), (66, 97, 0, 1, [1., 1.]));
+        assert_eq!(new.glyph_list, new.ptr + 40);
+        let expected = map.glyphs().unwrap();
+        assert_eq!(new.glyphs.len(), expected.len());
+        for (a, b) in new.glyphs.iter().zip(&expected) {
+            assert_eq!((a.character, a.x, a.y, a.width, a.height, a.shift, a.offset), (b.character, b.x, b.y, b.width, b.height, b.shift, b.offset));
+            assert_eq!(a.kerning, b.kerning);
+        }
+    }
+}
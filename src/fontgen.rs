@@ -0,0 +1,406 @@
+use std::{collections::{BTreeSet, HashMap}, fs, path::{Path, PathBuf}};
+use fontdue::FontSettings;
+use hudhook::imgui;
+use rfd::FileDialog;
+
+use crate::arena::Arena;
+use crate::atlas::pack;
+use crate::font::{font_entry, parse_fonts, replace_fonts, Font, FontMap, GlyphMap};
+use crate::form::Form;
+use crate::sprite::page_item;
+use crate::strings::unescape;
+use crate::texture::{decode_texture, encode_texture, heap_pointers, Image, TexturePanel};
+
+/// Kerning pairs are only looked up for small sets, CJK sets would need millions of lookups.
+const MAX_KERNING_SET: usize = 512;
+/// Widest page the offline generator makes before adding rows.
+const MAX_WIDTH: u32 = 2048;
+
+/// Named character sets that can be mixed with hex ranges in a charset.
+const PRESETS: [(&str, u32, u32); 6] = [
+    ("ascii", 0x20, 0x7e),
+    ("latin1", 0xa0, 0xff),
+    ("latin-ext", 0x100, 0x17f),
+    ("greek", 0x370, 0x3ff),
+    ("cyrillic", 0x400, 0x4ff),
+    ("kana", 0x3040, 0x30ff),
+];
+
+/// Characters of a charset like `ascii,cyrillic,2013-2026,20AC`, presets and hex ranges or code points.
+pub fn parse_charset(spec: &str) -> Result<Vec<char>, String> {
+    let mut chars = BTreeSet::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (first, last) = match PRESETS.iter().find(|(name, _, _)| name.eq_ignore_ascii_case(part)) {
+            Some(&(_, first, last)) => (first, last),
+            None => {
+                let code = |s: &str| u32::from_str_radix(s.trim().trim_start_matches("U+").trim_start_matches("0x"), 16).map_err(|_| format!("\"{part}\" is not a preset or a hex range"));
+                match part.split_once('-') {
+                    Some((first, last)) => (code(first)?, code(last)?),
+                    None => (code(part)?, code(part)?),
+                }
+            }
+        };
+        chars.extend((first..=last).filter_map(char::from_u32));
+    }
+    Ok(chars.into_iter().collect())
+}
+
+/// Characters drawn by `strings` with a space, without control characters and duplicates.
+pub fn string_characters<'a>(strings: impl IntoIterator<Item = &'a str>) -> Vec<char> {
+    let mut chars = BTreeSet::from([' ']);
+    for string in strings {
+        chars.extend(string.chars().filter(|c| !c.is_control()));
+    }
+    chars.into_iter().collect()
+}
+
+/// Characters of a strings file saved by the overlay "Export" button.
+pub fn file_characters(path: &Path) -> Result<Vec<char>, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let indexed = text.starts_with("#indexed");
+    let lines = text.split("\r\n").skip(indexed as usize).map(|line| {
+        let line = if indexed { line.split_once('\t').map_or(line, |(_, l)| l) } else { line };
+        String::from_utf8_lossy(&unescape(line, true)).to_string()
+    }).collect::<Vec<String>>();
+    Ok(string_characters(lines.iter().map(|l| l.as_str())))
+}
+
+/// Rasterizes `chars` of a TrueType/OpenType font at `size` points (96 DPI, like the GameMaker IDE).
+/// Glyphs are white with the coverage as alpha, packed in rows no wider than `width`. Every glyph is a
+/// cell as tall as the line with the bitmap on the baseline, the way GameMaker lays them out.
+pub fn rasterize(file: &[u8], size: f32, chars: &[char], width: Option<u32>) -> Result<(Image, Vec<GlyphMap>), String> {
+    let font = fontdue::Font::from_bytes(file, FontSettings::default())?;
+    let px = size * 96. / 72.;
+    let line = font.horizontal_line_metrics(px).ok_or("The font has no horizontal metrics")?;
+    let ascent = line.ascent.round() as i32;
+    let mut missing = String::new();
+    let mut cells = Vec::new();
+    for &c in chars {
+        if c != ' ' && font.lookup_glyph_index(c) == 0 {
+            missing.push(c);
+            continue;
+        }
+        let (metrics, coverage) = font.rasterize(c, px);
+        let top = (ascent - metrics.ymin - metrics.height as i32).max(0) as u32;
+        let mut cell = Image::new(metrics.width as u32, ((line.ascent - line.descent).ceil() as u32).max(top + metrics.height as u32));
+        for (i, &alpha) in coverage.iter().enumerate() {
+            let (x, y) = (i as u32 % cell.width, top + i as u32 / cell.width);
+            cell.pixels[((y * cell.width + x) * 4) as usize..][..4].copy_from_slice(&[255, 255, 255, alpha]);
+        }
+        cells.push((c, metrics.xmin, metrics.advance_width.round() as i16, cell));
+    }
+    if !missing.is_empty() {
+        println!("The font has no glyphs for \"{missing}\"");
+    }
+    if cells.is_empty() {
+        return Err("None of the characters are in the font".to_string());
+    }
+    let sizes = cells.iter().map(|(_, _, _, cell)| (cell.width, cell.height)).collect::<Vec<(u32, u32)>>();
+    let width = width.unwrap_or_else(|| {
+        let area = sizes.iter().map(|&(w, h)| (w + 2) * (h + 2)).sum::<u32>();
+        // A glyph wider than the maximum still gets its row
+        ((area as f32).sqrt() as u32).next_power_of_two().min(MAX_WIDTH).max(sizes.iter().map(|s| s.0).max().unwrap_or(1))
+    });
+    let placements = pack(&sizes, (width, u32::MAX))?;
+    let height = placements.iter().zip(&sizes).map(|(p, s)| p.y + s.1).max().unwrap_or(1);
+    let mut image = Image::new(width, height);
+    let mut glyphs = Vec::new();
+    let kerning = chars.len() <= MAX_KERNING_SET;
+    for ((c, offset, shift, cell), placement) in cells.iter().zip(&placements) {
+        image.blit(cell, (0, 0), (cell.width, cell.height), (placement.x, placement.y));
+        glyphs.push(GlyphMap {
+            character: c.to_string(),
+            x: placement.x as u16,
+            y: placement.y as u16,
+            width: cell.width as u16,
+            height: cell.height as u16,
+            shift: *shift,
+            offset: *offset as i16,
+            // The pairs are kept on the glyph drawn second
+            kerning: if kerning {
+                cells.iter().filter_map(|(left, _, _, _)| {
+                    let amount = font.horizontal_kern(*left, *c, px)?.round() as i16;
+                    (amount != 0).then(|| (left.to_string(), amount))
+                }).collect()
+            } else {
+                Vec::new()
+            },
+        });
+    }
+    Ok((image, glyphs))
+}
+
+/// Replaces the font `name` of a data.win loaded in `data` with glyphs generated from a font file.
+pub fn generate_font(data: &mut Vec<u8>, name: &str, file: &[u8], size: f32, chars: &[char]) -> Result<usize, String> {
+    let form = Form::new(data).ok_or("Not a GameMaker data file")?;
+    let font = parse_fonts(&form).into_iter().find(|f| f.name == name).ok_or(format!("There is no font named {name}"))?;
+    let (image, glyphs) = rasterize(file, size, chars, None)?;
+    let count = glyphs.len();
+    let map = FontMap { name: font.name.clone(), size, bold: font.bold, italic: font.italic, glyphs };
+    replace_fonts(data, &[(font, image, map)])?;
+    Ok(count)
+}
+
+/// A font pointing at a generated entry.
+struct Replacement {
+    addr: usize, // Arena buffer
+    slot: usize, // FONT list slot
+    heap: Vec<usize>, // Runner copies of the entry pointer
+    original: Font,
+}
+
+/// Generates fonts from TTF/OTF files and replaces game fonts in the running game.
+///
+/// Live, the glyphs are drawn into the region the font already has on its texture page, so they must fit
+/// in it: use a smaller size or charset, or the `generate-font` command which repacks the pages.
+/// The runner reads fonts when it loads them, fonts it already set up keep their old glyphs.
+#[derive(Default)]
+pub struct FontGenerator {
+    file: Option<PathBuf>,
+    selected: usize,
+    size: f32,
+    charset: String,
+    from_strings: bool,
+    replaced: HashMap<usize, Replacement>, // By font index
+}
+
+impl FontGenerator {
+    pub fn load(&mut self) {
+        self.size = 12.;
+        self.charset = "ascii".to_string();
+    }
+
+    unsafe fn replace(&mut self, form: &Form, arena: &mut Arena, textures: &mut TexturePanel, font: &Font, chars: &[char]) -> Result<Vec<GlyphMap>, String> {
+        let file = fs::read(self.file.as_ref().ok_or("Pick a TTF/OTF file first")?).map_err(|e| e.to_string())?;
+        let item = page_item(form, font.tpag).ok_or(format!("Font {} has an invalid TPAG entry", font.name))?;
+        let [x, y, width, height] = item.source.map(u32::from);
+        let (image, glyphs) = rasterize(&file, self.size, chars, Some(width))?;
+        if image.height > height {
+            return Err(format!("The glyphs need {}x{} pixels, the font region is {width}x{height}", image.width, image.height));
+        }
+        let page = *textures.pages.iter().find(|p| p.index as i16 == item.page).ok_or(format!("Texture page {} is not embedded", item.page))?;
+        let mut pixels = decode_texture(textures.page_data(form, arena, &page).unwrap())?;
+        pixels.blit(&Image::new(width, height), (0, 0), (width, height), (x, y));
+        pixels.blit(&image, (0, 0), (image.width, image.height), (x, y));
+        textures.set_page(form, arena, page, encode_texture(&pixels, page.format)?);
+
+        let index = self.selected;
+        let original = self.replaced.get(&index).map_or(font, |r| &r.original).clone();
+        self.restore(form, arena, index);
+        let map = FontMap { name: font.name.clone(), size: self.size, bold: font.bold, italic: font.italic, glyphs };
+        let length = font_entry(form, &original, &map, original.tpag, 0)?.len();
+        let addr = arena.alloc(vec![0; length]);
+        let entry = font_entry(form, &original, &map, original.tpag, addr.wrapping_sub(form.base))?;
+        arena.get_mut(addr).unwrap().copy_from_slice(&entry);
+        let font_list = form.chunk(b"FONT").map(|c| c.offset).ok_or("The data file has no FONT chunk")?;
+        let slot = form.pointer_list(font_list).unwrap_or_default().iter().position(|&f| f == original.ptr).ok_or(format!("Font {} is not in the FONT list", original.name))?;
+        let slot = form.addr(font_list + 4 + slot * 4);
+        arena.set(slot, addr.wrapping_sub(form.base) as u32, Some(addr));
+        let heap = heap_pointers(form.addr(original.ptr), &[form.addr(original.tpag), form.addr(original.glyph_list)]);
+        for &slot in &heap {
+            println!("Found a pointer at 0x{slot:x} for font {}", original.name);
+            arena.set(slot, addr as u32, Some(addr));
+        }
+        self.replaced.insert(index, Replacement { addr, slot, heap, original });
+        Ok(map.glyphs)
+    }
+
+    unsafe fn restore(&mut self, form: &Form, arena: &mut Arena, index: usize) -> Option<Font> {
+        let replacement = self.replaced.remove(&index)?;
+        arena.set(replacement.slot, replacement.original.ptr as u32, None);
+        for slot in replacement.heap {
+            arena.set(slot, form.addr(replacement.original.ptr) as u32, None);
+        }
+        debug_assert_eq!(arena.references(replacement.addr), 0);
+        Some(replacement.original)
+    }
+
+    /// Draws the region of `font` back from its page in data.win, fonts generated on the same page stay.
+    /// The page is restored once nothing else on it differs.
+    unsafe fn restore_region(form: &Form, arena: &mut Arena, textures: &mut TexturePanel, font: &Font) -> Result<(), String> {
+        let item = page_item(form, font.tpag).ok_or(format!("Font {} has an invalid TPAG entry", font.name))?;
+        let Some(page) = textures.pages.iter().find(|p| p.index as i16 == item.page).copied() else {
+            return Ok(());
+        };
+        let original = decode_texture(form.bytes(page.data, page.size).ok_or(format!("Texture page {} is out of bounds", page.index))?)?;
+        let mut pixels = decode_texture(textures.page_data(form, arena, &page).unwrap())?;
+        let [x, y, width, height] = item.source.map(u32::from);
+        pixels.blit(&original, (x, y), (width, height), (x, y));
+        if pixels.pixels == original.pixels {
+            textures.restore(form, arena, page);
+        } else {
+            textures.set_page(form, arena, page, encode_texture(&pixels, page.format)?);
+        }
+        Ok(())
+    }
+
+    /// `strings` gives the translated strings when the characters come from them.
+    pub fn render(&mut self, ui: &imgui::Ui, form: Option<&Form>, arena: &mut Arena, textures: &mut TexturePanel, fonts: &mut [Font], strings: impl Fn() -> Vec<String>) {
+        let Some(form) = form else {
+            return;
+        };
+        if fonts.is_empty() {
+            ui.text_disabled("No fonts found");
+            return;
+        }
+        if ui.button("Pick TTF/OTF") {
+            self.file = FileDialog::new().add_filter("Font Files", &["ttf", "otf"]).pick_file().or(self.file.take());
+        }
+        ui.same_line();
+        ui.text(self.file.as_ref().map_or("No font file".to_string(), |f| f.display().to_string()));
+        let names = fonts.iter().enumerate()
+            .map(|(i, f)| format!("{}{}", f.name, if self.replaced.contains_key(&i) { " (replaced)" } else { "" }))
+            .collect::<Vec<String>>();
+        ui.set_next_item_width(150.);
+        ui.combo_simple_string("Font##generator", &mut self.selected, &names);
+        ui.same_line();
+        ui.set_next_item_width(80.);
+        ui.input_float("Size##generator", &mut self.size).build();
+        ui.checkbox("Translated strings only", &mut self.from_strings);
+        if !self.from_strings {
+            ui.same_line();
+            ui.set_next_item_width(200.);
+            ui.input_text("Charset", &mut self.charset).build();
+        }
+        if ui.button("Generate Live") {
+            let chars = if self.from_strings { Ok(string_characters(strings().iter().map(|s| s.as_str()))) } else { parse_charset(&self.charset) };
+            let font = fonts[self.selected].clone();
+            match chars.and_then(|chars| unsafe { self.replace(form, arena, textures, &font, &chars) }) {
+                Ok(glyphs) => {
+                    let map = FontMap { name: font.name.clone(), size: self.size, bold: font.bold, italic: font.italic, glyphs };
+                    fonts[self.selected].glyphs = map.glyphs().unwrap_or_default();
+                    println!("========== Generated font {} with {} glyphs ==========", font.name, map.glyphs.len());
+                }
+                Err(e) => println!("Could not generate font {}: {e}", font.name),
+            }
+        }
+        ui.same_line();
+        if ui.button("Restore OG Font") {
+            match unsafe { self.restore(form, arena, self.selected) } {
+                Some(font) => {
+                    if let Err(e) = unsafe { Self::restore_region(form, arena, textures, &font) } {
+                        println!("Could not restore the glyphs of font {}: {e}", font.name);
+                    }
+                    println!("========== Restored font {} ==========", font.name);
+                    fonts[self.selected] = font;
+                }
+                None => println!("========== The font has not been modified =========="),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::form::builder::{put, FormBuilder};
+    use crate::form::put_u32;
+    use crate::sprite::PageCache;
+    use crate::texture::{encode_png, parse_textures};
+
+    /// A TTF file of the system, the tests that rasterize are skipped without one.
+    fn system_font() -> Option<Vec<u8>> {
+        ["C:\\Windows\\Fonts\\arial.ttf", "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf", "/Library/Fonts/Arial.ttf"]
+            .iter().find_map(|path| fs::read(path).ok())
+    }
+
+    #[test]
+    fn charsets() {
+        let ascii = parse_charset("ascii").unwrap();
+        assert_eq!((ascii.len(), ascii[0], ascii[94]), (95, ' ', '~'));
+        assert_eq!(parse_charset("U+0416, 41-43,20AC,0x42").unwrap(), ['A', 'B', 'C', '\u{416}', '€']);
+        // Overlaps are merged, presets ignore case and surrogates are skipped
+        assert_eq!(parse_charset("ASCII,41-5a").unwrap(), ascii);
+        assert_eq!(parse_charset("Kana,,").unwrap().len(), 0xc0);
+        assert_eq!(parse_charset("d7ff-e000").unwrap(), ['\u{d7ff}', '\u{e000}']);
+        assert!(parse_charset("").unwrap().is_empty());
+        for spec in ["runic", "41-zz", "41-", "ascii,g"] {
+            assert!(parse_charset(spec).is_err(), "{spec} was accepted");
+        }
+    }
+
+    #[test]
+    fn glyphs_inside_atlas() {
+        let Some(file) = system_font() else {
+            return;
+        };
+        let chars = parse_charset("ascii,cyrillic").unwrap();
+        for width in [None, Some(64)] {
+            let (image, glyphs) = rasterize(&file, 12., &chars, width).unwrap();
+            assert!(glyphs.len() > 95 && glyphs.len() <= chars.len());
+            if let Some(width) = width {
+                assert_eq!(image.width, width);
+            }
+            for (i, a) in glyphs.iter().enumerate() {
+                assert!(a.x as u32 + a.width as u32 <= image.width && a.y as u32 + a.height as u32 <= image.height, "{} is outside", a.character);
+                for b in &glyphs[i + 1..] {
+                    let apart = a.x + a.width <= b.x || b.x + b.width <= a.x || a.y + a.height <= b.y || b.y + b.height <= a.y;
+                    assert!(apart, "{} overlaps {}", a.character, b.character);
+                }
+            }
+        }
+        assert!(rasterize(&file, 12., &['\u{10fffd}'], None).is_err());
+    }
+
+    /// FORM with the 12 point font fnt_a using the 8x8 region at the top left of a 16x16 page.
+    fn font_form() -> Vec<u8> {
+        let mut builder = FormBuilder::new(17, &["fnt_a"]);
+        let name = builder.string(0);
+        let mut tpag = 0;
+        builder.chunk(b"TPAG", |d| {
+            tpag = d.len() + 8;
+            put(d, &[1, tpag as u32]);
+            d.extend([0, 0, 0, 0, 8, 0, 8, 0, 0, 0, 0, 0, 8, 0, 8, 0, 8, 0, 8, 0, 0, 0, 0, 0]);
+        });
+        builder.chunk(b"FONT", |d| {
+            let ptr = d.len() + 8;
+            put(d, &[1, ptr as u32, name, name, 12, 0, 0]);
+            d.extend([32, 0, 0, 1]);
+            put(d, &[127, tpag as u32, 1f32.to_bits(), 1f32.to_bits(), 0]);
+        });
+        let png = encode_png(&Image { width: 16, height: 16, pixels: vec![255; 16 * 16 * 4] }).unwrap();
+        builder.chunk(b"TXTR", |d| {
+            let entry = d.len() + 8;
+            put(d, &[1, entry as u32, 1, 0, 0]);
+            while d.len() % 128 != 0 {
+                d.push(0);
+            }
+            let blob = d.len() as u32;
+            put_u32(d, entry + 8, blob);
+            d.extend(png);
+        });
+        builder.build()
+    }
+
+    #[test]
+    fn generate_round_trip() {
+        let Some(file) = system_font() else {
+            return;
+        };
+        let chars = parse_charset("ascii").unwrap();
+        let mut data = font_form();
+        assert_eq!(generate_font(&mut data, "fnt_a", &file, 12., &chars), Ok(95));
+        assert!(generate_font(&mut data, "fnt_b", &file, 12., &chars).is_err());
+
+        let form = Form::new(&data).unwrap();
+        let font = &parse_fonts(&form)[0];
+        let (image, glyphs) = rasterize(&file, 12., &chars, None).unwrap();
+        assert_eq!((font.name.as_str(), font.size, font.range_start, font.range_end), ("fnt_a", 12., 32, 126));
+        let expected = FontMap { name: font.name.clone(), size: 12., bold: false, italic: false, glyphs }.glyphs().unwrap();
+        assert_eq!(font.glyphs.len(), expected.len());
+        for (a, b) in font.glyphs.iter().zip(&expected) {
+            assert_eq!((a.character, a.x, a.y, a.width, a.height, a.shift, a.offset), (b.character, b.x, b.y, b.width, b.height, b.shift, b.offset));
+            assert_eq!(a.kerning, b.kerning);
+        }
+
+        // The glyph image is on the page the new TPAG entry points at
+        let item = page_item(&form, font.tpag).unwrap();
+        let [x, y, width, height] = item.source.map(u32::from);
+        assert_eq!((width, height), (image.width, image.height));
+        let pages = parse_textures(&form);
+        let mut cache = PageCache::new(&form, &pages);
+        let mut region = Image::new(width, height);
+        region.blit(cache.page(item.page).unwrap(), (x, y), (width, height), (0, 0));
+        assert!(region.pixels == image.pixels);
+    }
+}
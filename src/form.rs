@@ -0,0 +1,349 @@
+use core::slice;
+use mmap_rs::MemoryAreas;
+
+/// A GameMaker `FORM` container (data.win or an audiogroup), either mapped in the game memory or loaded from disk.
+/// Every pointer stored inside the container is an offset from the start of the `FORM` header.
+#[derive(Clone, Copy)]
+pub struct Form<'a> {
+    pub base: usize,
+    pub data: &'a [u8],
+}
+
+#[derive(Clone, Copy)]
+pub struct Chunk<'a> {
+    pub name: [u8; 4],
+    pub offset: usize, // Offset of the chunk body (after the name and size)
+    pub data: &'a [u8],
+}
+
+impl<'a> Form<'a> {
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        if data.len() < 8 || &data[0..4] != b"FORM" {
+            return None;
+        }
+        let size = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        Some(Self {
+            base: data.as_ptr().addr(),
+            data: &data[..(size + 8).min(data.len())],
+        })
+    }
+
+    pub fn chunks(&self) -> Vec<Chunk<'a>> {
+        let mut chunks = Vec::new();
+        let mut offset = 8;
+        while let (Some(name), Some(size)) = (self.bytes(offset, 4), self.u32(offset + 4)) {
+            let Some(data) = self.bytes(offset + 8, size as usize) else {
+                break;
+            };
+            chunks.push(Chunk {
+                name: name.try_into().unwrap(),
+                offset: offset + 8,
+                data,
+            });
+            offset += 8 + size as usize;
+        }
+        chunks
+    }
+
+    pub fn chunk(&self, name: &[u8; 4]) -> Option<Chunk<'a>> {
+        self.chunks().into_iter().find(|c| &c.name == name)
+    }
+
+    pub fn bytes(&self, offset: usize, size: usize) -> Option<&'a [u8]> {
+        self.data.get(offset..offset.checked_add(size)?)
+    }
+
+    pub fn u8(&self, offset: usize) -> Option<u8> {
+        self.data.get(offset).copied()
+    }
+
+    pub fn u16(&self, offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(offset, 2)?.try_into().unwrap()))
+    }
+
+    pub fn i16(&self, offset: usize) -> Option<i16> {
+        Some(self.u16(offset)? as i16)
+    }
+
+    pub fn u32(&self, offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+
+    pub fn i32(&self, offset: usize) -> Option<i32> {
+        Some(self.u32(offset)? as i32)
+    }
+
+    pub fn f32(&self, offset: usize) -> Option<f32> {
+        Some(f32::from_bits(self.u32(offset)?))
+    }
+
+    /// Reads a `count` followed by `count` pointers.
+    pub fn pointer_list(&self, offset: usize) -> Option<Vec<usize>> {
+        let count = self.u32(offset)? as usize;
+        self.bytes(offset + 4, count.checked_mul(4)?)?;
+        Some((0..count).map(|i| self.u32(offset + 4 + i * 4).unwrap() as usize).collect())
+    }
+
+    /// String references point to the characters, the length is stored in the 4 bytes before them.
+    pub fn string(&self, ptr: usize) -> Option<String> {
+        let size = self.u32(ptr.checked_sub(4)?)? as usize;
+        Some(String::from_utf8_lossy(self.bytes(ptr, size)?).to_string())
+    }
+
+    /// Address of a container offset in the memory it was read from.
+    pub fn addr(&self, offset: usize) -> usize {
+        self.base + offset
+    }
+}
+
+impl<'a> Chunk<'a> {
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.name).to_string()
+    }
+
+    pub fn end(&self) -> usize {
+        self.offset + self.data.len()
+    }
+
+    pub fn contains(&self, offset: usize, size: usize) -> bool {
+        offset >= self.offset && offset.saturating_add(size) <= self.end()
+    }
+}
+
+pub fn put_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+// Chunks made of a pointer list and the entries it points at. The runner only reaches their content
+// through the pointers, bytes added after the last entry are never read as part of the chunk.
+const APPENDABLE: [&[u8; 4]; 3] = [b"AUDO", b"TXTR", b"STRG"];
+
+/// Appends `bytes` to the last chunk of a data file loaded in `data`, padded to 16 bytes. Returns their offset.
+/// Every pointer in the file stays valid, which inserting into the chunk the bytes belong to (CODE, TPAG...)
+/// would not. Data files end with AUDO, the last chunk must be a pointer list for the added bytes to be ignored.
+pub fn append(data: &mut Vec<u8>, bytes: &[u8]) -> Result<usize, String> {
+    let form = Form::new(data).ok_or("Not a GameMaker data file")?;
+    let chunks = form.chunks();
+    let last = chunks.last().ok_or("The data file has no chunks")?;
+    if !APPENDABLE.contains(&&last.name) {
+        return Err(format!("The data file ends with {}, only AUDO, TXTR or STRG can be grown safely", last.name()));
+    }
+    let (last, size) = (last.offset, last.data.len());
+    let end = last + size;
+    if end != data.len() {
+        return Err("The data file has trailing bytes after the last chunk".to_string());
+    }
+    let padded = bytes.len().next_multiple_of(16);
+    put_u32(data, last - 4, (size + padded) as u32);
+    put_u32(data, 4, (end + padded - 8) as u32);
+    data.extend_from_slice(bytes);
+    data.resize(end + padded, 0);
+    Ok(end)
+}
+
+impl Form<'static> {
+    /// # Safety
+    /// `base` must point to a readable `FORM` header followed by its whole body.
+    pub unsafe fn from_ptr(base: usize) -> Option<Self> {
+        if slice::from_raw_parts(base as *const u8, 4) != b"FORM" {
+            return None;
+        }
+        let size = ((base + 4) as *const u32).read_unaligned() as usize;
+        Self::new(slice::from_raw_parts(base as *const u8, size + 8))
+    }
+
+    /// Walks back from the address of a known chunk header until the `FORM` that contains it is found.
+    /// # Safety
+    /// `chunk` must point inside the game memory.
+    pub unsafe fn locate(chunk: usize) -> Option<Self> {
+        let area = MemoryAreas::query(chunk).ok()??;
+        let mut base = chunk;
+        while base > area.allocation_base() {
+            base -= 1;
+            if slice::from_raw_parts(base as *const u8, 4) != b"FORM" {
+                continue;
+            }
+            let size = ((base + 4) as *const u32).read_unaligned() as usize;
+            if base.saturating_add(size).saturating_add(8) < chunk + 8 {
+                continue;
+            }
+            // Make sure that the chunk list of this candidate lands exactly on the known chunk
+            let mut offset = base + 8;
+            while offset < chunk {
+                match offset.checked_add(((offset + 4) as *const u32).read_unaligned() as usize).and_then(|o| o.checked_add(8)) {
+                    Some(next) => offset = next,
+                    None => break,
+                }
+            }
+            if offset == chunk {
+                return Self::from_ptr(base);
+            }
+        }
+        None
+    }
+}
+
+/// Small data files for tests, built one chunk at a time with the bytecode 15 and newer layouts.
+#[cfg(test)]
+pub mod builder {
+    use super::put_u32;
+
+    pub fn word(opcode: u8, type2: u8, type1: u8, value: u16) -> u32 {
+        (opcode as u32) << 24 | (type2 as u32) << 20 | (type1 as u32) << 16 | value as u32
+    }
+
+    pub fn words(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    pub fn put(data: &mut Vec<u8>, values: &[u32]) {
+        data.extend(words(values));
+    }
+
+    pub struct FormBuilder {
+        pub data: Vec<u8>,
+        strings: Vec<u32>, // Offsets of the characters
+    }
+
+    impl FormBuilder {
+        /// A `FORM` with a GEN8 chunk for bytecode `version` and a STRG chunk holding `strings`.
+        pub fn new(version: u8, strings: &[&str]) -> Self {
+            let mut builder = Self { data: b"FORM\0\0\0\0".to_vec(), strings: Vec::new() };
+            builder.chunk(b"GEN8", |d| d.extend([0, version, 0, 0]));
+            let mut offsets = Vec::new();
+            builder.chunk(b"STRG", |d| {
+                put(d, &[strings.len() as u32]);
+                let table = d.len();
+                put(d, &vec![0; strings.len()]);
+                for (i, string) in strings.iter().enumerate() {
+                    let ptr = d.len() as u32;
+                    put_u32(d, table + i * 4, ptr);
+                    put(d, &[string.len() as u32]);
+                    offsets.push(d.len() as u32);
+                    d.extend(string.as_bytes());
+                    d.push(0);
+                }
+            });
+            builder.strings = offsets;
+            builder
+        }
+
+        /// Offset of the characters of a string, what string references hold.
+        pub fn string(&self, index: usize) -> u32 {
+            self.strings[index]
+        }
+
+        /// Appends a chunk, `body` writes its content at the end of the data. Returns the offset of the content.
+        pub fn chunk(&mut self, name: &[u8; 4], body: impl FnOnce(&mut Vec<u8>)) -> usize {
+            self.data.extend(name);
+            let size = self.data.len();
+            put(&mut self.data, &[0]);
+            body(&mut self.data);
+            let length = (self.data.len() - size - 4) as u32;
+            put_u32(&mut self.data, size, length);
+            size + 4
+        }
+
+        /// Appends a CODE chunk with an entry per name (a string index) and bytecode. Returns the offset of each bytecode.
+        pub fn code(&mut self, entries: &[(usize, &[u8])]) -> Vec<usize> {
+            let names = entries.iter().map(|&(name, _)| self.string(name)).collect::<Vec<u32>>();
+            let mut starts = Vec::new();
+            self.chunk(b"CODE", |d| {
+                let table = d.len() + 4;
+                put(d, &[entries.len() as u32]);
+                put(d, &vec![0; entries.len()]);
+                for (_, bytecode) in entries {
+                    starts.push(d.len());
+                    d.extend(*bytecode);
+                }
+                for (i, (name, (_, bytecode))) in names.iter().zip(entries).enumerate() {
+                    let entry = d.len();
+                    put_u32(d, table + i * 4, entry as u32);
+                    put(d, &[*name, bytecode.len() as u32, 0, (starts[i] as i32 - entry as i32 - 12) as u32, 0]);
+                }
+            });
+            starts
+        }
+
+        /// Appends a VARI chunk of self variables, each a name and the addresses of the instructions using it.
+        pub fn variables(&mut self, variables: &[(usize, &[usize])]) {
+            let names = variables.iter().map(|&(name, _)| self.string(name)).collect::<Vec<u32>>();
+            self.chunk(b"VARI", |d| {
+                put(d, &[variables.len() as u32, variables.len() as u32, 0]);
+                for (i, (name, (_, addresses))) in names.iter().zip(variables).enumerate() {
+                    put(d, &[*name, -1i32 as u32, i as u32, addresses.len() as u32, addresses.first().map_or(0, |&a| a as u32)]);
+                }
+            });
+            for (_, addresses) in variables {
+                self.link(addresses);
+            }
+        }
+
+        /// Appends a FUNC chunk, each function a name and the addresses of the calls to it.
+        pub fn functions(&mut self, functions: &[(usize, &[usize])]) {
+            let names = functions.iter().map(|&(name, _)| self.string(name)).collect::<Vec<u32>>();
+            self.chunk(b"FUNC", |d| {
+                put(d, &[functions.len() as u32]);
+                for (name, (_, addresses)) in names.iter().zip(functions) {
+                    put(d, &[*name, addresses.len() as u32, addresses.first().map_or(0, |&a| a as u32)]);
+                }
+            });
+            for (_, addresses) in functions {
+                self.link(addresses);
+            }
+        }
+
+        /// Stores the distance to the next occurrence in the operand of each reference, keeping its kind bits.
+        fn link(&mut self, addresses: &[usize]) {
+            for (i, &address) in addresses.iter().enumerate() {
+                let next = addresses.get(i + 1).map_or(0, |&n| (n - address) as u32);
+                let operand = u32::from_le_bytes(self.data[address + 4..address + 8].try_into().unwrap());
+                put_u32(&mut self.data, address + 4, operand & 0xf8000000 | next);
+            }
+        }
+
+        pub fn build(mut self) -> Vec<u8> {
+            let size = self.data.len() as u32 - 8;
+            put_u32(&mut self.data, 4, size);
+            self.data
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::FormBuilder;
+
+    #[test]
+    fn append_to_last_chunk() {
+        let mut builder = FormBuilder::new(17, &[]);
+        let audo = builder.chunk(b"AUDO", |d| d.extend([0; 4]));
+        let mut data = builder.build();
+        let end = data.len();
+        assert_eq!(append(&mut data, b"abc"), Ok(end));
+        assert_eq!(append(&mut data, &[1; 16]), Ok(end + 16));
+        assert_eq!(&data[end..end + 4], b"abc\0");
+        let form = Form::new(&data).unwrap();
+        assert_eq!(form.data.len(), data.len());
+        let chunk = form.chunk(b"AUDO").unwrap();
+        assert_eq!((chunk.offset, chunk.end()), (audo, data.len()));
+    }
+
+    #[test]
+    fn append_refused() {
+        let mut builder = FormBuilder::new(17, &[]);
+        builder.chunk(b"CODE", |d| d.extend([0; 4]));
+        let mut data = builder.build();
+        assert!(append(&mut data, b"abc").is_err());
+        let mut builder = FormBuilder::new(17, &[]);
+        builder.chunk(b"AUDO", |d| d.extend([0; 4]));
+        let mut data = builder.build();
+        data.extend([0; 4]);
+        assert!(append(&mut data, b"abc").is_err());
+    }
+}
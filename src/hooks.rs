@@ -0,0 +1,494 @@
+use std::{collections::{HashMap, HashSet}, ffi::CStr, panic::{catch_unwind, AssertUnwindSafe}, sync::{atomic::{AtomicI32, Ordering}, Arc, Mutex, MutexGuard, PoisonError}};
+use hudhook::imgui;
+use retour::RawDetour;
+
+use crate::code::parse_functions;
+use crate::form::Form;
+use crate::sigscan::{readable_spans, Signature};
+use crate::variables::{format_rvalue, KIND_BOOL, KIND_INT32, KIND_INT64, KIND_REAL};
+use crate::watch::{is_executable, is_readable, WatchList};
+
+/// Value of the runner, 16 bytes on 32 bit builds.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RValue {
+    pub value: u64, // double, int32, int64 or pointer
+    pub flags: u32,
+    pub kind: u32,
+}
+
+/// Signature of the built-in functions of the runner:
+/// `void F(RValue& result, CInstance* self, CInstance* other, int argc, RValue* args)`.
+pub type Routine = unsafe extern "C" fn(result: *mut RValue, self_: usize, other: usize, argc: i32, args: *const RValue);
+
+/// A call going through a hook. Handlers run before the original can change the arguments, or set
+/// `skip` to return `result` without calling it. Handlers run after it can replace `result`.
+pub struct Call {
+    pub name: String,
+    pub self_: usize,
+    pub other: usize,
+    pub args: Vec<RValue>,
+    pub result: RValue,
+    pub skip: bool,
+}
+
+pub type Handler = Arc<dyn Fn(&mut Call) + Send + Sync>;
+
+struct Hook {
+    name: String,
+    address: usize,
+    slot: usize,
+    detour: RawDetour,
+    calls: u64,
+    log: bool,
+    before: Vec<Handler>,
+    after: Vec<Handler>,
+}
+
+/// State of an installed hook, for display.
+pub struct HookInfo {
+    pub name: String,
+    pub address: usize,
+    pub enabled: bool,
+    pub calls: u64,
+    pub log: bool,
+}
+
+/// A built-in of the runner function table.
+#[derive(Clone, Copy)]
+pub struct RuntimeFunction {
+    pub routine: usize,
+    pub arguments: i32, // -1 for variable arguments
+}
+
+/// Entries of the runner function table, they start with a 64 byte name or with a pointer to it
+/// depending on the runner version.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TableLayout {
+    Inline, // char name[64], TRoutine routine, int argc, int usage
+    Pointer, // const char* name, TRoutine routine, int argc, int usage
+}
+
+#[derive(Clone, Copy, Debug)]
+struct FunctionTable {
+    address: usize,
+    count: usize,
+    layout: TableLayout,
+}
+
+impl TableLayout {
+    fn stride(self) -> usize {
+        match self {
+            Self::Inline => 76,
+            Self::Pointer => 16,
+        }
+    }
+
+    fn routine(self) -> usize {
+        match self {
+            Self::Inline => 64,
+            Self::Pointer => 4,
+        }
+    }
+
+    /// The function at `entry`, `None` when it doesn't have a name and code.
+    fn read(self, entry: usize) -> Option<(String, RuntimeFunction)> {
+        if !is_readable(entry, self.stride()) {
+            return None;
+        }
+        let name = unsafe {
+            match self {
+                Self::Inline => {
+                    let bytes = core::slice::from_raw_parts(entry as *const u8, 64);
+                    bytes[..bytes.iter().position(|&c| c == 0)?].to_vec()
+                }
+                Self::Pointer => {
+                    let name = (entry as *const u32).read_unaligned() as usize;
+                    if !is_readable(name, 1) {
+                        return None;
+                    }
+                    CStr::from_ptr(name as *const i8).to_bytes().to_vec()
+                }
+            }
+        };
+        let routine = unsafe { ((entry + self.routine()) as *const u32).read_unaligned() } as usize;
+        let arguments = unsafe { ((entry + self.routine() + 4) as *const i32).read_unaligned() };
+        if name.is_empty() || !name.iter().all(|c| c.is_ascii_graphic()) || !is_executable(routine) || !(-1..256).contains(&arguments) {
+            return None;
+        }
+        Some((String::from_utf8_lossy(&name).to_string(), RuntimeFunction { routine, arguments }))
+    }
+}
+
+impl FunctionTable {
+    /// The table around the entry at `entry`.
+    fn around(entry: usize, layout: TableLayout) -> Self {
+        let stride = layout.stride();
+        let mut address = entry;
+        while address >= stride && layout.read(address - stride).is_some() {
+            address -= stride;
+        }
+        let mut end = entry + stride;
+        while layout.read(end).is_some() {
+            end += stride;
+        }
+        Self { address, count: (end - address) / stride, layout }
+    }
+
+    /// Searches the process memory for the entry of `room_goto`, every runner has it.
+    fn find() -> Result<Self, String> {
+        let name = Signature { bytes: b"room_goto\0".iter().map(|&b| Some(b)).collect() };
+        let spans = readable_spans(0..usize::MAX)?;
+        let mut strings = HashSet::new();
+        for span in &spans {
+            let data = unsafe { core::slice::from_raw_parts(span.start as *const u8, span.len()) };
+            for index in name.find_all(data) {
+                let address = span.start + index;
+                if TableLayout::Inline.read(address).is_some() {
+                    return Ok(Self::around(address, TableLayout::Inline));
+                }
+                strings.insert(address);
+            }
+        }
+        // Then for an entry pointing at one of the names
+        for span in &spans {
+            for entry in (span.start..span.end.saturating_sub(16)).step_by(4) {
+                let name = unsafe { (entry as *const u32).read_volatile() } as usize;
+                if strings.contains(&name) && TableLayout::Pointer.read(entry).is_some() {
+                    return Ok(Self::around(entry, TableLayout::Pointer));
+                }
+            }
+        }
+        Err("No function table found in memory".to_string())
+    }
+}
+
+impl RValue {
+    pub fn real(value: f64) -> Self {
+        Self {
+            value: value.to_bits(),
+            flags: 0,
+            kind: KIND_REAL,
+        }
+    }
+
+    pub fn as_real(&self) -> Option<f64> {
+        match self.kind & 0xffffff {
+            KIND_REAL | KIND_BOOL => Some(f64::from_bits(self.value)),
+            KIND_INT32 => Some(self.value as u32 as i32 as f64),
+            KIND_INT64 => Some(self.value as i64 as f64),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for RValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", unsafe { format_rvalue(self as *const RValue as usize) })
+    }
+}
+
+static HOOKS: Mutex<Vec<Hook>> = Mutex::new(Vec::new());
+static TABLE: Mutex<Option<FunctionTable>> = Mutex::new(None); // Found in memory
+
+// A detour can't tell which function it replaces, so every hook gets its own entry point
+macro_rules! slots {
+    ($($slot:literal),*) => {
+        const SLOTS: [Routine; 32] = [$({
+            unsafe extern "C" fn slot(result: *mut RValue, self_: usize, other: usize, argc: i32, args: *const RValue) {
+                dispatch($slot, result, self_, other, argc, args)
+            }
+            slot
+        }),*];
+    };
+}
+
+slots!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31);
+
+/// A handler that panicked loses its change, the game keeps running. A panic can't unwind into the runner.
+fn run_handlers(handlers: &[Handler], call: &mut Call) {
+    for handler in handlers {
+        if catch_unwind(AssertUnwindSafe(|| handler(call))).is_err() {
+            println!("========== A handler of {} panicked ==========", call.name);
+        }
+    }
+}
+
+/// Every change to the hooks is a single push or field write, a panic while holding the lock can't leave them half updated.
+fn lock_hooks() -> MutexGuard<'static, Vec<Hook>> {
+    HOOKS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+unsafe fn dispatch(slot: usize, result: *mut RValue, self_: usize, other: usize, argc: i32, args: *const RValue) {
+    // The lock is released before running anything, hooked functions can call each other
+    let hook = {
+        let mut hooks = lock_hooks();
+        hooks.iter_mut().find(|h| h.slot == slot).map(|hook| {
+            hook.calls += 1;
+            (hook.detour.trampoline() as *const () as usize, hook.name.clone(), hook.log, hook.before.clone(), hook.after.clone())
+        })
+    };
+    // Without its hook the original is unknown, the call returns the result it came with
+    let Some((trampoline, name, log, before, after)) = hook else {
+        return;
+    };
+    let original: Routine = std::mem::transmute(trampoline);
+    let mut call = Call {
+        name,
+        self_,
+        other,
+        args: if argc > 0 && !args.is_null() { core::slice::from_raw_parts(args, argc as usize).to_vec() } else { Vec::new() },
+        result: *result,
+        skip: false,
+    };
+    run_handlers(&before, &mut call);
+    if !call.skip {
+        original(&mut call.result, self_, other, call.args.len() as i32, call.args.as_ptr());
+    }
+    run_handlers(&after, &mut call);
+    if log {
+        let args = call.args.iter().map(|a| a.to_string()).collect::<Vec<String>>().join(", ");
+        println!("{}({args}) -> {}{}", call.name, call.result, if call.skip { " (skipped)" } else { "" });
+    }
+    *result = call.result;
+}
+
+/// Detours the function at `address` and enables the hook, returns false if it was already installed.
+pub fn install(name: &str, address: usize) -> Result<bool, String> {
+    let mut hooks = lock_hooks();
+    if hooks.iter().any(|h| h.name == name) {
+        return Ok(false);
+    }
+    let slot = (0..SLOTS.len()).find(|s| !hooks.iter().any(|h| h.slot == *s)).ok_or("Every hook slot is in use")?;
+    let detour = unsafe { RawDetour::new(address as *const (), SLOTS[slot] as *const ()) }.map_err(|e| e.to_string())?;
+    unsafe { detour.enable() }.map_err(|e| e.to_string())?;
+    hooks.push(Hook {
+        name: name.to_string(),
+        address,
+        slot,
+        detour,
+        calls: 0,
+        log: false,
+        before: Vec::new(),
+        after: Vec::new(),
+    });
+    println!("========== Hooked {name} at 0x{address:x} ==========");
+    Ok(true)
+}
+
+fn with_hook<T>(name: &str, f: impl FnOnce(&mut Hook) -> T) -> Result<T, String> {
+    let mut hooks = lock_hooks();
+    hooks.iter_mut().find(|h| h.name == name).map(f).ok_or(format!("{name} is not hooked"))
+}
+
+/// Runs `handler` before the original function.
+pub fn before(name: &str, handler: impl Fn(&mut Call) + Send + Sync + 'static) -> Result<(), String> {
+    with_hook(name, |h| h.before.push(Arc::new(handler)))
+}
+
+/// Runs `handler` after the original function (or after it was skipped).
+pub fn after(name: &str, handler: impl Fn(&mut Call) + Send + Sync + 'static) -> Result<(), String> {
+    with_hook(name, |h| h.after.push(Arc::new(handler)))
+}
+
+pub fn set_enabled(name: &str, enabled: bool) -> Result<(), String> {
+    with_hook(name, |h| unsafe { if enabled { h.detour.enable() } else { h.detour.disable() } }.map_err(|e| e.to_string()))?
+}
+
+pub fn set_logging(name: &str, log: bool) -> Result<(), String> {
+    with_hook(name, |h| h.log = log)
+}
+
+/// Calls the original function of a hook, skipping the handlers.
+/// Must be called from the game thread, the overlay renders on it.
+pub fn call_original(name: &str, self_: usize, other: usize, args: &[RValue]) -> Result<RValue, String> {
+    let trampoline = with_hook(name, |h| h.detour.trampoline() as *const () as usize)?;
+    Ok(call_builtin(trampoline, self_, other, args))
+}
+
+/// Calls the built-in at `address`, hooked or not. Must be called from the game thread.
+pub fn call_builtin(address: usize, self_: usize, other: usize, args: &[RValue]) -> RValue {
+    let routine: Routine = unsafe { std::mem::transmute(address) };
+    let mut result = RValue::default();
+    unsafe { routine(&mut result, self_, other, args.len() as i32, args.as_ptr()) };
+    result
+}
+
+pub fn hooks() -> Vec<HookInfo> {
+    lock_hooks().iter().map(|h| HookInfo {
+        name: h.name.clone(),
+        address: h.address,
+        enabled: h.detour.is_enabled(),
+        calls: h.calls,
+        log: h.log,
+    }).collect()
+}
+
+/// Reads the built-in table of the runner. The `gml_functions` profile symbol is the address of the
+/// array and `gml_function_count` the address of its length, without them the table is searched in memory.
+pub fn runtime_functions(watch: &WatchList) -> Result<HashMap<String, RuntimeFunction>, String> {
+    let table = function_table(watch)?;
+    if !is_readable(table.address, table.count * table.layout.stride()) {
+        return Err(format!("The function table is not readable for {} entries", table.count));
+    }
+    let mut functions = HashMap::new();
+    for i in 0..table.count {
+        if let Some((name, function)) = table.layout.read(table.address + i * table.layout.stride()) {
+            functions.insert(name, function);
+        }
+    }
+    Ok(functions)
+}
+
+fn function_table(watch: &WatchList) -> Result<FunctionTable, String> {
+    if watch.symbols.contains_key("gml_functions") {
+        let table = watch.resolve("gml_functions")?;
+        let count = watch.resolve("gml_function_count")?;
+        if !is_readable(count, 4) || !is_readable(table, 4) {
+            return Err("The function table is not readable".to_string());
+        }
+        let count = unsafe { (count as *const u32).read_unaligned() } as usize;
+        // The layout whose first entry is a function, a name can start with bytes that look like a pointer
+        let layout = [TableLayout::Inline, TableLayout::Pointer].into_iter().find(|l| l.read(table).is_some())
+            .ok_or("gml_functions doesn't point at a function table")?;
+        return Ok(FunctionTable { address: table, count, layout });
+    }
+    // The runner fills the table once at startup
+    let mut found = TABLE.lock().unwrap_or_else(PoisonError::into_inner);
+    if found.is_none() {
+        let table = FunctionTable::find()?;
+        println!("========== Found {} built-in functions at 0x{:x} ==========", table.count, table.address);
+        *found = Some(table);
+    }
+    Ok(found.unwrap())
+}
+
+/// Address of a built-in, a profile symbol with the same name takes priority over the function table.
+pub fn resolve(watch: &WatchList, name: &str) -> Result<usize, String> {
+    if watch.symbols.contains_key(name) {
+        return watch.resolve(name);
+    }
+    runtime_functions(watch)?.get(name).map(|f| f.routine).ok_or(format!("{name} is not a built-in function"))
+}
+
+static LAST_ROOM: AtomicI32 = AtomicI32::new(-1);
+
+/// Hooks the `room_goto` built-in at `addr` to remember the room the game asked for last.
+pub fn hook_room_goto(addr: usize) -> Result<(), String> {
+    if install("room_goto", addr)? {
+        before("room_goto", |call| {
+            if let Some(room) = call.args.first().and_then(|a| a.as_real()) {
+                LAST_ROOM.store(room as i32, Ordering::Relaxed);
+            }
+        })?;
+    }
+    Ok(())
+}
+
+/// Last room passed to `room_goto`, by the game or by the tool.
+pub fn last_room() -> Option<i32> {
+    Some(LAST_ROOM.load(Ordering::Relaxed)).filter(|&r| r >= 0)
+}
+
+/// The runner switches rooms at the end of the current step.
+pub fn goto_room(room: usize) -> Result<(), String> {
+    // No instance is running, `room_goto` only reads its argument and is assumed to never dereference
+    // the null self and other. A runner whose built-in does would crash here.
+    call_original("room_goto", 0, 0, &[RValue::real(room as f64)])?;
+    LAST_ROOM.store(room as i32, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Lists the built-ins called by the game and hooks them to log their calls. Scripts run in the VM
+/// instead of being native functions and can't be detoured, they are left out once the function table is read.
+#[derive(Default)]
+pub struct HookPanel {
+    names: Vec<String>, // From FUNC
+    runtime: HashMap<String, RuntimeFunction>,
+    filter: String,
+    selected: Option<usize>,
+}
+
+impl HookPanel {
+    pub fn load(&mut self, form: &Form) {
+        self.names = parse_functions(form).into_iter().map(|f| f.name).collect();
+        self.names.sort();
+        self.names.dedup();
+    }
+
+    pub fn render(&mut self, ui: &imgui::Ui, watch: &WatchList) {
+        if ui.button("Read Function Table") {
+            match runtime_functions(watch) {
+                Ok(functions) => {
+                    println!("========== Found {} built-in functions ==========", functions.len());
+                    self.runtime = functions;
+                }
+                Err(e) => println!("Could not read the function table: {e}"),
+            }
+        }
+        ui.same_line();
+        ui.set_next_item_width(150.);
+        ui.input_text("Filter##hook", &mut self.filter).build();
+        let filter = self.filter.to_lowercase();
+        let hookable = |name: &str| self.runtime.is_empty() || self.runtime.contains_key(name) || watch.symbols.contains_key(name);
+        ui.child_window("##functions").size([0., 100.]).build(|| {
+            for (i, name) in self.names.iter().enumerate() {
+                if !name.to_lowercase().contains(&filter) || !hookable(name) {
+                    continue;
+                }
+                let kind = match self.runtime.get(name) {
+                    Some(f) if f.arguments < 0 => "built-in, variable arguments".to_string(),
+                    Some(f) => format!("built-in, {} arguments", f.arguments),
+                    None if watch.symbols.contains_key(name) => "symbol".to_string(),
+                    None => "?".to_string(),
+                };
+                if ui.selectable_config(format!("{name} ({kind})##func{i}")).selected(self.selected == Some(i)).build() {
+                    self.selected = Some(i);
+                }
+            }
+        });
+        if let Some(name) = self.selected.and_then(|s| self.names.get(s)).filter(|n| hookable(n)) {
+            if ui.button("Hook and Log Calls") {
+                let address = self.runtime.get(name).map(|f| Ok(f.routine)).unwrap_or_else(|| resolve(watch, name));
+                if let Err(e) = address.and_then(|a| install(name, a)).and_then(|_| set_logging(name, true)) {
+                    println!("Could not hook {name}: {e}");
+                }
+            }
+        }
+        for hook in hooks() {
+            let mut enabled = hook.enabled;
+            if ui.checkbox(format!("##hookon{}", hook.name), &mut enabled) {
+                if let Err(e) = set_enabled(&hook.name, enabled) {
+                    println!("Could not switch {}: {e}", hook.name);
+                }
+            }
+            ui.same_line();
+            let mut log = hook.log;
+            if ui.checkbox(format!("Log##hooklog{}", hook.name), &mut log) {
+                if let Err(e) = set_logging(&hook.name, log) {
+                    println!("Could not switch logging of {}: {e}", hook.name);
+                }
+            }
+            ui.same_line();
+            ui.text(format!("{} at 0x{:x}, {} calls", hook.name, hook.address, hook.calls));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panicking_handler() {
+        let mut call = Call { name: "f".to_string(), self_: 0, other: 0, args: Vec::new(), result: RValue::default(), skip: false };
+        let handlers: Vec<Handler> = vec![
+            Arc::new(|call: &mut Call| call.result = RValue::real(1.)),
+            Arc::new(|_: &mut Call| panic!("bad handler")),
+            Arc::new(|call: &mut Call| call.skip = true),
+        ];
+        run_handlers(&handlers, &mut call);
+        assert_eq!(call.result.as_real(), Some(1.));
+        assert!(call.skip);
+    }
+}
@@ -1,9 +1,45 @@
 #![feature(strict_provenance)]
-#![feature(vec_into_raw_parts)]
+
+pub mod address;
+pub mod arena;
+pub mod asm;
+pub mod atlas;
+pub mod cheat_table;
+pub mod font;
+pub mod fontgen;
+pub mod form;
+pub mod code;
+pub mod decompile;
+pub mod disasm;
+pub mod hooks;
+pub mod preview;
+pub mod qoi;
+pub mod references;
+pub mod room;
+pub mod sigscan;
+pub mod sprite;
+pub mod strings;
+pub mod texture;
+pub mod variables;
+pub mod watch;
 
 use core::slice;
-use std::{env, ffi::CStr, fs::File, io::{BufReader, BufWriter, Read, Write}, os::windows::process::CommandExt, process::Command};
+use std::{collections::HashMap, env, fs::File, io::{BufReader, BufWriter, Read, Write}, os::windows::process::CommandExt, process::Command};
 use hudhook::{hooks::dx9::ImguiDx9Hooks, *};
+use arena::Arena;
+use font::{export_fonts, missing_glyphs, parse_fonts, Font};
+use fontgen::FontGenerator;
+use form::Form;
+use references::{string_fonts, string_references, StringUse, UseKind};
+use room::RoomBrowser;
+use disasm::CodePanel;
+use hooks::HookPanel;
+use preview::StringPreview;
+use sprite::SpritePanel;
+use strings::{escape, unescape};
+use texture::TexturePanel;
+use variables::VariableBrowser;
+use watch::WatchList;
 use mmap_rs::MemoryAreas;
 use rand::Rng;
 use rfd::FileDialog;
@@ -21,9 +57,27 @@ pub struct RenderLoop {
     w1_position: (f32, imgui::Condition),
     last_w1_position: [f32; 2],
     string_search: StringSearch,
+    text_fit: TextFit,
     string_edit: String,
+    string_missing: Vec<char>,
+    form: Option<Form<'static>>,
+    fonts: Vec<Font>,
+    references: HashMap<usize, Vec<StringUse>>,
+    export_filter: usize,
+    arena: Arena,
+    watch: WatchList,
+    variables: VariableBrowser,
+    rooms: RoomBrowser,
+    hooks: HookPanel,
+    code: CodePanel,
+    textures: TexturePanel,
+    sprites: SpritePanel,
+    font_generator: FontGenerator,
+    preview: StringPreview,
 }
 
+const EXPORT_FILTERS: [&str; 4] = ["All Strings", "Code Literals Only", "Asset Names Only", "Unreferenced Only"];
+
 #[derive(Default)]
 pub struct ListBoxData {
     item: i32,
@@ -39,16 +93,19 @@ pub struct MusicEntry {
     local_ptr: usize, // *mut u8
     local_ptr2: usize, // *mut u8,
     local_size_ptr: usize, // *mut u32
-    new_music: Option<(usize, usize, usize)>, // *mut u8, size, capacity
+    new_music: Option<usize>, // Arena buffer
 }
 
 #[derive(Default)]
 pub struct StringEntry {
+    index: usize, // Position in the STRG chunk
     offset: usize,
     entry: usize, // *mut u32
     entry_ptr: usize, // *mut u8
-    string: String,
-    new_string: Option<(usize, usize, usize)>, // *mut u8, size, capacity
+    raw: Vec<u8>, // Original bytes, the game doesn't require them to be UTF-8
+    is_utf8: bool,
+    string: String, // Editable text, with the invalid bytes escaped
+    new_string: Option<usize>, // Arena buffer
 }
 
 #[derive(Default)]
@@ -59,6 +116,15 @@ pub struct StringSearch {
     size: u16,
 }
 
+/// Checks translated strings against the width of the originals in the font they are drawn with.
+#[derive(Default)]
+pub struct TextFit {
+    fonts: HashMap<usize, String>, // STRG index -> font its code draws it with
+    font: usize, // 0 measures with the detected font, then the fonts in order
+    ratio: f32, // Widest line allowed, relative to the original
+    wrap: i32,
+}
+
 impl Default for RenderLoop {
     fn default() -> Self {
         Self {
@@ -75,7 +141,36 @@ impl Default for RenderLoop {
             ),
             last_w1_position: [15., 15.],
             string_search: StringSearch::default(),
+            text_fit: TextFit { ratio: 1.1, wrap: 300, ..Default::default() },
             string_edit: String::new(),
+            string_missing: Vec::new(),
+            form: None,
+            fonts: Vec::new(),
+            references: HashMap::new(),
+            export_filter: 0,
+            arena: Arena::default(),
+            watch: WatchList::default(),
+            variables: VariableBrowser::default(),
+            rooms: RoomBrowser::default(),
+            hooks: HookPanel::default(),
+            code: CodePanel::default(),
+            textures: TexturePanel::default(),
+            sprites: SpritePanel::default(),
+            font_generator: FontGenerator::default(),
+            preview: StringPreview::default(),
+        }
+    }
+}
+
+impl StringEntry {
+    unsafe fn is_modified(&self) -> bool {
+        self.new_string.is_some() && self.current() != self.raw
+    }
+
+    unsafe fn current(&self) -> Vec<u8> {
+        match self.new_string {
+            Some(ptr) => slice::from_raw_parts((ptr + 4) as *const u8, *(ptr as *const u32) as usize).to_vec(),
+            None => self.raw.clone(),
         }
     }
 }
@@ -110,31 +205,67 @@ impl RenderLoop {
                 ..Default::default()
             });
         }
-        const STRING_FIRST_ENTRY_POINTER: usize = 0x01b62c39 + 12;
-        let mut size = 0;
-        while *((STRING_FIRST_ENTRY_POINTER + (size as usize * 4)) as *mut u32) > 0x80 {
-            size += 1;
-        }
-        let mut offset = 0;
-        let mut pointers = Vec::new();
-        for i in 0..size {
-            if i == 0 {
-                offset = (STRING_FIRST_ENTRY_POINTER - *(STRING_FIRST_ENTRY_POINTER as *mut u32) as usize) + (size as usize * 4);
+        const STRING_CHUNK_POINTER: usize = 0x01b62c39;
+        self.form = Form::locate(STRING_CHUNK_POINTER);
+        if let Some(form) = self.form {
+            println!("Found data.win at 0x{:x}", form.base);
+            match form.chunk(b"STRG") {
+                Some(chunk) if form.addr(chunk.offset - 8) == STRING_CHUNK_POINTER => {
+                    let pointers = form.pointer_list(chunk.offset).unwrap_or_default();
+                    println!("STRG has {} entries", pointers.len());
+                    for (index, ptr) in pointers.into_iter().enumerate() {
+                        // The length prefix, the characters and the NUL terminator must all be inside the chunk
+                        let size = match form.u32(ptr) {
+                            // A corrupt size can overflow on the 32 bit target
+                            Some(size) if chunk.contains(ptr, 4) && (size as usize).checked_add(1).is_some_and(|n| chunk.contains(ptr + 4, n)) => size as usize,
+                            _ => {
+                                println!("Invalid string {index}: pointer 0x{ptr:x} is outside of STRG");
+                                continue;
+                            }
+                        };
+                        if form.u8(ptr + 4 + size) != Some(0) {
+                            println!("Invalid string {index}: missing NUL terminator at 0x{:x}", ptr + 4 + size);
+                            continue;
+                        }
+                        let raw = form.bytes(ptr + 4, size).unwrap().to_vec();
+                        let is_utf8 = std::str::from_utf8(&raw).is_ok();
+                        if !is_utf8 {
+                            println!("String {index} is not valid UTF-8, its invalid bytes are shown as \\xNN");
+                        }
+                        let string = escape(&raw, false);
+                        self.string.items.push(string.clone());
+                        self.string_entry.push(StringEntry {
+                            index,
+                            offset: form.base,
+                            entry: form.addr(ptr),
+                            entry_ptr: form.addr(chunk.offset + 4 + index * 4),
+                            raw,
+                            is_utf8,
+                            string,
+                            new_string: None,
+                        });
+                    }
+                }
+                _ => println!("========== STRG chunk not found =========="),
             }
-            let ptr = STRING_FIRST_ENTRY_POINTER + (i as usize * 4);
-            pointers.push(((*(ptr as *mut u32)) as usize + offset, ptr));
-        }
-
-        for (ptr, sptr) in pointers {
-            let string = CStr::from_ptr((ptr + 4) as *mut i8).to_string_lossy().to_string();
-            self.string.items.push(string.clone());
-            self.string_entry.push(StringEntry {
-                offset,
-                entry: ptr,
-                entry_ptr: sptr,
-                string,
-                new_string: None,
-            });
+            self.references = string_references(&form);
+            println!("Found references to {} strings", self.references.len());
+            self.text_fit.fonts = string_fonts(&form);
+            println!("Found the fonts of {} strings", self.text_fit.fonts.len());
+            self.fonts = parse_fonts(&form);
+            for font in &self.fonts {
+                println!("Found font {} ({} glyphs)", font.name, font.glyphs.len());
+            }
+            self.variables.load(&form);
+            self.rooms.load(&form);
+            self.hooks.load(&form);
+            self.code.load(&form);
+            self.textures.load(&form);
+            self.sprites.load(&form);
+            self.font_generator.load();
+            self.preview.load();
+        } else {
+            println!("========== Could not find data.win ==========");
         }
         // External pointer data
         let mut current_audiogroup = 0;
@@ -146,7 +277,7 @@ impl RenderLoop {
                     let size = *(ptr.offset(0x10) as *mut u32);
                     let first_entry_pointer = ptr.offset(0x14) as *mut u32;
                     let mut pointers = Vec::new();
-                    offset = 0;
+                    let mut offset = 0;
                     for i in 0..size {
                         if i == 0 {
                             offset = (first_entry_pointer.addr() - *first_entry_pointer as usize) + (size as usize * 4);
@@ -192,14 +323,155 @@ impl RenderLoop {
         }
         println!("========== Finished localizing pointers ==========");
     }
+
+    unsafe fn music_data(&self, item: usize) -> &[u8] {
+        let entry = &self.music_entry[item];
+        match entry.new_music.and_then(|addr| self.arena.get(addr)) {
+            Some(data) => &data[4..],
+            None => slice::from_raw_parts((entry.entry + 4) as *const u8, entry.size as usize),
+        }
+    }
+
+    /// Points the STRG slot of a string at new content, the previous buffer is released by the arena.
+    unsafe fn set_string(&mut self, item: usize, bytes: Vec<u8>) {
+        if bytes == self.string_entry[item].raw {
+            // Back to the original content, no copy needed
+            self.restore_string(item);
+            return;
+        }
+        if self.string_entry[item].current() == bytes {
+            return;
+        }
+        let addr = self.arena.alloc(string_to_gmdata(bytes));
+        let entry = &mut self.string_entry[item];
+        self.arena.set(entry.entry_ptr, (addr - entry.offset) as u32, Some(addr));
+        entry.new_string = Some(addr);
+    }
+
+    unsafe fn restore_string(&mut self, item: usize) {
+        let entry = &mut self.string_entry[item];
+        if entry.new_string.take().is_some() {
+            self.arena.set(entry.entry_ptr, (entry.entry - entry.offset) as u32, None);
+        }
+    }
+
+    fn string_uses(&self, entry: &StringEntry) -> &[StringUse] {
+        self.references.get(&entry.index).map(|r| r.as_slice()).unwrap_or_default()
+    }
+
+    fn is_exported(&self, entry: &StringEntry) -> bool {
+        let uses = self.string_uses(entry);
+        match self.export_filter {
+            1 => uses.iter().any(|u| u.kind == UseKind::CodeLiteral),
+            2 => uses.iter().any(|u| u.is_asset_name()),
+            3 => uses.is_empty(),
+            _ => true,
+        }
+    }
+
+    fn check_glyphs(&self, item: usize, string: &str) -> Vec<char> {
+        let missing = missing_glyphs(&self.fonts, string);
+        if !missing.is_empty() {
+            println!("String {} uses characters missing from every font: {}", self.string_entry[item].index, missing.iter().collect::<String>());
+        }
+        missing
+    }
+
+    /// Font a string is measured with, the chosen one or the one its code draws it with.
+    fn fit_font_index(&self, item: usize) -> Option<usize> {
+        match self.text_fit.font {
+            0 => {
+                let name = self.text_fit.fonts.get(&self.string_entry.get(item)?.index)?;
+                self.fonts.iter().position(|f| &f.name == name)
+            }
+            i => (i <= self.fonts.len()).then_some(i - 1),
+        }
+    }
+
+    fn fit_font(&self, item: usize) -> Option<&Font> {
+        self.fit_font_index(item).map(|i| &self.fonts[i])
+    }
+
+    /// Widths of `text` and of the original string, with the font measuring them.
+    fn string_widths(&self, item: usize, text: &[u8]) -> Option<(&Font, i32, i32)> {
+        let font = self.fit_font(item)?;
+        let width = font.text_width(&String::from_utf8_lossy(text));
+        let original = font.text_width(&String::from_utf8_lossy(&self.string_entry[item].raw));
+        Some((font, width, original))
+    }
+
+    fn overflows(&self, width: i32, original: i32) -> bool {
+        original > 0 && width as f32 > original as f32 * self.text_fit.ratio
+    }
+
+    fn overflow_report(&self) {
+        println!("========== Overflow Report ==========");
+        let (mut checked, mut overflows) = (0, 0);
+        for (item, entry) in self.string_entry.iter().enumerate() {
+            if !unsafe { entry.is_modified() } {
+                continue;
+            }
+            let Some((font, width, original)) = self.string_widths(item, &unsafe { entry.current() }) else {
+                continue;
+            };
+            checked += 1;
+            if self.overflows(width, original) {
+                overflows += 1;
+                println!("String {}: {width}px in {}, the original is {original}px ({:.0}%)", entry.index, font.name, width as f32 * 100. / original as f32);
+            }
+        }
+        println!("{overflows} of {checked} measured strings are over {:.0}% of the original width", self.text_fit.ratio * 100.);
+        println!("========== Finished Overflow Report ==========");
+    }
+
+    fn glyph_coverage_report(&self) {
+        println!("========== Glyph Coverage ==========");
+        let modified = self.string_entry.iter()
+            .filter(|e| unsafe { e.is_modified() })
+            .map(|e| (e.index, escape(&unsafe { e.current() }, false)))
+            .collect::<Vec<(usize, String)>>();
+        for font in &self.fonts {
+            let mut missing = Vec::new();
+            let mut strings = 0;
+            for (_, string) in &modified {
+                let m = font.missing(string);
+                if !m.is_empty() {
+                    strings += 1;
+                }
+                for c in m {
+                    if !missing.contains(&c) {
+                        missing.push(c);
+                    }
+                }
+            }
+            if missing.is_empty() {
+                println!("{}: covers every modified string", font.name);
+            } else {
+                println!("{}: missing \"{}\" in {strings} strings", font.name, missing.iter().collect::<String>());
+            }
+        }
+        for (index, string) in &modified {
+            let missing = missing_glyphs(&self.fonts, string);
+            if !missing.is_empty() {
+                println!("String {index} can't be drawn by any font: {}", missing.iter().collect::<String>());
+            }
+        }
+        println!("{} modified strings checked against {} fonts", modified.len(), self.fonts.len());
+        println!("========== Finished Glyph Coverage ==========");
+    }
 }
 
 impl ImguiRenderLoop for RenderLoop {
+    fn before_render<'a>(&'a mut self, _ctx: &mut imgui::Context, render_context: &'a mut dyn RenderContext) {
+        self.preview.upload(render_context);
+    }
+
     fn render(&mut self, ui: &mut imgui::Ui) {
         if !self.end_setup {
             self.end_setup = true;
             unsafe {
                 windows::Win32::System::Console::AllocConsole().unwrap();
+                self.watch.load();
                 self.refresh_music_data();
             }
         }
@@ -238,30 +510,24 @@ impl ImguiRenderLoop for RenderLoop {
                 ui.text("Hide the Window using the F11 key (or Fn + F11 on Laptop)");
                 ui.separator();
                 ui.text_colored([0.2, 1., 0.2, 1.], "General Functions");
-                let mut game_id = unsafe { (*(0xa4e5e0 as *mut i32)).to_string() };
-                ui.input_text("Game ID", &mut game_id).build();
-                unsafe {
-                    if let Ok(n) = game_id.parse::<i32>() {
-                        if *(0xa4e5e0 as *mut i32) != n {
-                            println!("========== Changed Game ID to {n} ==========");
-                            *(0xa4e5e0 as *mut i32) = n;
-                        }
-                    }
-                }
+                self.watch.render(ui);
+                ui.text(format!("Injected memory: {} buffers, {:.1} KiB", self.arena.len(), self.arena.size() as f32 / 1024.));
+                ui.separator();
+                ui.text_colored([0.4, 0.8, 1., 1.], "GML Variables");
+                self.variables.render(ui, &mut self.watch);
+                ui.separator();
+                ui.text_colored([0.8, 0.6, 1., 1.], "Rooms");
+                self.rooms.render(ui, &self.watch);
+                ui.separator();
+                ui.text_colored([1., 0.4, 0.4, 1.], "GML Hooks");
+                self.hooks.render(ui, &self.watch);
+                ui.separator();
+                ui.text_colored([0.6, 1., 0.8, 1.], "Code");
+                self.code.render(ui, self.form.as_ref(), &mut self.arena);
                 ui.separator();
                 ui.text_colored([1., 0.5, 0., 1.], "Music Functions");
                 if ui.button("Save") {
-                    let data = unsafe {
-                        if let Some((ptr, size, _capacity)) = self.music_entry[self.music.item as usize].new_music {
-                            slice::from_raw_parts(
-                                (ptr + 4) as *mut u8,
-                                size)
-                        } else {
-                            slice::from_raw_parts(
-                                (self.music_entry[self.music.item as usize].entry + 4) as *mut u8,
-                                self.music_entry[self.music.item as usize].size as usize)
-                        }
-                    };
+                    let data = unsafe { self.music_data(self.music.item as usize) };
                     let is_ogg = &data[0..4] == b"OggS";
                     let file = FileDialog::new()
                         .add_filter(if is_ogg { "OGG Files" } else { "WAV Files" },
@@ -277,17 +543,7 @@ impl ImguiRenderLoop for RenderLoop {
                 }
                 ui.same_line();
                 if ui.button("Save & Play") {
-                    let data = unsafe {
-                        if let Some((ptr, size, _capacity)) = self.music_entry[self.music.item as usize].new_music {
-                            slice::from_raw_parts(
-                                (ptr + 4) as *mut u8,
-                                size)
-                        } else {
-                            slice::from_raw_parts(
-                                (self.music_entry[self.music.item as usize].entry + 4) as *mut u8,
-                                self.music_entry[self.music.item as usize].size as usize)
-                        }
-                    };
+                    let data = unsafe { self.music_data(self.music.item as usize) };
                     let is_ogg = &data[0..4] == b"OggS";
                     let file = FileDialog::new()
                         .add_filter(if is_ogg { "OGG Files" } else { "WAV Files" },
@@ -307,17 +563,7 @@ impl ImguiRenderLoop for RenderLoop {
                 }
                 ui.same_line();
                 if ui.button("Temp Save & Play") {
-                    let data = unsafe {
-                        if let Some((ptr, size, _capacity)) = self.music_entry[self.music.item as usize].new_music {
-                            slice::from_raw_parts(
-                                (ptr + 4) as *mut u8,
-                                size)
-                        } else {
-                            slice::from_raw_parts(
-                                (self.music_entry[self.music.item as usize].entry + 4) as *mut u8,
-                                self.music_entry[self.music.item as usize].size as usize)
-                        }
-                    };
+                    let data = unsafe { self.music_data(self.music.item as usize) };
                     let mut file = env::temp_dir();
                     let is_ogg = &data[0..4] == b"OggS";
                     file.push(rand::thread_rng().gen_range(0..0xffffff).to_string() + if is_ogg { ".ogg" } else { ".wav" });
@@ -331,17 +577,7 @@ impl ImguiRenderLoop for RenderLoop {
                         .spawn().unwrap();
                 }
                 if ui.button("Load") {
-                    let data = unsafe {
-                        if let Some((ptr, size, _capacity)) = self.music_entry[self.music.item as usize].new_music {
-                            slice::from_raw_parts(
-                                (ptr + 4) as *mut u8,
-                                size)
-                        } else {
-                            slice::from_raw_parts(
-                                (self.music_entry[self.music.item as usize].entry + 4) as *mut u8,
-                                self.music_entry[self.music.item as usize].size as usize)
-                        }
-                    };
+                    let data = unsafe { self.music_data(self.music.item as usize) };
                     let is_ogg = &data[0..4] == b"OggS";
                     let file = FileDialog::new()
                         .add_filter(if is_ogg { "OGG Files" } else { "WAV Files" },
@@ -356,25 +592,28 @@ impl ImguiRenderLoop for RenderLoop {
                         let mut final_data = Vec::new();
                         final_data.extend((data.len() as u32).to_le_bytes());
                         final_data.extend(data);
-                        let final_data = final_data.into_raw_parts();
+                        let size = final_data.len() - 4;
+                        let addr = self.arena.alloc(final_data);
                         unsafe {
                             let entry = &mut self.music_entry[self.music.item as usize];
                             println!("========== Loaded new song ==========");
                             println!("Main entry pointer: {:?}", entry.entry_ptr);
-                            println!("New data pointer: {:?}", final_data.0.addr());
+                            println!("New data pointer: {:?}", addr);
                             println!("Entry pointer: {:?}", entry.entry);
                             println!("Entry size: {:?}", entry.size);
                             println!("Local pointer: {:?}", entry.local_ptr);
                             println!("Second Local pointer: {:?}", entry.local_ptr2);
-                            *(entry.entry_ptr as *mut u32) = (final_data.0.addr() - entry.offset) as u32;
-                            *(entry.local_ptr as *mut u32) = final_data.0.addr() as u32;
+                            self.arena.set(entry.entry_ptr, (addr - entry.offset) as u32, Some(addr));
+                            if entry.local_ptr != 0 {
+                                self.arena.set(entry.local_ptr, addr as u32, Some(addr));
+                            }
                             if entry.local_ptr2 != 0 {
-                                *(entry.local_ptr2 as *mut u32) = (final_data.0.addr() + 4) as u32;
-                                *(entry.local_size_ptr as *mut u32) = (final_data.1 - 4) as u32;
+                                self.arena.set(entry.local_ptr2, (addr + 4) as u32, Some(addr));
+                                *(entry.local_size_ptr as *mut u32) = size as u32;
                             } else {
                                 println!("========== Invalid pointer for replacing audio ==========");
                             }
-                            entry.new_music = Some((final_data.0.addr(), final_data.1, final_data.2));
+                            entry.new_music = Some(addr);
                         }
                     }
                 }
@@ -382,21 +621,22 @@ impl ImguiRenderLoop for RenderLoop {
                 if ui.button("Restore OG Song") {
                     unsafe {
                         let entry = &mut self.music_entry[self.music.item as usize];
-                        if let Some((ptr, size, capacity)) = entry.new_music.take() {
+                        if entry.new_music.take().is_some() {
                             println!("========== Restored old song ==========");
                             println!("Main entry pointer: {:?}", entry.entry_ptr);
                             println!("Entry pointer: {:?}", entry.entry);
                             println!("Entry size: {:?}", entry.size);
                             println!("Local pointer: {:?}", entry.local_ptr);
                             println!("Second Local pointer: {:?}", entry.local_ptr2);
-                            *(entry.entry_ptr as *mut u32) = (entry.entry - entry.offset) as u32;
-                            *(entry.local_ptr as *mut u32) = entry.entry as u32;
+                            // The song buffer is released once none of these point at it
+                            self.arena.set(entry.entry_ptr, (entry.entry - entry.offset) as u32, None);
+                            if entry.local_ptr != 0 {
+                                self.arena.set(entry.local_ptr, entry.entry as u32, None);
+                            }
                             if entry.local_ptr2 != 0 {
-                                *(entry.local_ptr2 as *mut u32) = (entry.entry + 4) as u32;
+                                self.arena.set(entry.local_ptr2, (entry.entry + 4) as u32, None);
                                 *(entry.local_size_ptr as *mut u32) = entry.size;
                             }
-                            // Deallocate the song
-                            drop(Vec::from_raw_parts(ptr as *mut u8, size, capacity));
                         } else {
                             println!("========== The song has not been modified ==========");
                         }
@@ -404,6 +644,20 @@ impl ImguiRenderLoop for RenderLoop {
                 }
                 ui.list_box("Music Data", &mut self.music.item, &self.music.items.iter().collect::<Vec<&String>>(), 10);
                 ui.separator();
+                ui.text_colored([1., 0.8, 0.2, 1.], "Texture Functions");
+                self.textures.render(ui, self.form.as_ref(), &mut self.arena, &self.watch);
+                ui.separator();
+                ui.text_colored([1., 0.6, 0.6, 1.], "Sprites");
+                self.sprites.render(ui, self.form.as_ref(), &self.textures.pages);
+                ui.separator();
+                ui.text_colored([0.6, 0.8, 0.4, 1.], "Font Generator");
+                let string_entry = &self.string_entry;
+                let translated = || string_entry.iter()
+                    .filter(|e| unsafe { e.is_modified() })
+                    .map(|e| String::from_utf8_lossy(&unsafe { e.current() }).to_string())
+                    .collect();
+                self.font_generator.render(ui, self.form.as_ref(), &mut self.arena, &mut self.textures, &mut self.fonts, translated);
+                ui.separator();
                 ui.text_colored([1., 0., 0., 1.], "String Functions");
                 if ui.button("Export") {
                     let file = FileDialog::new()
@@ -412,11 +666,21 @@ impl ImguiRenderLoop for RenderLoop {
                         .save_file();
                     if let Some(file) = file {
                         let mut fstr = String::new();
-                        for string in self.string.items.iter() {
+                        if self.export_filter != 0 {
+                            // Filtered exports keep the STRG index of every line so they can be imported back
+                            fstr += "#indexed";
+                        }
+                        for entry in &self.string_entry {
+                            if !self.is_exported(entry) {
+                                continue;
+                            }
                             if !fstr.is_empty() {
                                 fstr += "\r\n";
                             }
-                            fstr += &(string.clone().replace('\n', "\\n").replace('\r', "\\r"));
+                            if self.export_filter != 0 {
+                                fstr += &format!("{}\t", entry.index);
+                            }
+                            fstr += &escape(&unsafe { entry.current() }, true);
                         }
                         let mut f = BufWriter::new(File::create(file).unwrap());
                         f.write_all(fstr.as_bytes()).unwrap();
@@ -426,6 +690,9 @@ impl ImguiRenderLoop for RenderLoop {
                     }
                 }
                 ui.same_line();
+                ui.set_next_item_width(150.);
+                ui.combo_simple_string("##Export Filter", &mut self.export_filter, &EXPORT_FILTERS);
+                ui.same_line();
                 if ui.button("Import") {
                     let file = FileDialog::new()
                         .add_filter("Text Files", &["txt"])
@@ -437,19 +704,28 @@ impl ImguiRenderLoop for RenderLoop {
                         f.read_to_string(&mut fstr).unwrap();
                         drop(f);
 
-                        for (index, line) in fstr.split("\r\n").enumerate() {
-                            self.string.items[index] = line.replace("\\r", "\r").replace("\\n", "\n");
-                            let entry = &mut self.string_entry[index];
-                            let r = string_to_gmpointer(line.replace("\\r", "\r").replace("\\n", "\n"));
+                        let mut lines = fstr.split("\r\n").enumerate().collect::<Vec<(usize, &str)>>();
+                        if fstr.starts_with("#indexed") {
+                            let positions = self.string_entry.iter().enumerate().map(|(i, e)| (e.index, i)).collect::<HashMap<usize, usize>>();
+                            lines = lines.into_iter().skip(1).filter_map(|(_, line)| {
+                                let (index, line) = line.split_once('\t')?;
+                                let index = index.parse::<usize>().ok()?;
+                                Some((*positions.get(&index)?, line))
+                            }).collect();
+                        }
+                        for (index, line) in lines {
+                            let bytes = unescape(line, true);
+                            self.string.items[index] = escape(&bytes, false);
+                            self.check_glyphs(index, &String::from_utf8_lossy(&bytes));
                             unsafe {
-                                *(entry.entry_ptr as *mut u32) = (r.0.addr() - entry.offset) as u32;
+                                self.set_string(index, bytes);
                             }
-                            if let Some(string) = entry.new_string {
-                                unsafe {
-                                    drop(Vec::from_raw_parts(string.0 as *mut u8, string.1, string.2));
-                                }
-                            }
-                            entry.new_string = Some((r.0.addr(), r.1, r.2));
+                        }
+                        // The edit box still shows the text from before the import
+                        if let Some(entry) = self.string_entry.get(self.string.item as usize) {
+                            let bytes = unsafe { entry.current() };
+                            self.string_edit = escape(&bytes, false);
+                            self.string_missing = missing_glyphs(&self.fonts, &String::from_utf8_lossy(&bytes));
                         }
                         println!("========== Imported New Strings ==========");
                     }
@@ -457,15 +733,11 @@ impl ImguiRenderLoop for RenderLoop {
                 ui.same_line();
                 if ui.button("Restore All") {
                     for index in 0..self.string_entry.len() {
-                        let entry = &mut self.string_entry[index];
                         unsafe {
-                            *(entry.entry_ptr as *mut u32) = (entry.entry - entry.offset) as u32;
-                        }
-                        if let Some(string) = entry.new_string {
-                            unsafe {
-                                drop(Vec::from_raw_parts(string.0 as *mut u8, string.1, string.2));
-                            }
+                            self.restore_string(index);
                         }
+                        let entry = &self.string_entry[index];
+                        self.string.items[index].clone_from(&entry.string);
                         if index == self.string.item as usize {
                             self.string_edit.clone_from(&entry.string);
                         }
@@ -481,29 +753,63 @@ impl ImguiRenderLoop for RenderLoop {
                 ui.same_line();
                 if ui.button("Paste from Clipboard") {
                     self.string_edit += &ui.clipboard_text().unwrap_or_default();
-                    let entry = &mut self.string_entry[self.string.item as usize];
                     unsafe {
-                        let old = entry.new_string;
-                        let r = string_to_gmpointer(self.string_edit.clone());
-                        entry.new_string = Some((r.0.addr(), r.1, r.2));
-                        *(entry.entry_ptr as *mut u32) = (r.0.addr() - entry.offset) as u32;
-                        if let Some(string) = old {
-                            drop(Vec::from_raw_parts(string.0 as *mut u8, string.1, string.2));
-                        }
+                        self.set_string(self.string.item as usize, unescape(&self.string_edit, false));
                     }
+                    self.string_missing = self.check_glyphs(self.string.item as usize, &self.string_edit);
                 }
                 if ui.button("Restore this String") {
-                    let entry = &mut self.string_entry[self.string.item as usize];
                     unsafe {
-                        *(entry.entry_ptr as *mut u32) = (entry.entry - entry.offset) as u32;
+                        self.restore_string(self.string.item as usize);
                     }
-                    if let Some(string) = entry.new_string {
-                        unsafe {
-                            drop(Vec::from_raw_parts(string.0 as *mut u8, string.1, string.2));
+                    let entry = &self.string_entry[self.string.item as usize];
+                    self.string.items[self.string.item as usize].clone_from(&entry.string);
+                    self.string_edit.clone_from(&entry.string);
+                    self.string_missing = missing_glyphs(&self.fonts, &self.string_edit);
+                }
+                ui.same_line();
+                if ui.button("Glyph Coverage") {
+                    self.glyph_coverage_report();
+                }
+                ui.same_line();
+                if ui.button("Export Fonts") {
+                    if let (Some(form), Some(dir)) = (self.form.as_ref(), FileDialog::new().pick_folder()) {
+                        match export_fonts(form, &self.textures.pages, &self.fonts, &dir) {
+                            Ok(count) => println!("========== Exported {count} fonts to {} ==========", dir.display()),
+                            Err(e) => println!("Could not export the fonts: {e}"),
                         }
                     }
-                    self.string_edit.clone_from(&entry.string);
                 }
+                let mut names = vec!["Detected font".to_string()];
+                names.extend(self.fonts.iter().map(|f| f.name.clone()));
+                ui.set_next_item_width(120.);
+                ui.combo_simple_string("##Measure Font", &mut self.text_fit.font, &names);
+                ui.same_line();
+                ui.set_next_item_width(80.);
+                ui.input_float("Max Ratio", &mut self.text_fit.ratio).build();
+                ui.same_line();
+                if ui.button("Overflow Report") {
+                    self.overflow_report();
+                }
+                ui.same_line();
+                if ui.button("Word Wrap") {
+                    let item = self.string.item as usize;
+                    match self.fit_font(item) {
+                        _ if !self.string_entry[item].is_utf8 => println!("Only UTF-8 strings can be wrapped"),
+                        Some(font) => {
+                            let text = String::from_utf8(unescape(&self.string_edit, false)).unwrap_or_default();
+                            self.string_edit = escape(font.wrap(&text, self.text_fit.wrap).as_bytes(), false);
+                            unsafe {
+                                self.set_string(item, unescape(&self.string_edit, false));
+                            }
+                            self.string.items[item].clone_from(&self.string_edit);
+                        }
+                        None => println!("No font is known for string {}, choose one to wrap with", self.string_entry[item].index),
+                    }
+                }
+                ui.same_line();
+                ui.set_next_item_width(80.);
+                ui.input_int("Wrap Width", &mut self.text_fit.wrap).build();
                 if ui.button("Search") {
                     if self.string_search.search == self.string_search.last {
                         self.string_search.times += 1;
@@ -548,51 +854,71 @@ impl ImguiRenderLoop for RenderLoop {
                         }
                     }
                     println!("========== Finished Searching ==========");
-                    let entry = &mut self.string_entry[self.string.item as usize];
-                    self.string_edit.clone_from(&entry.string);
-                    if entry.new_string.is_none() {
-                        let r = string_to_gmpointer(entry.string.clone());
-                        entry.new_string = Some((r.0.addr(), r.1, r.2));
-                        unsafe {
-                            *(entry.entry_ptr as *mut u32) = (r.0.addr() - entry.offset) as u32;
-                        }
-                    }
+                    // Selecting is read-only, the string is only copied once it's edited
+                    let entry = &self.string_entry[self.string.item as usize];
+                    self.string_edit = escape(&unsafe { entry.current() }, false);
+                    self.string_missing = missing_glyphs(&self.fonts, &self.string_edit);
                 }
                 ui.same_line();
                 ui.input_text("Search Strings", &mut self.string_search.search).build();
                 if ui.input_text_multiline("Edit String", &mut self.string_edit, [420.0, 150.0]).build() {
-                    let entry = &mut self.string_entry[self.string.item as usize];
                     unsafe {
-                        let old = entry.new_string;
-                        let r = string_to_gmpointer(self.string_edit.clone());
-                        entry.new_string = Some((r.0.addr(), r.1, r.2));
-                        *(entry.entry_ptr as *mut u32) = (r.0.addr() - entry.offset) as u32;
-                        if let Some(string) = old {
-                            drop(Vec::from_raw_parts(string.0 as *mut u8, string.1, string.2));
-                        }
+                        self.set_string(self.string.item as usize, unescape(&self.string_edit, false));
                     }
+                    self.string_missing = self.check_glyphs(self.string.item as usize, &self.string_edit);
                 }
-                if ui.list_box("String Data", &mut self.string.item, &self.string.items.iter().collect::<Vec<&String>>(), 10) {
-                    let entry = &mut self.string_entry[self.string.item as usize];
-                    self.string_edit.clone_from(&entry.string);
-                    if entry.new_string.is_none() {
-                        let r = string_to_gmpointer(entry.string.clone());
-                        entry.new_string = Some((r.0.addr(), r.1, r.2));
-                        unsafe {
-                            *(entry.entry_ptr as *mut u32) = (r.0.addr() - entry.offset) as u32;
+                if !self.string_missing.is_empty() {
+                    ui.text_colored([1., 0.2, 0.2, 1.], format!("Missing glyphs: {}", self.string_missing.iter().collect::<String>()));
+                }
+                if let Some((font, width, original)) = self.string_entry.get(self.string.item as usize).and_then(|_| self.string_widths(self.string.item as usize, &unescape(&self.string_edit, false))) {
+                    let text = format!("Width: {width}px in {} (original {original}px)", font.name);
+                    if self.overflows(width, original) {
+                        ui.text_colored([1., 0.5, 0.2, 1.], format!("{text}, over {:.0}% of the original", self.text_fit.ratio * 100.));
+                    } else {
+                        ui.text_disabled(text);
+                    }
+                }
+                if !self.string_entry.is_empty() {
+                    let text = String::from_utf8_lossy(&unescape(&self.string_edit, false)).to_string();
+                    let font = self.fit_font_index(self.string.item as usize).map(|i| &self.fonts[i]);
+                    self.preview.render(ui, self.form.as_ref(), &self.arena, &self.textures, font, &text);
+                }
+                if self.string_entry.get(self.string.item as usize).is_some_and(|e| unsafe { e.is_modified() }) {
+                    ui.text_colored([0.4, 0.7, 1., 1.], "Modified");
+                }
+                if self.string_entry.get(self.string.item as usize).is_some_and(|e| !e.is_utf8) {
+                    ui.text_colored([1., 0.8, 0., 1.], "Not valid UTF-8: invalid bytes are shown as \\xNN and kept as they are");
+                }
+                if let Some(entry) = self.string_entry.get(self.string.item as usize) {
+                    let uses = self.string_uses(entry);
+                    if uses.is_empty() {
+                        ui.text_disabled("Used by: nothing found");
+                    } else {
+                        ui.text(format!("Used by {} references:", uses.len()));
+                        for u in uses.iter().take(5) {
+                            ui.bullet_text(u.describe());
+                        }
+                        if uses.len() > 5 {
+                            ui.text_disabled(format!("... and {} more", uses.len() - 5));
                         }
                     }
                 }
+                if ui.list_box("String Data", &mut self.string.item, &self.string.items.iter().collect::<Vec<&String>>(), 10) {
+                    // Selecting is read-only, the string is only copied once it's edited
+                    let entry = &self.string_entry[self.string.item as usize];
+                    self.string_edit = escape(&unsafe { entry.current() }, false);
+                    self.string_missing = missing_glyphs(&self.fonts, &self.string_edit);
+                }
             });
     }
 }
 
-/// Result is: (Pointer, Size, Capacity)
-pub fn string_to_gmpointer(string: impl Into<String>) -> (*mut u8, usize, usize) {
+/// Length prefixed and NUL terminated, the way STRG stores them.
+pub fn string_to_gmdata(string: impl Into<Vec<u8>>) -> Vec<u8> {
     let string = string.into();
     let mut data = Vec::new();
     data.extend((string.len() as u32).to_le_bytes());
-    data.extend(string.as_bytes());
+    data.extend(&string);
     data.push(0);
-    data.into_raw_parts()
+    data
 }
@@ -1,8 +1,264 @@
 use hudhook::inject::Process;
-use std::env;
+use libdfmodtool::{asm::patch_file, atlas::{import_sprites, read_frames}, decompile::Decompiler, disasm::Disassembler, font::{export_fonts, import_fonts, parse_fonts}, fontgen::{file_characters, generate_font, parse_charset}, form::Form, sprite::{export_sprites, parse_sprites}, texture::{export_textures, parse_textures}};
+use std::{env, fs, path::PathBuf};
 
 fn main() {
-    let mut dllp = env::current_exe().unwrap().parent().unwrap().to_path_buf();
-    dllp.push("libdfmodtool.dll");
-    Process::by_name("DF CONNECTED v2.7.9c.exe").unwrap().inject(dllp).unwrap();
+    let args = env::args().collect::<Vec<String>>();
+    match args.get(1).map(|a| a.as_str()) {
+        Some("disasm") => disasm(&args[2..]),
+        Some("patch") => patch(&args[2..]),
+        Some("decompile") => decompile(&args[2..]),
+        Some("textures") => textures(&args[2..]),
+        Some("sprites") => sprites(&args[2..]),
+        Some("import-sprites") => import(&args[2..]),
+        Some("fonts") => fonts(&args[2..]),
+        Some("import-fonts") => import_font_files(&args[2..]),
+        Some("generate-font") => generate(&args[2..]),
+        Some(_) => {
+            println!("Usage: dfmodtool                                  Inject the mod tool in the game");
+            println!("       dfmodtool disasm <data.win> [output dir]   Disassemble every code entry");
+            println!("       dfmodtool patch <data.win> <output.win> <entry.asm>...");
+            println!("                                                  Assemble edited entries into a copy of data.win");
+            println!("       dfmodtool decompile <data.win> [output dir]");
+            println!("                                                  Decompile every code entry to GML");
+            println!("       dfmodtool textures <data.win> [output dir]");
+            println!("                                                  List or export the texture pages as PNG");
+            println!("       dfmodtool sprites <data.win> <output dir> [strip]");
+            println!("                                                  Export the sprite frames with a manifest");
+            println!("       dfmodtool import-sprites <data.win> <output.win> <frames dir>");
+            println!("                                                  Replace sprite frames and repack their texture pages");
+            println!("       dfmodtool fonts <data.win> <output dir>    Export the fonts as images and glyph maps");
+            println!("       dfmodtool import-fonts <data.win> <output.win> <fonts dir>");
+            println!("                                                  Replace fonts with edited images and glyph maps");
+            println!("       dfmodtool generate-font <data.win> <output.win> <font> <file.ttf> <size> [charset | strings.txt]");
+            println!("                                                  Replace a font with glyphs from a TTF/OTF file");
+        }
+        None => {
+            let mut dllp = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+            dllp.push("libdfmodtool.dll");
+            Process::by_name("DF CONNECTED v2.7.9c.exe").unwrap().inject(dllp).unwrap();
+        }
+    }
+}
+
+fn disasm(args: &[String]) {
+    let Some(path) = args.first() else {
+        println!("Missing the data.win path");
+        return;
+    };
+    let data = fs::read(path).unwrap();
+    let Some(form) = Form::new(&data) else {
+        println!("{path} is not a GameMaker data file");
+        return;
+    };
+    let disassembler = Disassembler::new(&form);
+    match args.get(1) {
+        Some(dir) => {
+            fs::create_dir_all(dir).unwrap();
+            for entry in &disassembler.code {
+                let mut file = PathBuf::from(dir);
+                file.push(format!("{}.asm", entry.name));
+                fs::write(file, disassembler.disassemble(&form, entry)).unwrap();
+            }
+            println!("Wrote {} code entries to {dir}", disassembler.code.len());
+        }
+        None => {
+            for entry in &disassembler.code {
+                println!("{}", disassembler.disassemble(&form, entry));
+            }
+        }
+    }
+}
+
+fn decompile(args: &[String]) {
+    let Some(path) = args.first() else {
+        println!("Missing the data.win path");
+        return;
+    };
+    let data = fs::read(path).unwrap();
+    let Some(form) = Form::new(&data) else {
+        println!("{path} is not a GameMaker data file");
+        return;
+    };
+    let disassembler = Disassembler::new(&form);
+    if let Some(dir) = args.get(1) {
+        fs::create_dir_all(dir).unwrap();
+    }
+    for entry in &disassembler.code {
+        let text = Decompiler::new(&disassembler, &form, entry).decompile();
+        match args.get(1) {
+            Some(dir) => {
+                let mut file = PathBuf::from(dir);
+                file.push(format!("{}.gml", entry.name));
+                fs::write(file, text).unwrap();
+            }
+            None => println!("// {}\n{text}", entry.name),
+        }
+    }
+    if let Some(dir) = args.get(1) {
+        println!("Wrote {} code entries to {dir}", disassembler.code.len());
+    }
+}
+
+fn textures(args: &[String]) {
+    let Some(path) = args.first() else {
+        println!("Missing the data.win path");
+        return;
+    };
+    let data = fs::read(path).unwrap();
+    let Some(form) = Form::new(&data) else {
+        println!("{path} is not a GameMaker data file");
+        return;
+    };
+    let pages = parse_textures(&form);
+    match args.get(1) {
+        Some(dir) => match export_textures(&form, &pages, &PathBuf::from(dir)) {
+            Ok(count) => println!("Wrote {count} texture pages to {dir}"),
+            Err(e) => println!("Could not export the texture pages: {e}"),
+        },
+        None => {
+            for page in &pages {
+                println!("Texture {}: {}x{} {}, {} bytes at 0x{:x}", page.index, page.width, page.height, page.format.name(), page.size, page.data);
+            }
+        }
+    }
+}
+
+fn sprites(args: &[String]) {
+    let (Some(path), Some(dir)) = (args.first(), args.get(1)) else {
+        println!("Missing the data.win path or the output directory");
+        return;
+    };
+    let data = fs::read(path).unwrap();
+    let Some(form) = Form::new(&data) else {
+        println!("{path} is not a GameMaker data file");
+        return;
+    };
+    let strip = args.get(2).is_some_and(|a| a == "strip");
+    match export_sprites(&form, &parse_textures(&form), &parse_sprites(&form), &PathBuf::from(dir), strip) {
+        Ok(count) => println!("Wrote {count} sprites to {dir}"),
+        Err(e) => println!("Could not export the sprites: {e}"),
+    }
+}
+
+fn import(args: &[String]) {
+    let [path, output, dir] = args else {
+        println!("Usage: dfmodtool import-sprites <data.win> <output.win> <frames dir>");
+        return;
+    };
+    let mut data = fs::read(path).unwrap();
+    let Some(form) = Form::new(&data) else {
+        println!("{path} is not a GameMaker data file");
+        return;
+    };
+    let frames = match read_frames(&PathBuf::from(dir), &parse_sprites(&form)) {
+        Ok(frames) => frames,
+        Err(e) => {
+            println!("Could not read the frames: {e}");
+            return;
+        }
+    };
+    match import_sprites(&mut data, &frames) {
+        Ok(count) => {
+            fs::write(output, data).unwrap();
+            println!("Replaced {count} sprites, wrote {output}");
+        }
+        Err(e) => println!("Could not import the sprites: {e}"),
+    }
+}
+
+fn fonts(args: &[String]) {
+    let (Some(path), Some(dir)) = (args.first(), args.get(1)) else {
+        println!("Missing the data.win path or the output directory");
+        return;
+    };
+    let data = fs::read(path).unwrap();
+    let Some(form) = Form::new(&data) else {
+        println!("{path} is not a GameMaker data file");
+        return;
+    };
+    match export_fonts(&form, &parse_textures(&form), &parse_fonts(&form), &PathBuf::from(dir)) {
+        Ok(count) => println!("Wrote {count} fonts to {dir}"),
+        Err(e) => println!("Could not export the fonts: {e}"),
+    }
+}
+
+fn import_font_files(args: &[String]) {
+    let [path, output, dir] = args else {
+        println!("Usage: dfmodtool import-fonts <data.win> <output.win> <fonts dir>");
+        return;
+    };
+    let mut data = fs::read(path).unwrap();
+    match import_fonts(&mut data, &PathBuf::from(dir)) {
+        Ok(count) => {
+            fs::write(output, data).unwrap();
+            println!("Replaced {count} fonts, wrote {output}");
+        }
+        Err(e) => println!("Could not import the fonts: {e}"),
+    }
+}
+
+fn generate(args: &[String]) {
+    let [path, output, name, file, size, rest @ ..] = args else {
+        println!("Usage: dfmodtool generate-font <data.win> <output.win> <font> <file.ttf> <size> [charset | strings.txt]");
+        return;
+    };
+    let Ok(size) = size.parse::<f32>() else {
+        println!("{size} is not a font size");
+        return;
+    };
+    // A strings file exported by the overlay gives exactly the characters of the translation
+    let charset = rest.first().map_or("ascii", |c| c.as_str());
+    let chars = match PathBuf::from(charset).is_file() {
+        true => file_characters(&PathBuf::from(charset)),
+        false => parse_charset(charset),
+    };
+    let chars = match chars {
+        Ok(chars) => chars,
+        Err(e) => {
+            println!("Invalid charset: {e}");
+            return;
+        }
+    };
+    let mut data = fs::read(path).unwrap();
+    match generate_font(&mut data, name, &fs::read(file).unwrap(), size, &chars) {
+        Ok(count) => {
+            fs::write(output, data).unwrap();
+            println!("Generated {count} glyphs for {name}, wrote {output}");
+        }
+        Err(e) => println!("Could not generate font {name}: {e}"),
+    }
+}
+
+fn patch(args: &[String]) {
+    let [input, output, files @ ..] = args else {
+        println!("Missing the data.win and output paths");
+        return;
+    };
+    let mut data = fs::read(input).unwrap();
+    for file in files {
+        let text = fs::read_to_string(file).unwrap();
+        // Reparse after every patch, the references moved
+        let Some(form) = Form::new(&data) else {
+            println!("{input} is not a GameMaker data file");
+            return;
+        };
+        let disassembler = Disassembler::new(&form);
+        // The disassembler header names the entry, otherwise the file name does
+        let name = text.lines().next().and_then(|l| l.strip_prefix("; ")).and_then(|l| l.split(' ').next())
+            .map(|n| n.to_string())
+            .unwrap_or(PathBuf::from(file).file_stem().unwrap().to_string_lossy().to_string());
+        let Some(entry) = disassembler.code.iter().find(|e| e.name == name).cloned() else {
+            println!("{file}: no code entry is named {name}");
+            return;
+        };
+        let result = disassembler.assemble(&text).and_then(|assembled| patch_file(&mut data, &disassembler, &entry, &assembled));
+        if let Err(e) = result {
+            println!("{file}: {e}");
+            return;
+        }
+        println!("Patched {name}");
+    }
+    fs::write(output, data).unwrap();
+    println!("Wrote {output}");
 }
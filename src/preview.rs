@@ -0,0 +1,125 @@
+use hudhook::{imgui, RenderContext};
+
+use crate::arena::Arena;
+use crate::font::Font;
+use crate::form::Form;
+use crate::sprite::page_item;
+use crate::texture::{decode_texture, Image, TexturePanel};
+
+/// Draws `text` with the glyphs of `font`, cut from `page` where the font region starts at `origin`.
+/// Lines are as tall as the tallest glyph, characters without a glyph are skipped like the runner does.
+pub fn draw_text(font: &Font, page: &Image, origin: (u32, u32), text: &str) -> Image {
+    let line_height = font.glyphs.iter().map(|g| g.height as i32).max().unwrap_or(0);
+    let mut placed = Vec::new();
+    for (row, line) in text.split('\n').enumerate() {
+        let mut x = 0;
+        let mut previous = None;
+        for c in line.trim_end_matches('\r').chars() {
+            let Some(glyph) = font.glyph(c) else {
+                continue;
+            };
+            if let Some(previous) = previous {
+                x += glyph.kerning.iter().find(|k| k.0 as u32 == previous as u32).map_or(0, |k| k.1 as i32);
+            }
+            placed.push((x + glyph.offset as i32, row as i32 * line_height, glyph));
+            x += glyph.shift as i32;
+            previous = Some(c);
+        }
+    }
+    // Glyphs with a negative offset can start left of the pen
+    let left = placed.iter().map(|p| p.0).min().unwrap_or(0).min(0);
+    let width = placed.iter().map(|p| p.0 - left + p.2.width as i32).max().unwrap_or(0);
+    let height = text.split('\n').count() as i32 * line_height;
+    let mut image = Image::new(width.max(1) as u32, height.max(1) as u32);
+    for (x, y, glyph) in placed {
+        image.blend(page, (origin.0 + glyph.x as u32, origin.1 + glyph.y as u32), (glyph.width as u32, glyph.height as u32), ((x - left) as u32, y as u32));
+    }
+    image
+}
+
+/// Shows the edited string drawn with the glyphs of a game font, at the scale of the font.
+///
+/// The text is drawn on the CPU and uploaded before the next frame. Textures can't be freed, so a new one
+/// is only made when the text outgrows the current one.
+#[derive(Default)]
+pub struct StringPreview {
+    texture: Option<(imgui::TextureId, u32, u32)>, // Texture and its size
+    pending: Option<Image>, // Waiting to be uploaded
+    shown: (u32, u32), // Size of the text in the texture
+    key: String, // What the texture shows
+    page: Option<(usize, Image)>, // Decoded page of the font, by blob address
+    zoom: f32,
+}
+
+impl StringPreview {
+    pub fn load(&mut self) {
+        self.zoom = 1.;
+    }
+
+    /// Uploads the text drawn since the last frame, from `before_render`.
+    pub fn upload(&mut self, render_context: &mut dyn RenderContext) {
+        let Some(image) = self.pending.take() else {
+            return;
+        };
+        let (width, height) = match self.texture {
+            Some((_, width, height)) if image.width <= width && image.height <= height => (width, height),
+            _ => (image.width.next_power_of_two().max(64), image.height.next_power_of_two().max(64)),
+        };
+        let mut padded = Image::new(width, height);
+        padded.blit(&image, (0, 0), (image.width, image.height), (0, 0));
+        match self.texture {
+            Some((id, w, h)) if (w, h) == (width, height) => {
+                if let Err(e) = render_context.replace_texture(id, &padded.pixels, width, height) {
+                    println!("Could not update the string preview: {e:?}");
+                }
+            }
+            _ => match render_context.load_texture(&padded.pixels, width, height) {
+                Ok(id) => self.texture = Some((id, width, height)),
+                Err(e) => println!("Could not create the string preview texture: {e:?}"),
+            },
+        }
+        self.shown = (image.width, image.height);
+    }
+
+    pub fn render(&mut self, ui: &imgui::Ui, form: Option<&Form>, arena: &Arena, textures: &TexturePanel, font: Option<&Font>, text: &str) {
+        let (Some(form), Some(font)) = (form, font) else {
+            ui.text_disabled("Preview: no font is known for this string, choose one to measure with");
+            return;
+        };
+        let Some(item) = page_item(form, font.tpag) else {
+            ui.text_disabled(format!("Preview: font {} has an invalid TPAG entry", font.name));
+            return;
+        };
+        let Some(data) = textures.pages.iter().find(|p| p.index as i16 == item.page).and_then(|p| textures.page_data(form, arena, p)) else {
+            ui.text_disabled(format!("Preview: texture page {} is not embedded", item.page));
+            return;
+        };
+        // Redrawn when the text, the font or its page change
+        let key = format!("{}\0{}\0{:x}\0{text}", font.name, font.glyphs.len(), data.as_ptr().addr());
+        if key != self.key {
+            if self.page.as_ref().map(|p| p.0) != Some(data.as_ptr().addr()) {
+                match decode_texture(data) {
+                    Ok(image) => self.page = Some((data.as_ptr().addr(), image)),
+                    Err(e) => println!("Could not decode texture page {}: {e}", item.page),
+                }
+            }
+            if let Some((_, page)) = &self.page {
+                self.pending = Some(draw_text(font, page, (item.source[0] as u32, item.source[1] as u32), text));
+            }
+            self.key = key;
+        }
+        ui.text(format!("Preview in {}", font.name));
+        ui.same_line();
+        ui.set_next_item_width(80.);
+        ui.input_float("Zoom##preview", &mut self.zoom).build();
+        let Some((id, width, height)) = self.texture else {
+            return;
+        };
+        let (w, h) = self.shown;
+        let scale = font.scale.map(|s| if s > 0. { s * self.zoom } else { self.zoom });
+        let size = [w as f32 * scale[0], h as f32 * scale[1]];
+        ui.child_window("##preview").size([0., (size[1] + 16.).min(200.)]).horizontal_scrollbar(true).build(|| {
+            imgui::Image::new(id, size).uv1([w as f32 / width as f32, h as f32 / height as f32]).build(ui);
+        });
+    }
+}
@@ -0,0 +1,209 @@
+use crate::texture::Image;
+
+// GameMaker uses an early draft of QOI, with a reversed magic and 16 bit sizes
+pub const MAGIC: &[u8; 4] = b"fioq";
+const INDEX: u8 = 0x00; // 00xxxxxx
+const RUN_8: u8 = 0x40; // 010xxxxx
+const RUN_16: u8 = 0x60; // 011xxxxx
+const DIFF_8: u8 = 0x80; // 10xxxxxx
+const DIFF_16: u8 = 0xc0; // 110xxxxx
+const DIFF_24: u8 = 0xe0; // 1110xxxx
+const COLOR: u8 = 0xf0; // 1111xxxx
+const MASK_2: u8 = 0xc0;
+const MASK_3: u8 = 0xe0;
+const MASK_4: u8 = 0xf0;
+const HEADER: usize = 12;
+
+fn hash(pixel: [u8; 4]) -> usize {
+    (pixel[0] ^ pixel[1] ^ pixel[2] ^ pixel[3]) as usize % 64
+}
+
+fn next(data: &[u8], p: &mut usize) -> u8 {
+    *p += 1;
+    data.get(*p - 1).copied().unwrap_or(0)
+}
+
+/// Width and height from the header.
+pub fn size(data: &[u8]) -> Option<(u32, u32)> {
+    if data.get(..4)? != MAGIC {
+        return None;
+    }
+    Some((u16::from_le_bytes([*data.get(4)?, *data.get(5)?]) as u32, u16::from_le_bytes([*data.get(6)?, *data.get(7)?]) as u32))
+}
+
+/// Length of an image with its header.
+pub fn length(data: &[u8]) -> Option<usize> {
+    size(data)?;
+    Some(HEADER + u32::from_le_bytes(data.get(8..12)?.try_into().unwrap()) as usize)
+}
+
+pub fn decode(data: &[u8]) -> Result<Image, String> {
+    let (width, height) = size(data).ok_or("Not a QOI image")?;
+    let end = length(data).ok_or("Truncated QOI header")?.min(data.len());
+    let mut image = Image::new(width, height);
+    let mut index = [[0u8; 4]; 64];
+    let mut pixel = [0, 0, 0, 255];
+    let mut run = 0;
+    let mut p = HEADER;
+    for out in image.pixels.chunks_mut(4) {
+        if run > 0 {
+            run -= 1;
+        } else if p < end {
+            let b1 = next(data, &mut p);
+            if b1 & MASK_2 == INDEX {
+                pixel = index[(b1 ^ INDEX) as usize];
+            } else if b1 & MASK_3 == RUN_8 {
+                run = (b1 & 0x1f) as u32;
+            } else if b1 & MASK_3 == RUN_16 {
+                run = (((b1 & 0x1f) as u32) << 8 | next(data, &mut p) as u32) + 32;
+            } else if b1 & MASK_2 == DIFF_8 {
+                pixel[0] = pixel[0].wrapping_add((b1 >> 4 & 0x03).wrapping_sub(2));
+                pixel[1] = pixel[1].wrapping_add((b1 >> 2 & 0x03).wrapping_sub(2));
+                pixel[2] = pixel[2].wrapping_add((b1 & 0x03).wrapping_sub(2));
+            } else if b1 & MASK_3 == DIFF_16 {
+                let b2 = next(data, &mut p);
+                pixel[0] = pixel[0].wrapping_add((b1 & 0x1f).wrapping_sub(16));
+                pixel[1] = pixel[1].wrapping_add((b2 >> 4).wrapping_sub(8));
+                pixel[2] = pixel[2].wrapping_add((b2 & 0x0f).wrapping_sub(8));
+            } else if b1 & MASK_4 == DIFF_24 {
+                let (b2, b3) = (next(data, &mut p), next(data, &mut p));
+                pixel[0] = pixel[0].wrapping_add(((b1 & 0x0f) << 1 | b2 >> 7).wrapping_sub(16));
+                pixel[1] = pixel[1].wrapping_add(((b2 & 0x7c) >> 2).wrapping_sub(16));
+                pixel[2] = pixel[2].wrapping_add(((b2 & 0x03) << 3 | (b3 & 0xe0) >> 5).wrapping_sub(16));
+                pixel[3] = pixel[3].wrapping_add((b3 & 0x1f).wrapping_sub(16));
+            } else if b1 & MASK_4 == COLOR {
+                for (channel, bit) in [8, 4, 2, 1].into_iter().enumerate() {
+                    if b1 & bit != 0 {
+                        pixel[channel] = next(data, &mut p);
+                    }
+                }
+            }
+            index[hash(pixel)] = pixel;
+        }
+        out.copy_from_slice(&pixel);
+    }
+    Ok(image)
+}
+
+pub fn encode(image: &Image) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER + image.pixels.len() / 2);
+    data.extend(MAGIC);
+    data.extend((image.width as u16).to_le_bytes());
+    data.extend((image.height as u16).to_le_bytes());
+    data.extend([0; 4]);
+    let mut index = [[0u8; 4]; 64];
+    let mut previous = [0, 0, 0, 255];
+    let mut run = 0;
+    let count = image.pixels.len() / 4;
+    for (i, pixel) in image.pixels.chunks(4).enumerate() {
+        let pixel: [u8; 4] = pixel.try_into().unwrap();
+        if pixel == previous {
+            run += 1;
+        }
+        if run > 0 && (run == 0x2020 || pixel != previous || i == count - 1) {
+            if run < 33 {
+                data.push(RUN_8 | (run - 1) as u8);
+            } else {
+                run -= 33;
+                data.push(RUN_16 | (run >> 8) as u8);
+                data.push(run as u8);
+            }
+            run = 0;
+        }
+        if pixel != previous {
+            let position = hash(pixel);
+            if index[position] == pixel {
+                data.push(INDEX | position as u8);
+            } else {
+                index[position] = pixel;
+                let [r, g, b, a] = [0, 1, 2, 3].map(|c| pixel[c] as i32 - previous[c] as i32);
+                if [r, g, b, a].iter().all(|v| (-16..16).contains(v)) {
+                    if a == 0 && [r, g, b].iter().all(|v| (-2..2).contains(v)) {
+                        data.push(DIFF_8 | ((r + 2) << 4 | (g + 2) << 2 | (b + 2)) as u8);
+                    } else if a == 0 && [g, b].iter().all(|v| (-8..8).contains(v)) {
+                        data.push(DIFF_16 | (r + 16) as u8);
+                        data.push(((g + 8) << 4 | (b + 8)) as u8);
+                    } else {
+                        data.push(DIFF_24 | ((r + 16) >> 1) as u8);
+                        data.push(((r + 16) << 7 | (g + 16) << 2 | (b + 16) >> 3) as u8);
+                        data.push(((b + 16) << 5 | (a + 16)) as u8);
+                    }
+                } else {
+                    let changed = [r, g, b, a].map(|v| v != 0);
+                    data.push(COLOR | changed.iter().zip([8, 4, 2, 1]).filter(|(c, _)| **c).map(|(_, bit)| bit).sum::<u8>());
+                    for channel in 0..4 {
+                        if changed[channel] {
+                            data.push(pixel[channel]);
+                        }
+                    }
+                }
+            }
+        }
+        previous = pixel;
+    }
+    let length = (data.len() - HEADER) as u32;
+    data[8..12].copy_from_slice(&length.to_le_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::{decode_texture, encode_texture, TextureFormat};
+
+    fn image(pixels: &[[u8; 4]]) -> Image {
+        Image { width: pixels.len() as u32, height: 1, pixels: pixels.concat() }
+    }
+
+    /// Tag of the first operation after the header, for a single pixel after the implicit black one.
+    fn first_tag(pixel: [u8; 4], mask: u8) -> u8 {
+        encode(&image(&[pixel]))[HEADER] & mask
+    }
+
+    #[test]
+    fn operations() {
+        assert_eq!(first_tag([0, 0, 0, 255], MASK_3), RUN_8);
+        assert_eq!(first_tag([1, 0, 1, 255], MASK_2), DIFF_8);
+        assert_eq!(first_tag([10, 5, 0, 255], MASK_3), DIFF_16);
+        assert_eq!(first_tag([0, 12, 0, 250], MASK_4), DIFF_24);
+        assert_eq!(first_tag([200, 0, 0, 255], MASK_4), COLOR);
+        let data = encode(&image(&[[1, 0, 0, 255], [90, 90, 90, 255], [1, 0, 0, 255]]));
+        assert_eq!(data[data.len() - 1], INDEX | hash([1, 0, 0, 255]) as u8);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut pixels = Vec::new();
+        for run in [1, 32, 33, 40, 0x2020, 0x2020 + 7] {
+            pixels.extend(vec![[run as u8, 3, 7, 255]; run]);
+            pixels.push([0, 0, 0, 255]);
+        }
+        for i in 0..600u32 {
+            // Steps of 1 with drops of 3 to 29 cover the diff sizes, and the colors repeat for the index
+            pixels.push([100 + i % 4, 100 + i % 12, 100 + i % 30, 255].map(|v| v as u8));
+        }
+        for i in 0..200u32 {
+            pixels.push([i * 40, i * 7 % 19, i * 13 % 37 + 100, 255 - i % 20].map(|v| v as u8));
+        }
+        let image = image(&pixels);
+        let decoded = decode(&encode(&image)).unwrap();
+        assert_eq!((decoded.width, decoded.height), (image.width, image.height));
+        assert!(decoded.pixels == image.pixels);
+    }
+
+    #[test]
+    fn bz2_round_trip() {
+        let image = Image {
+            width: 37,
+            height: 23,
+            pixels: (0..37 * 23 * 4).map(|i| (i * i / 7 % 256) as u8).collect(),
+        };
+        for sized in [false, true] {
+            let data = encode_texture(&image, TextureFormat::Bz2Qoi { sized }).unwrap();
+            assert_eq!(TextureFormat::detect(&data), Some(TextureFormat::Bz2Qoi { sized }));
+            let decoded = decode_texture(&data).unwrap();
+            assert_eq!((decoded.width, decoded.height), (37, 23));
+            assert!(decoded.pixels == image.pixels);
+        }
+    }
+}
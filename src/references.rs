@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use crate::code::{bytecode_version, instructions, parse_code, parse_functions, parse_variables, OP_CALL, OP_CONV, OP_PUSH, OP_PUSHI, TYPE_INT16, TYPE_INT32, TYPE_STRING};
+use crate::disasm::Disassembler;
+use crate::form::Form;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum UseKind {
+    Object,
+    Room,
+    RoomCaption,
+    Sprite,
+    Sound,
+    SoundFile,
+    Font,
+    Variable,
+    Function,
+    CodeName,
+    CodeLiteral,
+}
+
+#[derive(Clone)]
+pub struct StringUse {
+    pub kind: UseKind,
+    pub user: String,
+}
+
+impl StringUse {
+    /// Names of the assets in OBJT, ROOM, SPRT, SOND and FONT, not variables, functions or captions.
+    pub fn is_asset_name(&self) -> bool {
+        matches!(self.kind, UseKind::Object | UseKind::Room | UseKind::Sprite | UseKind::Sound | UseKind::Font)
+    }
+
+    pub fn describe(&self) -> String {
+        match self.kind {
+            UseKind::Object => "Object name".to_string(),
+            UseKind::Room => "Room name".to_string(),
+            UseKind::RoomCaption => format!("Caption of {}", self.user),
+            UseKind::Sprite => "Sprite name".to_string(),
+            UseKind::Sound => "Sound name".to_string(),
+            UseKind::SoundFile => format!("File name of {}", self.user),
+            UseKind::Font => "Font name".to_string(),
+            UseKind::Variable => "Variable name".to_string(),
+            UseKind::Function => "Function name".to_string(),
+            UseKind::CodeName => "Code entry name".to_string(),
+            UseKind::CodeLiteral => format!("Code literal in {}", self.user),
+        }
+    }
+}
+
+/// Builds a reverse index from STRG index to everything that references that string.
+pub fn string_references(form: &Form) -> HashMap<usize, Vec<StringUse>> {
+    let mut references: HashMap<usize, Vec<StringUse>> = HashMap::new();
+    let Some(strg) = form.chunk(b"STRG") else {
+        return references;
+    };
+    // Chunks reference strings by the address of their characters, after the length prefix
+    let indices = form.pointer_list(strg.offset).unwrap_or_default()
+        .into_iter().enumerate()
+        .map(|(index, ptr)| (ptr + 4, index))
+        .collect::<HashMap<usize, usize>>();
+    let mut add = |ptr: Option<u32>, kind: UseKind, user: &str| {
+        if let Some(index) = ptr.and_then(|p| indices.get(&(p as usize))) {
+            references.entry(*index).or_default().push(StringUse {
+                kind,
+                user: user.to_string(),
+            });
+        }
+    };
+
+    let named = [(b"OBJT", UseKind::Object), (b"ROOM", UseKind::Room), (b"SPRT", UseKind::Sprite), (b"SOND", UseKind::Sound), (b"FONT", UseKind::Font)];
+    for (name, kind) in named {
+        let Some(chunk) = form.chunk(name) else {
+            continue;
+        };
+        for ptr in form.pointer_list(chunk.offset).unwrap_or_default() {
+            let user = form.u32(ptr).and_then(|p| form.string(p as usize)).unwrap_or_default();
+            add(form.u32(ptr), kind, &user);
+            match kind {
+                UseKind::Room => add(form.u32(ptr + 4), UseKind::RoomCaption, &user),
+                UseKind::Sound => add(form.u32(ptr + 12), UseKind::SoundFile, &user),
+                _ => {}
+            }
+        }
+    }
+    for variable in parse_variables(form) {
+        add(form.u32(variable.ptr), UseKind::Variable, &variable.name);
+    }
+    for function in parse_functions(form) {
+        add(form.u32(function.ptr), UseKind::Function, &function.name);
+    }
+    let code = parse_code(form);
+    let version = bytecode_version(form);
+    for entry in &code {
+        add(form.u32(entry.ptr), UseKind::CodeName, &entry.name);
+    }
+    for entry in &code {
+        // Functions declared inside a script share its bytecode, only walk it once
+        if entry.offset != 0 {
+            continue;
+        }
+        for instruction in instructions(form.data, entry.bytecode, entry.length, version) {
+            if instruction.opcode == OP_PUSH && instruction.type1 == TYPE_STRING {
+                // Pushed strings are stored as STRG indices
+                if let Some(index) = instruction.operand_u32() {
+                    references.entry(index as usize).or_default().push(StringUse {
+                        kind: UseKind::CodeLiteral,
+                        user: entry.name.clone(),
+                    });
+                }
+            }
+        }
+    }
+    references
+}
+
+/// Font each code literal is drawn with: the last `draw_set_font` of a constant font before the literal
+/// in the same code entry. Strings pushed in several entries keep the first font found.
+pub fn string_fonts(form: &Form) -> HashMap<usize, String> {
+    let mut fonts = HashMap::new();
+    let names = form.chunk(b"FONT").and_then(|c| form.pointer_list(c.offset)).unwrap_or_default().into_iter()
+        .map(|ptr| form.u32(ptr).and_then(|p| form.string(p as usize)).unwrap_or_default())
+        .collect::<Vec<String>>();
+    let disassembler = Disassembler::new(form);
+    for entry in &disassembler.code {
+        if entry.offset != 0 {
+            continue;
+        }
+        let mut font = None;
+        let mut constant = None;
+        for instruction in instructions(form.data, entry.bytecode, entry.length, disassembler.version) {
+            match (instruction.opcode, instruction.type1) {
+                (OP_PUSHI, TYPE_INT16) => constant = Some(instruction.value as i16 as i32),
+                (OP_PUSH, TYPE_INT32) => constant = instruction.operand_u32().map(|v| v as i32),
+                (OP_PUSH, TYPE_STRING) => {
+                    if let (Some(font), Some(index)) = (font, instruction.operand_u32()) {
+                        fonts.entry(index as usize).or_insert_with(|| names.get(font as usize).cloned().unwrap_or_default());
+                    }
+                    constant = None;
+                }
+                (OP_CALL, _) if disassembler.function_name(&instruction) == Some("draw_set_font") => {
+                    // A font from a variable is unknown, the strings after it are not measured
+                    font = constant.take();
+                }
+                // The constant is converted before it is passed
+                (OP_CONV, _) => {}
+                _ => constant = None,
+            }
+        }
+    }
+    fonts.retain(|_, name| !name.is_empty());
+    fonts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::{OP_EXIT, OP_POPZ, TYPE_VARIABLE};
+    use crate::form::builder::{put, word, words, FormBuilder};
+
+    #[test]
+    fn fonts_through_conv() {
+        let program = [
+            word(OP_PUSHI, 0, TYPE_INT16, 1), // 00: fnt_b
+            word(OP_CONV, TYPE_VARIABLE, TYPE_INT32, 0),
+            word(OP_CALL, 0, TYPE_INT32, 1), 0, // 08
+            word(OP_POPZ, 0, TYPE_VARIABLE, 0),
+            word(OP_PUSH, 0, TYPE_STRING, 0), 4, // 14: "hello"
+            word(OP_POPZ, 0, TYPE_VARIABLE, 0),
+            word(OP_PUSH, 0, TYPE_STRING, 0), 5, // 20: "x", still drawn with fnt_b
+            word(OP_CONV, TYPE_VARIABLE, TYPE_STRING, 0),
+            word(OP_CALL, 0, TYPE_INT32, 1), 0, // 2C: a font that isn't a constant
+            word(OP_POPZ, 0, TYPE_VARIABLE, 0),
+            word(OP_PUSH, 0, TYPE_STRING, 0), 6, // 38: "after"
+            word(OP_POPZ, 0, TYPE_VARIABLE, 0),
+            word(OP_EXIT, 0, TYPE_INT32, 0),
+        ];
+        let mut builder = FormBuilder::new(17, &["draw_set_font", "gml_Script_draw", "fnt_a", "fnt_b", "hello", "x", "after"]);
+        let (fnt_a, fnt_b) = (builder.string(2), builder.string(3));
+        builder.chunk(b"FONT", |d| {
+            let entries = d.len() as u32 + 12;
+            put(d, &[2, entries, entries + 4, fnt_a, fnt_b]);
+        });
+        let start = builder.code(&[(1, &words(&program))])[0];
+        builder.functions(&[(0, &[start + 0x08, start + 0x2c])]);
+        let data = builder.build();
+
+        let form = Form::new(&data).unwrap();
+        let fonts = string_fonts(&form);
+        assert_eq!(fonts.get(&4).map(|f| f.as_str()), Some("fnt_b"));
+        assert_eq!(fonts.get(&5).map(|f| f.as_str()), Some("fnt_b"));
+        assert_eq!(fonts.len(), 2);
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use hudhook::imgui;
+
+use crate::form::Form;
+use crate::hooks::{goto_room, hook_room_goto, last_room, resolve};
+use crate::watch::WatchList;
+
+#[derive(Default, Clone)]
+pub struct Room {
+    pub name: String,
+    pub caption: String,
+    pub width: u32,
+    pub height: u32,
+    pub speed: u32,
+    pub persistent: bool,
+    pub backgrounds: Vec<RoomBackground>,
+    pub instances: Vec<RoomInstance>,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct RoomBackground {
+    pub enabled: bool,
+    pub foreground: bool,
+    pub definition: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct RoomInstance {
+    pub x: i32,
+    pub y: i32,
+    pub object: i32,
+    pub id: u32,
+}
+
+pub fn parse_rooms(form: &Form) -> Vec<Room> {
+    let Some(chunk) = form.chunk(b"ROOM") else {
+        return Vec::new();
+    };
+    let mut rooms = Vec::new();
+    for ptr in form.pointer_list(chunk.offset).unwrap_or_default() {
+        let room = (|| Some(Room {
+            name: form.string(form.u32(ptr)? as usize).unwrap_or_default(),
+            caption: form.string(form.u32(ptr + 4)? as usize).unwrap_or_default(),
+            width: form.u32(ptr + 8)?,
+            height: form.u32(ptr + 12)?,
+            speed: form.u32(ptr + 16)?,
+            persistent: form.u32(ptr + 20)? != 0,
+            // Colors, creation code and flags come before the lists
+            backgrounds: form.pointer_list(form.u32(ptr + 40)? as usize)?.into_iter().filter_map(|b| Some(RoomBackground {
+                enabled: form.u32(b)? != 0,
+                foreground: form.u32(b + 4)? != 0,
+                definition: form.i32(b + 8)?,
+                x: form.i32(b + 12)?,
+                y: form.i32(b + 16)?,
+            })).collect(),
+            instances: form.pointer_list(form.u32(ptr + 48)? as usize)?.into_iter().filter_map(|i| Some(RoomInstance {
+                x: form.i32(i)?,
+                y: form.i32(i + 4)?,
+                object: form.i32(i + 8)?,
+                id: form.u32(i + 12)?,
+            })).collect(),
+        }))();
+        match room {
+            Some(room) => rooms.push(room),
+            None => {
+                println!("Invalid room at 0x{ptr:x}");
+                rooms.push(Room::default());
+            }
+        }
+    }
+    rooms
+}
+
+/// Names of the OBJT entries, by object index.
+pub fn object_names(form: &Form) -> Vec<String> {
+    let Some(chunk) = form.chunk(b"OBJT") else {
+        return Vec::new();
+    };
+    form.pointer_list(chunk.offset).unwrap_or_default().into_iter()
+        .map(|ptr| form.u32(ptr).and_then(|p| form.string(p as usize)).unwrap_or_default())
+        .collect()
+}
+
+/// Lists the rooms and jumps to them through the `room_goto` built-in.
+#[derive(Default)]
+pub struct RoomBrowser {
+    pub rooms: Vec<Room>,
+    objects: Vec<String>,
+    filter: String,
+    selected: usize,
+}
+
+impl RoomBrowser {
+    pub fn load(&mut self, form: &Form) {
+        self.rooms = parse_rooms(form);
+        self.objects = object_names(form);
+        println!("Found {} rooms", self.rooms.len());
+    }
+
+    pub fn render(&mut self, ui: &imgui::Ui, watch: &WatchList) {
+        ui.set_next_item_width(150.);
+        ui.input_text("Filter##room", &mut self.filter).build();
+        if let Some(room) = last_room() {
+            ui.same_line();
+            ui.text(format!("Last room_goto: {}", self.rooms.get(room as usize).map(|r| r.name.as_str()).unwrap_or("?")));
+        }
+        let filter = self.filter.to_lowercase();
+        ui.child_window("##rooms").size([0., 120.]).build(|| {
+            for (i, room) in self.rooms.iter().enumerate() {
+                if !room.name.to_lowercase().contains(&filter) {
+                    continue;
+                }
+                if ui.selectable_config(format!("{i}: {} ({}x{})##room{i}", room.name, room.width, room.height)).selected(self.selected == i).build() {
+                    self.selected = i;
+                }
+            }
+        });
+        let Some(room) = self.rooms.get(self.selected) else {
+            return;
+        };
+        if ui.button("Go to room") {
+            match resolve(watch, "room_goto").and_then(hook_room_goto).and_then(|_| goto_room(self.selected)) {
+                Ok(()) => println!("========== Going to room {} ==========", room.name),
+                Err(e) => println!("Could not go to room {}: {e}", room.name),
+            }
+        }
+        if !room.caption.is_empty() {
+            ui.text(format!("Caption: {}", room.caption));
+        }
+        ui.text(format!("Size: {}x{}, Speed: {}{}", room.width, room.height, room.speed, if room.persistent { ", Persistent" } else { "" }));
+        for background in room.backgrounds.iter().filter(|b| b.enabled) {
+            ui.bullet_text(format!("{} {} at {}, {}", if background.foreground { "Foreground" } else { "Background" }, background.definition, background.x, background.y));
+        }
+        let mut counts: HashMap<i32, usize> = HashMap::new();
+        for instance in &room.instances {
+            *counts.entry(instance.object).or_default() += 1;
+        }
+        let mut counts = counts.into_iter().collect::<Vec<(i32, usize)>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        ui.text(format!("{} instances", room.instances.len()));
+        ui.child_window("##instances").size([0., 100.]).build(|| {
+            for (object, count) in counts {
+                let name = usize::try_from(object).ok().and_then(|o| self.objects.get(o)).map(|n| n.as_str()).unwrap_or("?");
+                ui.bullet_text(format!("{count}x {name}"));
+            }
+        });
+    }
+}
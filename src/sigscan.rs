@@ -0,0 +1,232 @@
+use std::ops::Range;
+use mmap_rs::{MemoryAreas, Protection};
+
+use crate::address::Modules;
+
+/// An IDA-style byte pattern, `??` (or `?`) matches any byte: `A1 ?? ?? ?? ?? 85 C0`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Signature {
+    pub bytes: Vec<Option<u8>>,
+}
+
+/// What to take from a match.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Extract {
+    /// Address of the match plus an offset.
+    Match(usize),
+    /// Absolute pointer stored at `match + offset`, like the operand of `mov eax, [addr]`.
+    Absolute(usize),
+    /// 32 bit displacement at `match + offset`, relative to `match + end` (the end of the instruction),
+    /// like `call rel32` or RIP-relative operands.
+    Relative { offset: usize, end: usize },
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SignatureDef {
+    pub module: Option<String>,
+    pub section: Option<String>,
+    pub signature: Signature,
+    pub extract: Extract,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Section {
+    pub name: String,
+    pub start: usize, // Relative to the image base
+    pub size: usize,
+}
+
+impl Signature {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let bytes = pattern.split_whitespace().map(|b| match b {
+            "?" | "??" => Ok(None),
+            b => u8::from_str_radix(b, 16).map(Some).map_err(|_| format!("Invalid byte \"{b}\" in the signature")),
+        }).collect::<Result<Vec<Option<u8>>, String>>()?;
+        if bytes.is_empty() {
+            return Err("Empty signature".to_string());
+        }
+        Ok(Self { bytes })
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() >= self.bytes.len() && self.bytes.iter().zip(data).all(|(p, b)| p.is_none_or(|p| p == *b))
+    }
+
+    pub fn find(&self, data: &[u8]) -> Option<usize> {
+        (0..(data.len() + 1).saturating_sub(self.bytes.len())).find(|&i| self.matches(&data[i..]))
+    }
+
+    pub fn find_all(&self, data: &[u8]) -> Vec<usize> {
+        (0..(data.len() + 1).saturating_sub(self.bytes.len())).filter(|&i| self.matches(&data[i..])).collect()
+    }
+}
+
+impl Extract {
+    /// `data` is the buffer the match was found in and `base` the address it was read from.
+    pub fn apply(&self, data: &[u8], index: usize, base: usize) -> Option<usize> {
+        let u32_at = |offset: usize| Some(u32::from_le_bytes(data.get(index + offset..index + offset + 4)?.try_into().unwrap()));
+        Some(match *self {
+            Self::Match(offset) => base + index + offset,
+            Self::Absolute(offset) => u32_at(offset)? as usize,
+            Self::Relative { offset, end } => (base + index + end).wrapping_add_signed(u32_at(offset)? as i32 as isize),
+        })
+    }
+
+    /// `match`, `match+N`, `abs N` or `rel N M` (numbers in hexadecimal).
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let number = |n: &str| usize::from_str_radix(n.trim_start_matches("0x"), 16).map_err(|_| format!("Invalid offset \"{n}\""));
+        let words = text.split_whitespace().collect::<Vec<&str>>();
+        match words.as_slice() {
+            ["match"] => Ok(Self::Match(0)),
+            [m] if m.starts_with("match+") => Ok(Self::Match(number(&m[6..])?)),
+            ["abs", offset] => Ok(Self::Absolute(number(offset)?)),
+            ["rel", offset, end] => Ok(Self::Relative { offset: number(offset)?, end: number(end)? }),
+            _ => Err(format!("Unknown extraction \"{text}\"")),
+        }
+    }
+}
+
+impl SignatureDef {
+    /// `module[:section]<TAB>pattern<TAB>extract`, `*` as module scans the whole process.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let fields = text.split('\t').collect::<Vec<&str>>();
+        let [location, pattern, extract] = fields.as_slice() else {
+            return Err("A signature needs a module, a pattern and an extraction".to_string());
+        };
+        let (module, section) = match location.rsplit_once(':') {
+            Some((module, section)) => (module, Some(section.to_string())),
+            None => (*location, None),
+        };
+        Ok(Self {
+            module: (module != "*").then(|| module.to_string()),
+            section,
+            signature: Signature::parse(pattern)?,
+            extract: Extract::parse(extract)?,
+        })
+    }
+
+    /// Scans the readable memory areas of the process (or of the module and section) for the first match.
+    pub fn scan(&self, modules: &Modules) -> Result<usize, String> {
+        let mut ranges = Vec::new();
+        match &self.module {
+            Some(name) => {
+                let module = modules.get(name).ok_or(format!("Module \"{name}\" is not loaded"))?;
+                match &self.section {
+                    Some(section) => {
+                        // The headers fit in the first page of the image
+                        let image = unsafe { core::slice::from_raw_parts(module.base as *const u8, module.size.min(0x1000)) };
+                        let section = pe_sections(image).into_iter().find(|s| &s.name == section)
+                            .ok_or(format!("Module \"{name}\" has no {section} section"))?;
+                        ranges.push(module.base + section.start..module.base + section.start + section.size);
+                    }
+                    None => ranges.push(module.base..module.base + module.size),
+                }
+            }
+            None => ranges.push(0..usize::MAX),
+        }
+        for range in ranges {
+            for span in readable_spans(range)? {
+                let data = unsafe { core::slice::from_raw_parts(span.start as *const u8, span.len()) };
+                if let Some(index) = self.signature.find(data) {
+                    return self.extract.apply(data, index, span.start).ok_or("The match is too short to extract from".to_string());
+                }
+            }
+        }
+        Err("Signature not found".to_string())
+    }
+}
+
+/// Readable parts of `range`, adjacent areas are joined so a match can cross their boundary.
+/// Guard and no access pages come back from mmap_rs without any protection and are skipped.
+pub fn readable_spans(range: Range<usize>) -> Result<Vec<Range<usize>>, String> {
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    for area in MemoryAreas::query_range(range.clone()).map_err(|e| e.to_string())?.flatten() {
+        if !area.protection().contains(Protection::READ) {
+            continue;
+        }
+        let start = area.start().max(range.start);
+        let end = area.end().min(range.end);
+        match spans.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => spans.push(start..end),
+        }
+    }
+    Ok(spans)
+}
+
+/// Section table of a PE image, mapped the way the loader does it.
+pub fn pe_sections(image: &[u8]) -> Vec<Section> {
+    let u16_at = |o: usize| image.get(o..o + 2).map(|b| u16::from_le_bytes(b.try_into().unwrap()) as usize);
+    let u32_at = |o: usize| image.get(o..o + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize);
+    let mut sections = Vec::new();
+    let Some(pe) = u32_at(0x3c) else {
+        return sections;
+    };
+    if image.get(pe..pe + 4) != Some(b"PE\0\0") {
+        return sections;
+    }
+    let (Some(count), Some(optional_size)) = (u16_at(pe + 6), u16_at(pe + 20)) else {
+        return sections;
+    };
+    let table = pe + 24 + optional_size;
+    for i in 0..count {
+        let entry = table + i * 40;
+        let (Some(name), Some(size), Some(start)) = (image.get(entry..entry + 8), u32_at(entry + 8), u32_at(entry + 12)) else {
+            break;
+        };
+        let end = name.iter().position(|&c| c == 0).unwrap_or(8);
+        sections.push(Section {
+            name: String::from_utf8_lossy(&name[..end]).to_string(),
+            start,
+            size,
+        });
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_wildcards() {
+        let signature = Signature::parse("A1 ?? ? 85 c0").unwrap();
+        assert_eq!(signature.bytes, vec![Some(0xa1), None, None, Some(0x85), Some(0xc0)]);
+        assert!(Signature::parse("A1 GG").is_err());
+        assert!(Signature::parse("  ").is_err());
+    }
+
+    #[test]
+    fn find_in_buffer() {
+        let data = [0x90, 0xe8, 0x01, 0x02, 0x03, 0x04, 0xe8, 0xff, 0x85, 0xc0];
+        let signature = Signature::parse("E8 ?? 85 C0").unwrap();
+        assert_eq!(signature.find(&data), Some(6));
+        assert_eq!(signature.find_all(&data), vec![6]);
+        assert_eq!(Signature::parse("E8").unwrap().find_all(&data), vec![1, 6]);
+        // The pattern may not run past the end
+        assert_eq!(Signature::parse("85 C0 ??").unwrap().find(&data), None);
+        assert_eq!(Signature::parse("C0").unwrap().find(&data), Some(9));
+    }
+
+    #[test]
+    fn extract() {
+        // call rel32 at 0x1001 to 0x1001 + 5 - 0x10, then mov eax, [0x00403020]
+        let data = [0x90, 0xe8, 0xf0, 0xff, 0xff, 0xff, 0xa1, 0x20, 0x30, 0x40, 0x00];
+        let relative = Extract::parse("rel 1 5").unwrap();
+        assert_eq!(relative, Extract::Relative { offset: 1, end: 5 });
+        assert_eq!(relative.apply(&data, 1, 0x1000), Some(0x1001 + 5 - 0x10));
+        let absolute = Extract::parse("abs 1").unwrap();
+        assert_eq!(absolute.apply(&data, 6, 0x1000), Some(0x403020));
+        assert_eq!(absolute.apply(&data, 7, 0x1000), None);
+        assert_eq!(Extract::parse("match+0x10").unwrap().apply(&data, 2, 0x1000), Some(0x1012));
+        assert!(Extract::parse("rel 1").is_err());
+    }
+
+    #[test]
+    fn parse_definition() {
+        let def = SignatureDef::parse("game.exe:.text\tA1 ?? ?? ?? ??\tabs 1").unwrap();
+        assert_eq!(def.module.as_deref(), Some("game.exe"));
+        assert_eq!(def.section.as_deref(), Some(".text"));
+        assert_eq!(SignatureDef::parse("*\tA1\tmatch").unwrap().module, None);
+    }
+}
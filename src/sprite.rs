@@ -0,0 +1,260 @@
+use std::{collections::HashMap, fs, path::Path};
+use hudhook::imgui;
+use rfd::FileDialog;
+use serde::Serialize;
+
+use crate::form::Form;
+use crate::texture::{decode_texture, encode_png, Image, TexturePage};
+
+/// A TPAG entry, the region of a texture page holding one frame.
+#[derive(Default, Clone, Copy)]
+pub struct PageItem {
+    pub offset: usize,
+    pub source: [u16; 4], // x, y, width, height on the page
+    pub target: [u16; 4], // x, y, width, height in the frame
+    pub bounding: [u16; 2], // Frame size
+    pub page: i16, // TXTR index
+}
+
+#[derive(Default, Clone)]
+pub struct Sprite {
+    pub name: String,
+    pub ptr: usize,
+    pub width: u32,
+    pub height: u32,
+    pub margins: [i32; 4], // Left, right, bottom, top
+    pub origin: [i32; 2],
+    pub frame_list: usize, // Offset of the TPAG pointer list
+    pub frames: Vec<usize>, // TPAG entries
+}
+
+pub fn page_item(form: &Form, offset: usize) -> Option<PageItem> {
+    let field = |i: usize| form.u16(offset + i * 2);
+    Some(PageItem {
+        offset,
+        source: [field(0)?, field(1)?, field(2)?, field(3)?],
+        target: [field(4)?, field(5)?, field(6)?, field(7)?],
+        bounding: [field(8)?, field(9)?],
+        page: form.i16(offset + 20)?,
+    })
+}
+
+pub fn parse_page_items(form: &Form) -> Vec<PageItem> {
+    let Some(chunk) = form.chunk(b"TPAG") else {
+        return Vec::new();
+    };
+    form.pointer_list(chunk.offset).unwrap_or_default().into_iter().filter_map(|ptr| page_item(form, ptr)).collect()
+}
+
+pub fn parse_sprites(form: &Form) -> Vec<Sprite> {
+    let Some(chunk) = form.chunk(b"SPRT") else {
+        return Vec::new();
+    };
+    let mut sprites = Vec::new();
+    for ptr in form.pointer_list(chunk.offset).unwrap_or_default() {
+        let sprite = (|| {
+            let name = form.string(form.u32(ptr)? as usize).unwrap_or_default();
+            // GameMaker 2 sprites have a version marker after the origin, with the frames after its fields
+            let frame_list = match form.i32(ptr + 56)? {
+                -1 => {
+                    let version = form.u32(ptr + 60)?;
+                    if form.u32(ptr + 64)? != 0 {
+                        println!("Sprite {name} is not a bitmap sprite");
+                        return None;
+                    }
+                    ptr + 76 + if version >= 2 { 4 } else { 0 } + if version >= 3 { 4 } else { 0 }
+                }
+                _ => ptr + 56,
+            };
+            Some(Sprite {
+                name,
+                ptr,
+                width: form.u32(ptr + 4)?,
+                height: form.u32(ptr + 8)?,
+                margins: [form.i32(ptr + 12)?, form.i32(ptr + 16)?, form.i32(ptr + 20)?, form.i32(ptr + 24)?],
+                origin: [form.i32(ptr + 48)?, form.i32(ptr + 52)?],
+                frame_list,
+                frames: form.pointer_list(frame_list)?,
+            })
+        })();
+        match sprite {
+            Some(sprite) => sprites.push(sprite),
+            None => println!("Skipped the sprite at 0x{ptr:x}"),
+        }
+    }
+    sprites
+}
+
+/// Decodes texture pages once for every frame cut out of them.
+pub struct PageCache<'a> {
+    form: &'a Form<'a>,
+    pages: &'a [TexturePage],
+    decoded: HashMap<i16, Image>,
+}
+
+impl<'a> PageCache<'a> {
+    pub fn new(form: &'a Form<'a>, pages: &'a [TexturePage]) -> Self {
+        Self { form, pages, decoded: HashMap::new() }
+    }
+
+    pub fn page(&mut self, index: i16) -> Result<&Image, String> {
+        if !self.decoded.contains_key(&index) {
+            let page = self.pages.iter().find(|p| p.index as i16 == index).ok_or(format!("Texture page {index} is not embedded"))?;
+            let data = self.form.bytes(page.data, page.size).ok_or(format!("Texture page {index} is out of bounds"))?;
+            self.decoded.insert(index, decode_texture(data).map_err(|e| format!("Texture page {index}: {e}"))?);
+        }
+        Ok(&self.decoded[&index])
+    }
+
+    /// Frames of a sprite at its full size, the trimmed page region placed at its target position.
+    pub fn frames(&mut self, sprite: &Sprite) -> Result<Vec<Image>, String> {
+        let mut frames = Vec::new();
+        for &offset in &sprite.frames {
+            let item = page_item(self.form, offset).ok_or(format!("Invalid TPAG entry at 0x{offset:x}"))?;
+            let mut frame = Image::new(sprite.width, sprite.height);
+            let [x, y, width, height] = item.source.map(u32::from);
+            frame.blit(self.page(item.page)?, (x, y), (width.min(item.target[2] as u32), height.min(item.target[3] as u32)), (item.target[0] as u32, item.target[1] as u32));
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+}
+
+/// Manifest entry of an exported sprite, with the frame files and where each frame came from.
+#[derive(Serialize)]
+pub struct SpriteManifest {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub origin: [i32; 2],
+    pub bbox: [i32; 4], // Left, top, right, bottom
+    pub files: Vec<String>,
+    pub frames: Vec<FrameManifest>,
+}
+
+#[derive(Serialize)]
+pub struct FrameManifest {
+    pub page: i16,
+    pub source: [u16; 4],
+    pub target: [u16; 4],
+}
+
+impl SpriteManifest {
+    fn new(form: &Form, sprite: &Sprite, files: Vec<String>) -> Self {
+        let [left, right, bottom, top] = sprite.margins;
+        Self {
+            name: sprite.name.clone(),
+            width: sprite.width,
+            height: sprite.height,
+            origin: sprite.origin,
+            bbox: [left, top, right, bottom],
+            files,
+            frames: sprite.frames.iter().filter_map(|&f| page_item(form, f)).map(|item| FrameManifest {
+                page: item.page,
+                source: item.source,
+                target: item.target,
+            }).collect(),
+        }
+    }
+}
+
+/// Writes the frames of a sprite as `name_N.png`, or side by side as `name_stripN.png` like GameMaker
+/// imports them. Returns the manifest entry of the sprite.
+pub fn export_sprite(cache: &mut PageCache, sprite: &Sprite, dir: &Path, strip: bool) -> Result<SpriteManifest, String> {
+    let frames = cache.frames(sprite)?;
+    if frames.is_empty() {
+        return Err("The sprite has no frames".to_string());
+    }
+    let mut files = Vec::new();
+    if strip {
+        let mut image = Image::new(sprite.width * frames.len() as u32, sprite.height);
+        for (i, frame) in frames.iter().enumerate() {
+            image.blit(frame, (0, 0), (frame.width, frame.height), (i as u32 * sprite.width, 0));
+        }
+        files.push(format!("{}_strip{}.png", sprite.name, frames.len()));
+        fs::write(dir.join(&files[0]), encode_png(&image)?).map_err(|e| e.to_string())?;
+    } else {
+        for (i, frame) in frames.iter().enumerate() {
+            let file = format!("{}_{i}.png", sprite.name);
+            fs::write(dir.join(&file), encode_png(frame)?).map_err(|e| e.to_string())?;
+            files.push(file);
+        }
+    }
+    Ok(SpriteManifest::new(cache.form, sprite, files))
+}
+
+/// Exports every sprite to `dir` with a `manifest.json` of their origins and bounding boxes.
+pub fn export_sprites(form: &Form, pages: &[TexturePage], sprites: &[Sprite], dir: &Path, strip: bool) -> Result<usize, String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let mut cache = PageCache::new(form, pages);
+    let mut entries = Vec::new();
+    for sprite in sprites {
+        match export_sprite(&mut cache, sprite, dir, strip) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => println!("Could not export sprite {}: {e}", sprite.name),
+        }
+    }
+    let json = serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())?;
+    fs::write(dir.join("manifest.json"), json).map_err(|e| e.to_string())?;
+    Ok(entries.len())
+}
+
+/// Lists the sprites and exports their frames.
+#[derive(Default)]
+pub struct SpritePanel {
+    pub sprites: Vec<Sprite>,
+    filter: String,
+    selected: Option<usize>,
+}
+
+impl SpritePanel {
+    pub fn load(&mut self, form: &Form) {
+        self.sprites = parse_sprites(form);
+        println!("Found {} sprites", self.sprites.len());
+    }
+
+    pub fn render(&mut self, ui: &imgui::Ui, form: Option<&Form>, pages: &[TexturePage]) {
+        let Some(form) = form else {
+            return;
+        };
+        ui.set_next_item_width(150.);
+        ui.input_text("Filter##sprite", &mut self.filter).build();
+        ui.same_line();
+        if ui.button("Export All Sprites") {
+            if let Some(dir) = FileDialog::new().pick_folder() {
+                match export_sprites(form, pages, &self.sprites, &dir, false) {
+                    Ok(count) => println!("========== Exported {count} sprites to {} ==========", dir.display()),
+                    Err(e) => println!("Could not export the sprites: {e}"),
+                }
+            }
+        }
+        let filter = self.filter.to_lowercase();
+        ui.child_window("##sprites").size([0., 120.]).build(|| {
+            for (i, sprite) in self.sprites.iter().enumerate() {
+                if sprite.name.to_lowercase().contains(&filter)
+                    && ui.selectable_config(format!("{} ({}x{}, {} frames)##sprite{i}", sprite.name, sprite.width, sprite.height, sprite.frames.len())).selected(self.selected == Some(i)).build() {
+                    self.selected = Some(i);
+                }
+            }
+        });
+        let Some(sprite) = self.selected.and_then(|i| self.sprites.get(i)) else {
+            return;
+        };
+        for (label, strip) in [("Export Frames", false), ("Export Strip", true)] {
+            if ui.button(label) {
+                if let Some(dir) = FileDialog::new().pick_folder() {
+                    let mut cache = PageCache::new(form, pages);
+                    let result = export_sprite(&mut cache, sprite, &dir, strip)
+                        .and_then(|entry| serde_json::to_string_pretty(&entry).map_err(|e| e.to_string()))
+                        .and_then(|json| fs::write(dir.join(format!("{}.json", sprite.name)), json).map_err(|e| e.to_string()));
+                    match result {
+                        Ok(()) => println!("========== Exported sprite {} ==========", sprite.name),
+                        Err(e) => println!("Could not export sprite {}: {e}", sprite.name),
+                    }
+                }
+            }
+            ui.same_line();
+        }
+        ui.text(format!("Origin: {}, {}", sprite.origin[0], sprite.origin[1]));
+    }
+}
@@ -0,0 +1,101 @@
+/// Turns raw string bytes into editable text without losing anything:
+/// bytes that aren't valid UTF-8 become `\xNN`, and a literal backslash that would be read back
+/// as an escape becomes `\x5C`. With `lines`, line breaks are written as `\n` and `\r` too.
+pub fn escape(bytes: &[u8], lines: bool) -> String {
+    let mut result = String::new();
+    for chunk in bytes.utf8_chunks() {
+        let valid = chunk.valid();
+        for (i, c) in valid.char_indices() {
+            let next = &valid.as_bytes()[i + 1..];
+            match c {
+                '\n' if lines => result += "\\n",
+                '\r' if lines => result += "\\r",
+                '\\' if is_escape(next, lines) || (next.is_empty() && !chunk.invalid().is_empty()) => result += "\\x5C",
+                c => result.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            result += &format!("\\x{b:02X}");
+        }
+    }
+    result
+}
+
+/// Reverse of [`escape`].
+pub fn unescape(text: &str, lines: bool) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            let next = &bytes[i + 1..];
+            if is_hex_escape(next) {
+                result.push(u8::from_str_radix(&text[i + 2..i + 4], 16).unwrap());
+                i += 4;
+                continue;
+            }
+            if lines && next.first() == Some(&b'n') {
+                result.push(b'\n');
+                i += 2;
+                continue;
+            }
+            if lines && next.first() == Some(&b'r') {
+                result.push(b'\r');
+                i += 2;
+                continue;
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    result
+}
+
+fn is_hex_escape(next: &[u8]) -> bool {
+    next.len() >= 3 && next[0] == b'x' && next[1].is_ascii_hexdigit() && next[2].is_ascii_hexdigit()
+}
+
+fn is_escape(next: &[u8], lines: bool) -> bool {
+    is_hex_escape(next) || (lines && matches!(next.first(), Some(b'n') | Some(b'r')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8], lines: bool) -> String {
+        let text = escape(bytes, lines);
+        assert_eq!(unescape(&text, lines), bytes, "{text}");
+        text
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        assert_eq!(round_trip(b"caf\xe9 \xff\xfe", true), "caf\\xE9 \\xFF\\xFE");
+        // A truncated sequence before valid text
+        assert_eq!(round_trip(b"\xc3ok", false), "\\xC3ok");
+        assert_eq!(round_trip("d\u{e9}j\u{e0}".as_bytes(), true), "d\u{e9}j\u{e0}");
+    }
+
+    #[test]
+    fn escapes() {
+        // Text that looks like an escape keeps its backslash
+        assert_eq!(round_trip(b"\\x41", true), "\\x5Cx41");
+        assert_eq!(round_trip(b"\\x5C", false), "\\x5Cx5C");
+        assert_eq!(round_trip(b"a\\b", true), "a\\b");
+        assert_eq!(round_trip(b"\\\xff", true), "\\x5C\\xFF");
+        assert_eq!(unescape("\\x41\\x4a", false), b"AJ");
+        // Not an escape: too short or not hexadecimal
+        assert_eq!(unescape("\\x4", false), b"\\x4");
+        assert_eq!(unescape("\\xZZ", false), b"\\xZZ");
+    }
+
+    #[test]
+    fn line_breaks() {
+        assert_eq!(round_trip(b"one\r\ntwo\n", true), "one\\r\\ntwo\\n");
+        assert_eq!(round_trip(b"one\r\ntwo\n", false), "one\r\ntwo\n");
+        assert_eq!(round_trip(b"\\n", true), "\\x5Cn");
+        assert_eq!(round_trip(b"\\n", false), "\\n");
+        assert_eq!(unescape("\\r\\n", false), b"\\r\\n");
+    }
+}
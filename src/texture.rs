@@ -0,0 +1,444 @@
+use core::slice;
+use std::{collections::HashMap, fs, io::{Read, Write}, path::Path};
+use bzip2::{read::BzDecoder, write::BzEncoder, Compression};
+use hudhook::imgui;
+use mmap_rs::MemoryAreas;
+use rfd::FileDialog;
+
+use crate::arena::Arena;
+use crate::form::Form;
+use crate::hooks::{call_builtin, resolve};
+use crate::qoi;
+use crate::watch::WatchList;
+
+const PNG_MAGIC: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+const BZ2_QOI_MAGIC: &[u8; 4] = b"2zoq";
+
+/// How a page is stored, newer runners use QOI and QOI compressed with BZip2.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum TextureFormat {
+    #[default]
+    Png,
+    Qoi,
+    Bz2Qoi {
+        sized: bool, // GameMaker 2022.5 added the QOI length after the size
+    },
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct TexturePage {
+    pub index: usize,
+    pub entry: usize, // Offset of the TXTR entry
+    pub field: usize, // Offset of the entry field pointing at the blob
+    pub data: usize, // Offset of the image blob
+    pub size: usize,
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+}
+
+impl TextureFormat {
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        match data.get(..4)? {
+            magic if magic == &PNG_MAGIC[..4] => Some(Self::Png),
+            magic if magic == qoi::MAGIC => Some(Self::Qoi),
+            magic if magic == BZ2_QOI_MAGIC => Some(Self::Bz2Qoi { sized: data.get(8..11)? != b"BZh" }),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Png => "PNG",
+            Self::Qoi => "QOI",
+            Self::Bz2Qoi { .. } => "BZ2+QOI",
+        }
+    }
+}
+
+/// Width and height of a page in any format.
+pub fn texture_size(data: &[u8]) -> Option<(u32, u32)> {
+    match TextureFormat::detect(data)? {
+        TextureFormat::Png => png_size(data),
+        TextureFormat::Qoi => qoi::size(data),
+        TextureFormat::Bz2Qoi { .. } => Some((u16::from_le_bytes([data[4], data[5]]) as u32, u16::from_le_bytes([data[6], data[7]]) as u32)),
+    }
+}
+
+pub fn decode_texture(data: &[u8]) -> Result<Image, String> {
+    match TextureFormat::detect(data).ok_or("Unknown texture format")? {
+        TextureFormat::Png => decode_png(data),
+        TextureFormat::Qoi => qoi::decode(data),
+        TextureFormat::Bz2Qoi { sized } => {
+            let mut qoi = Vec::new();
+            BzDecoder::new(&data[if sized { 12 } else { 8 }..]).read_to_end(&mut qoi).map_err(|e| e.to_string())?;
+            qoi::decode(&qoi)
+        }
+    }
+}
+
+pub fn encode_texture(image: &Image, format: TextureFormat) -> Result<Vec<u8>, String> {
+    match format {
+        TextureFormat::Png => encode_png(image),
+        TextureFormat::Qoi => Ok(qoi::encode(image)),
+        TextureFormat::Bz2Qoi { sized } => {
+            let qoi = qoi::encode(image);
+            let mut data = BZ2_QOI_MAGIC.to_vec();
+            data.extend((image.width as u16).to_le_bytes());
+            data.extend((image.height as u16).to_le_bytes());
+            if sized {
+                data.extend((qoi.len() as u32).to_le_bytes());
+            }
+            let mut encoder = BzEncoder::new(data, Compression::best());
+            encoder.write_all(&qoi).map_err(|e| e.to_string())?;
+            encoder.finish().map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Converts a PNG file to the format of a page.
+pub fn png_to_texture(data: Vec<u8>, format: TextureFormat) -> Result<Vec<u8>, String> {
+    match format {
+        TextureFormat::Png => Ok(data),
+        format => encode_texture(&decode_png(&data)?, format),
+    }
+}
+
+/// A page as a PNG file.
+pub fn texture_to_png(data: &[u8]) -> Result<Vec<u8>, String> {
+    match TextureFormat::detect(data) {
+        Some(TextureFormat::Png) => Ok(data.to_vec()),
+        _ => encode_png(&decode_texture(data)?),
+    }
+}
+
+/// An RGBA image, 4 bytes per pixel.
+#[derive(Default, Clone)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, pixels: vec![0; width as usize * height as usize * 4] }
+    }
+
+    /// Copies a `width`x`height` region at `x`, `y` of `source` to `to_x`, `to_y`, clipped to both images.
+    pub fn blit(&mut self, source: &Image, (x, y): (u32, u32), (width, height): (u32, u32), (to_x, to_y): (u32, u32)) {
+        let width = width.min(source.width.saturating_sub(x)).min(self.width.saturating_sub(to_x)) as usize;
+        let height = height.min(source.height.saturating_sub(y)).min(self.height.saturating_sub(to_y));
+        for row in 0..height {
+            let from = ((y + row) * source.width + x) as usize * 4;
+            let to = ((to_y + row) * self.width + to_x) as usize * 4;
+            self.pixels[to..to + width * 4].copy_from_slice(&source.pixels[from..from + width * 4]);
+        }
+    }
+
+    /// Like [`Image::blit`], but draws the region over what is already there using its alpha.
+    pub fn blend(&mut self, source: &Image, (x, y): (u32, u32), (width, height): (u32, u32), (to_x, to_y): (u32, u32)) {
+        let width = width.min(source.width.saturating_sub(x)).min(self.width.saturating_sub(to_x));
+        let height = height.min(source.height.saturating_sub(y)).min(self.height.saturating_sub(to_y));
+        for row in 0..height {
+            for column in 0..width {
+                let from = (((y + row) * source.width + x + column) * 4) as usize;
+                let to = (((to_y + row) * self.width + to_x + column) * 4) as usize;
+                let alpha = source.pixels[from + 3] as u32;
+                for channel in 0..3 {
+                    let (s, d) = (source.pixels[from + channel] as u32, self.pixels[to + channel] as u32);
+                    self.pixels[to + channel] = ((s * alpha + d * (255 - alpha)) / 255) as u8;
+                }
+                self.pixels[to + 3] = (alpha + self.pixels[to + 3] as u32 * (255 - alpha) / 255) as u8;
+            }
+        }
+    }
+}
+
+pub fn decode_png(data: &[u8]) -> Result<Image, String> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+    buffer.truncate(info.buffer_size());
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buffer.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+        png::ColorType::Indexed => return Err("Indexed PNG images are not supported".to_string()),
+    };
+    Ok(Image { width: info.width, height: info.height, pixels })
+}
+
+pub fn encode_png(image: &Image) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&image.pixels).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+/// Width and height from the IHDR chunk of a PNG image.
+pub fn png_size(data: &[u8]) -> Option<(u32, u32)> {
+    if data.get(..8)? != PNG_MAGIC || data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(data.get(16..20)?.try_into().unwrap());
+    let height = u32::from_be_bytes(data.get(20..24)?.try_into().unwrap());
+    Some((width, height))
+}
+
+/// Length of a PNG image, up to the end of its IEND chunk.
+pub fn png_length(data: &[u8]) -> Option<usize> {
+    let mut offset = PNG_MAGIC.len();
+    loop {
+        let length = u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().unwrap()) as usize;
+        let kind = data.get(offset + 4..offset + 8)?;
+        offset += 12 + length;
+        if kind == b"IEND" {
+            return (offset <= data.len()).then_some(offset);
+        }
+    }
+}
+
+/// Lists the embedded texture pages. The entry layout changed between GameMaker versions, so the blob
+/// pointer is found as the first field pointing at an image.
+pub fn parse_textures(form: &Form) -> Vec<TexturePage> {
+    let Some(chunk) = form.chunk(b"TXTR") else {
+        return Vec::new();
+    };
+    let mut pages = Vec::new();
+    for (index, entry) in form.pointer_list(chunk.offset).unwrap_or_default().into_iter().enumerate() {
+        let page = (0..8).map(|i| entry + i * 4).find_map(|field| {
+            let data = form.u32(field)? as usize;
+            let rest = form.bytes(data, chunk.end().checked_sub(data)?)?;
+            let format = TextureFormat::detect(rest)?;
+            let (width, height) = texture_size(rest)?;
+            let size = match format {
+                TextureFormat::Png => png_length(rest)?,
+                TextureFormat::Qoi => qoi::length(rest)?,
+                TextureFormat::Bz2Qoi { .. } => rest.len(), // Until the next blob, below
+            };
+            Some(TexturePage { index, entry, field, data, size, width, height, format })
+        });
+        match page {
+            Some(page) => pages.push(page),
+            None => println!("Texture page {index} at 0x{entry:x} is external or in an unknown format"),
+        }
+    }
+    // BZip2 streams don't store their length, the padding after them is ignored when decoding
+    let starts = pages.iter().map(|p| p.data).collect::<Vec<usize>>();
+    for page in &mut pages {
+        if let Some(&next) = starts.iter().filter(|&&s| s > page.data).min() {
+            page.size = page.size.min(next - page.data);
+        }
+    }
+    pages
+}
+
+/// Writes every page as `page_N.png` in `dir`.
+pub fn export_textures(form: &Form, pages: &[TexturePage], dir: &Path) -> Result<usize, String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    for page in pages {
+        let data = form.bytes(page.data, page.size).ok_or(format!("Texture page {} is out of bounds", page.index))?;
+        let png = texture_to_png(data).map_err(|e| format!("Texture page {}: {e}", page.index))?;
+        fs::write(dir.join(format!("page_{}.png", page.index)), png).map_err(|e| e.to_string())?;
+    }
+    Ok(pages.len())
+}
+
+/// Heap slots holding `target`, where the runner keeps its own copies of the blob pointers.
+/// Uses the same areas as the music pointers. A slot is only kept when one of `near` is stored within
+/// 4 words of it, a lone word equal to the target is as likely to be an unrelated value.
+/// # Safety
+/// Reads the memory of the running game, only call it from inside the game.
+pub unsafe fn heap_pointers(target: usize, near: &[usize]) -> Vec<usize> {
+    let mut slots = Vec::new();
+    for ma in MemoryAreas::open(None).unwrap().flatten() {
+        if ma.end() - ma.start() > (1024 * 1024) && ma.start() < 0x10000000 && ma.end() - ma.start() < (1024 * 1024 * 4) {
+            let words = slice::from_raw_parts(ma.start() as *const u32, (ma.end() - ma.start()) / 4);
+            for (i, &word) in words.iter().enumerate() {
+                if word as usize == target && is_corroborated(words, i, near) {
+                    slots.push(ma.start() + i * 4);
+                }
+            }
+        }
+    }
+    slots
+}
+
+/// Whether one of `near` is within 4 words of `words[index]`.
+fn is_corroborated(words: &[u32], index: usize, near: &[usize]) -> bool {
+    let start = index.saturating_sub(4);
+    words[start..(index + 5).min(words.len())].iter().enumerate()
+        .any(|(i, &w)| start + i != index && near.contains(&(w as usize)))
+}
+
+/// A page pointing at an injected PNG.
+struct Replacement {
+    addr: usize, // Arena buffer
+    heap: Vec<usize>, // Runner copies of the blob pointer
+}
+
+/// Lists the TXTR pages, saves them as PNG files and replaces them in the running game.
+///
+/// The runner decodes a page when it creates its texture. Replaced pages show up once they are
+/// created again: "Flush" calls `draw_texture_flush` to drop them from the GPU, pages decoded before
+/// the replacement that the runner keeps in memory only change after a room or texture group reload.
+#[derive(Default)]
+pub struct TexturePanel {
+    pub pages: Vec<TexturePage>,
+    selected: i32,
+    replaced: HashMap<usize, Replacement>, // By page index
+    flush: Option<Result<usize, String>>, // draw_texture_flush, resolved on the first use
+}
+
+impl TexturePanel {
+    pub fn load(&mut self, form: &Form) {
+        self.pages = parse_textures(form);
+        println!("Found {} texture pages", self.pages.len());
+    }
+
+    /// PNG of a page, the replacement when there is one.
+    pub fn page_data<'a>(&self, form: &Form<'a>, arena: &'a Arena, page: &TexturePage) -> Option<&'a [u8]> {
+        match self.replaced.get(&page.index).and_then(|r| arena.get(r.addr)) {
+            Some(data) => Some(data),
+            None => form.bytes(page.data, page.size),
+        }
+    }
+
+    unsafe fn replace(&mut self, form: &Form, arena: &mut Arena, page: TexturePage, data: Vec<u8>) -> Result<(), String> {
+        let (width, height) = png_size(&data).ok_or("The file is not a PNG image")?;
+        if (width, height) != (page.width, page.height) {
+            return Err(format!("The image is {width}x{height}, the page is {}x{}", page.width, page.height));
+        }
+        self.set_page(form, arena, page, png_to_texture(data, page.format)?);
+        Ok(())
+    }
+
+    /// Points a page at `data`, already in the format of the page.
+    /// # Safety
+    /// `form` must be the data.win loaded in the running game.
+    pub unsafe fn set_page(&mut self, form: &Form, arena: &mut Arena, page: TexturePage, data: Vec<u8>) {
+        self.restore(form, arena, page);
+        let addr = arena.alloc(data);
+        arena.set(form.addr(page.field), addr.wrapping_sub(form.base) as u32, Some(addr));
+        let heap = heap_pointers(form.addr(page.data), &[form.addr(page.entry), page.size]);
+        for &slot in &heap {
+            println!("Found a pointer at 0x{slot:x} for texture page {}", page.index);
+            arena.set(slot, addr as u32, Some(addr));
+        }
+        self.replaced.insert(page.index, Replacement { addr, heap });
+    }
+
+    /// Points a page back at its blob in data.win, returns false when it was not replaced.
+    /// # Safety
+    /// `form` must be the data.win loaded in the running game.
+    pub unsafe fn restore(&mut self, form: &Form, arena: &mut Arena, page: TexturePage) -> bool {
+        let Some(replacement) = self.replaced.remove(&page.index) else {
+            return false;
+        };
+        arena.set(form.addr(page.field), page.data as u32, None);
+        for slot in replacement.heap {
+            arena.set(slot, form.addr(page.data) as u32, None);
+        }
+        true
+    }
+
+    pub fn render(&mut self, ui: &imgui::Ui, form: Option<&Form>, arena: &mut Arena, watch: &WatchList) {
+        let Some(form) = form else {
+            ui.text("data.win was not found");
+            return;
+        };
+        let selected = self.pages.get(self.selected as usize).copied();
+        if ui.button("Export PNG") {
+            if let Some(page) = selected {
+                let file = FileDialog::new()
+                    .add_filter("PNG Files", &["png"])
+                    .set_file_name(format!("page_{}.png", page.index))
+                    .save_file();
+                if let Some(file) = file {
+                    let written = self.page_data(form, arena, &page).ok_or("the page is out of bounds".to_string())
+                        .and_then(texture_to_png)
+                        .and_then(|png| fs::write(&file, png).map_err(|e| e.to_string()));
+                    match written {
+                        Ok(()) => println!("========== Exported texture page {} ==========", page.index),
+                        Err(e) => println!("Could not export texture page {}: {e}", page.index),
+                    }
+                }
+            }
+        }
+        ui.same_line();
+        if ui.button("Export All PNG") {
+            if let Some(dir) = FileDialog::new().pick_folder() {
+                match export_textures(form, &self.pages, &dir) {
+                    Ok(count) => println!("========== Exported {count} texture pages to {} ==========", dir.display()),
+                    Err(e) => println!("Could not export the texture pages: {e}"),
+                }
+            }
+        }
+        if ui.button("Load##texture") {
+            if let Some(page) = selected {
+                let file = FileDialog::new()
+                    .add_filter("PNG Files", &["png"])
+                    .set_file_name(format!("page_{}.png", page.index))
+                    .pick_file();
+                if let Some(file) = file {
+                    match unsafe { self.replace(form, arena, page, fs::read(file).unwrap()) } {
+                        Ok(()) => println!("========== Loaded new texture page {} ==========", page.index),
+                        Err(e) => println!("Could not replace texture page {}: {e}", page.index),
+                    }
+                }
+            }
+        }
+        ui.same_line();
+        if ui.button("Restore OG Texture") {
+            if let Some(page) = selected {
+                if unsafe { self.restore(form, arena, page) } {
+                    println!("========== Restored texture page {} ==========", page.index);
+                } else {
+                    println!("========== The texture page has not been modified ==========");
+                }
+            }
+        }
+        // Hidden once the built-in can't be found in the symbols or the function table
+        if !matches!(self.flush, Some(Err(_))) {
+            ui.same_line();
+            if ui.button("Flush") {
+                match self.flush.get_or_insert_with(|| resolve(watch, "draw_texture_flush")) {
+                    Ok(address) => {
+                        call_builtin(*address, 0, 0, &[]);
+                        println!("========== Flushed the texture pages ==========");
+                    }
+                    Err(e) => println!("Could not flush the texture pages: {e}"),
+                }
+            }
+        }
+        let items = self.pages.iter()
+            .map(|p| format!("Texture {} ({}x{} {}){}", p.index, p.width, p.height, p.format.name(), if self.replaced.contains_key(&p.index) { " (replaced)" } else { "" }))
+            .collect::<Vec<String>>();
+        ui.list_box("Texture Pages", &mut self.selected, &items.iter().collect::<Vec<&String>>(), 6);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corroborated_pointers() {
+        let words = [7, 0x500, 0, 0, 0, 0, 0x500, 0, 0, 0, 0, 0, 0x900, 0x500];
+        let near = [0x900, 7];
+        assert!(is_corroborated(&words, 1, &near));
+        // The size is 6 words before, the entry 6 words after
+        assert!(!is_corroborated(&words, 6, &near));
+        assert!(is_corroborated(&words, 13, &near));
+        // The matching word itself doesn't count
+        assert!(!is_corroborated(&words, 12, &[0x900]));
+    }
+}
@@ -0,0 +1,403 @@
+use core::slice;
+use std::collections::HashSet;
+use hudhook::imgui;
+
+use crate::code::{parse_functions, parse_variables, Function, Variable};
+use crate::form::Form;
+use crate::sigscan::readable_spans;
+use crate::watch::{is_readable, is_writable, Format, ValueType, WatchEntry, WatchList};
+
+// RValue kinds of the runner
+pub const KIND_REAL: u32 = 0;
+pub const KIND_STRING: u32 = 1;
+pub const KIND_ARRAY: u32 = 2;
+pub const KIND_PTR: u32 = 3;
+pub const KIND_UNDEFINED: u32 = 5;
+pub const KIND_OBJECT: u32 = 6;
+pub const KIND_INT32: u32 = 7;
+pub const KIND_INT64: u32 = 10;
+pub const KIND_BOOL: u32 = 13;
+
+// VARI instance types
+pub const INSTANCE_SELF: i32 = -1;
+pub const INSTANCE_GLOBAL: i32 = -5;
+
+const SCOPES: [&str; 2] = ["Global", "Instance"];
+
+/// Browses the variables named in VARI with their live values.
+/// The runner keeps them in a `CHashMap<int, RValue*>` keyed by variable id, its address comes from the
+/// `gml_globals` profile symbol, and instances keep theirs at the `gml_instance_vars` offset.
+/// Without the symbols the maps are searched in memory by the variable ids they hold.
+#[derive(Default)]
+pub struct VariableBrowser {
+    pub variables: Vec<Variable>,
+    pub functions: Vec<Function>,
+    filter: String,
+    scope: usize,
+    instance: String,
+    selected: Option<usize>,
+    edit: String,
+    globals: Option<usize>, // Found in memory
+    instance_vars: Option<usize>, // Found in memory
+}
+
+/// Header of a runner `CHashMap<int, RValue*>`: m_curSize, m_numUsed, m_curMask, m_growThreshold, m_elements.
+struct MapHeader {
+    size: usize,
+    used: usize,
+    mask: usize,
+    elements: usize,
+}
+
+const MAP_HEADER_SIZE: usize = 20;
+const ELEMENT_SIZE: usize = 12;
+const RVALUE_SIZE: usize = 16;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+impl MapHeader {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            size: read_u32(bytes, 0) as usize,
+            used: read_u32(bytes, 4) as usize,
+            mask: read_u32(bytes, 8) as usize,
+            elements: read_u32(bytes, 16) as usize,
+        }
+    }
+
+    /// Whether it looks like a map: a power of two size with the matching mask.
+    fn is_valid(&self) -> bool {
+        (8..=1 << 20).contains(&self.size) && self.size.is_power_of_two() && self.mask == self.size - 1 && self.used <= self.size
+    }
+}
+
+/// Keys and values of the used slots, `Element { RValue* v, int k, unsigned int hash }`.
+fn parse_elements(bytes: &[u8]) -> Vec<(i32, usize)> {
+    bytes.chunks_exact(ELEMENT_SIZE)
+        .map(|e| (read_u32(e, 0) as usize, read_u32(e, 4) as i32, read_u32(e, 8)))
+        .filter(|&(value, _, hash)| hash != 0 && value != 0)
+        .map(|(value, key, _)| (key, value))
+        .collect()
+}
+
+/// Number of keys in `ids`, 0 when most of them are something else.
+fn entries_score(entries: &[(i32, usize)], ids: &HashSet<i32>) -> usize {
+    let score = entries.iter().filter(|(k, _)| ids.contains(k)).count();
+    if score * 2 < entries.len() { 0 } else { score }
+}
+
+/// Reads the used slots of a runner `CHashMap<int, RValue*>`.
+/// # Safety
+/// `map` must point to a hash map of the runner.
+pub unsafe fn hash_map_entries(map: usize) -> Vec<(i32, usize)> {
+    if !is_readable(map, MAP_HEADER_SIZE) {
+        return Vec::new();
+    }
+    let header = MapHeader::parse(slice::from_raw_parts(map as *const u8, MAP_HEADER_SIZE));
+    if !is_readable(header.elements, header.size * ELEMENT_SIZE) {
+        return Vec::new();
+    }
+    parse_elements(slice::from_raw_parts(header.elements as *const u8, header.size * ELEMENT_SIZE))
+}
+
+/// Number of keys of the map at `map` that are in `ids`, 0 when it doesn't look like a `CHashMap`
+/// or when most of its keys are something else.
+/// # Safety
+/// Reads the memory at `map` after checking it.
+unsafe fn hash_map_score(map: usize, ids: &HashSet<i32>) -> usize {
+    if !is_readable(map, MAP_HEADER_SIZE) || !MapHeader::parse(slice::from_raw_parts(map as *const u8, MAP_HEADER_SIZE)).is_valid() {
+        return 0;
+    }
+    entries_score(&hash_map_entries(map), ids)
+}
+
+/// Searches the process memory for the hash map holding the most of `ids`.
+pub fn find_variable_map(ids: &HashSet<i32>) -> Result<usize, String> {
+    let mut best = None;
+    for span in readable_spans(0..usize::MAX)? {
+        for map in (span.start..span.end.saturating_sub(MAP_HEADER_SIZE)).step_by(4) {
+            // Cheap test before reading the elements. Volatile, the scan goes over the stack of this thread too
+            let size = unsafe { (map as *const u32).read_volatile() } as usize;
+            if size < 8 || !size.is_power_of_two() || unsafe { ((map + 8) as *const u32).read_volatile() } as usize != size - 1 {
+                continue;
+            }
+            let score = unsafe { hash_map_score(map, ids) };
+            if score > 0 && best.is_none_or(|(_, s)| score > s) {
+                best = Some((map, score));
+            }
+        }
+    }
+    best.map(|(map, _)| map).ok_or("No variable map found in memory".to_string())
+}
+
+/// Offset of the variable map inside the instance at `instance`.
+pub fn find_instance_map(instance: usize, ids: &HashSet<i32>) -> Option<usize> {
+    (0..0x400).step_by(4)
+        .map(|offset| (offset, unsafe { hash_map_score(instance + offset, ids) }))
+        .filter(|(_, score)| *score > 0)
+        .max_by_key(|(offset, score)| (*score, usize::MAX - offset))
+        .map(|(offset, _)| offset)
+}
+
+/// Kind of an RValue, in the low 24 bits after the value and the flags.
+fn kind_of(rvalue: &[u8]) -> u32 {
+    read_u32(rvalue, 12) & 0xffffff
+}
+
+/// # Safety
+/// `rvalue` must point to a readable RValue.
+pub unsafe fn rvalue_kind(rvalue: usize) -> u32 {
+    kind_of(slice::from_raw_parts(rvalue as *const u8, RVALUE_SIZE))
+}
+
+/// Address and length of the text of a string RValue.
+/// # Safety
+/// `rvalue` must point to a readable RValue.
+pub unsafe fn string_text(rvalue: usize) -> Option<(usize, usize)> {
+    // RefString { const char* text, int refs, int size }
+    let string = (rvalue as *const u32).read_unaligned() as usize;
+    let text = if is_readable(string, 4) { (string as *const u32).read_unaligned() as usize } else { 0 };
+    if text == 0 || !is_readable(text, 1) {
+        return None;
+    }
+    Some((text, std::ffi::CStr::from_ptr(text as *const i8).to_bytes().len()))
+}
+
+/// Formats the bytes of an RValue, `text` reads the characters of a string.
+fn format_value(rvalue: &[u8], text: impl FnOnce() -> Option<String>) -> String {
+    let bits = u64::from_le_bytes(rvalue[..8].try_into().unwrap());
+    match kind_of(rvalue) {
+        KIND_REAL => f64::from_bits(bits).to_string(),
+        KIND_BOOL => (f64::from_bits(bits) != 0.).to_string(),
+        KIND_INT32 => (bits as i32).to_string(),
+        KIND_INT64 => (bits as i64).to_string(),
+        KIND_STRING => format!("\"{}\"", text().unwrap_or_default()),
+        KIND_ARRAY => "<array>".to_string(),
+        KIND_PTR => format!("<ptr 0x{:x}>", read_u32(rvalue, 0)),
+        KIND_UNDEFINED => "undefined".to_string(),
+        KIND_OBJECT => "<struct>".to_string(),
+        kind => format!("<kind {kind}>"),
+    }
+}
+
+/// Bytes written over the value of an RValue of `kind` set to `text`. Only numeric values can be
+/// edited, strings belong to the runner allocator.
+fn parse_value(kind: u32, text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim();
+    Ok(match kind {
+        KIND_REAL => text.parse::<f64>().map_err(|_| format!("{text} is not a number"))?.to_le_bytes().to_vec(),
+        KIND_BOOL => match text {
+            "true" | "1" => 1f64,
+            "false" | "0" => 0f64,
+            _ => return Err(format!("{text} is not a bool")),
+        }.to_le_bytes().to_vec(),
+        KIND_INT32 => text.parse::<i32>().map_err(|_| format!("{text} is not an integer"))?.to_le_bytes().to_vec(),
+        KIND_INT64 => text.parse::<i64>().map_err(|_| format!("{text} is not an integer"))?.to_le_bytes().to_vec(),
+        _ => return Err("Only numbers and bools can be edited".to_string()),
+    })
+}
+
+/// # Safety
+/// `rvalue` must point to a readable RValue.
+pub unsafe fn format_rvalue(rvalue: usize) -> String {
+    format_value(slice::from_raw_parts(rvalue as *const u8, RVALUE_SIZE), || {
+        string_text(rvalue).map(|(text, len)| String::from_utf8_lossy(slice::from_raw_parts(text as *const u8, len)).to_string())
+    })
+}
+
+/// # Safety
+/// `rvalue` must point to a writable RValue.
+pub unsafe fn write_rvalue(rvalue: usize, text: &str) -> Result<(), String> {
+    let bytes = parse_value(rvalue_kind(rvalue), text)?;
+    slice::from_raw_parts_mut(rvalue as *mut u8, bytes.len()).copy_from_slice(&bytes);
+    Ok(())
+}
+
+impl VariableBrowser {
+    pub fn load(&mut self, form: &Form) {
+        self.variables = parse_variables(form);
+        self.functions = parse_functions(form);
+        println!("Found {} variables and {} functions", self.variables.len(), self.functions.len());
+    }
+
+    fn ids(&self, instance_type: i32) -> HashSet<i32> {
+        self.variables.iter().filter(|v| v.instance_type == instance_type).map(|v| v.id).collect()
+    }
+
+    fn map(&mut self, watch: &WatchList) -> Result<usize, String> {
+        if self.scope == 0 {
+            if watch.symbols.contains_key("gml_globals") {
+                return watch.resolve("gml_globals");
+            }
+            return self.globals.ok_or("Find the global variables or set the gml_globals symbol".to_string());
+        }
+        if self.instance.trim().is_empty() {
+            return Err("Type the address of an instance".to_string());
+        }
+        let instance = watch.resolve(&self.instance)?;
+        if watch.symbols.contains_key("gml_instance_vars") {
+            return Ok(instance + watch.resolve("gml_instance_vars")?);
+        }
+        // Every instance has its map at the same offset
+        if self.instance_vars.is_none() {
+            self.instance_vars = find_instance_map(instance, &self.ids(INSTANCE_SELF));
+        }
+        self.instance_vars.map(|offset| instance + offset).ok_or("No variable map found in the instance".to_string())
+    }
+
+    pub fn render(&mut self, ui: &imgui::Ui, watch: &mut WatchList) {
+        ui.set_next_item_width(100.);
+        ui.combo_simple_string("Scope##vari", &mut self.scope, &SCOPES);
+        ui.same_line();
+        ui.set_next_item_width(150.);
+        ui.input_text("Filter##vari", &mut self.filter).build();
+        if self.scope == 1 {
+            ui.set_next_item_width(200.);
+            ui.input_text("Instance Address##vari", &mut self.instance).build();
+        }
+        if self.scope == 0 && !watch.symbols.contains_key("gml_globals") {
+            ui.same_line();
+            if ui.button("Find Globals##vari") {
+                match find_variable_map(&self.ids(INSTANCE_GLOBAL)) {
+                    Ok(map) => {
+                        println!("========== Found the global variables at 0x{map:x} ==========");
+                        self.globals = Some(map);
+                    }
+                    Err(e) => println!("Could not find the global variables: {e}"),
+                }
+            }
+        }
+        let values = match self.map(watch) {
+            Ok(map) => unsafe { hash_map_entries(map) },
+            Err(e) => {
+                ui.text_disabled(e);
+                Vec::new()
+            }
+        };
+        let instance_type = if self.scope == 0 { INSTANCE_GLOBAL } else { INSTANCE_SELF };
+        let filter = self.filter.to_lowercase();
+        let shown = self.variables.iter().enumerate()
+            .filter(|(_, v)| v.instance_type == instance_type && v.name.to_lowercase().contains(&filter))
+            .map(|(i, v)| (i, values.iter().find(|(k, _)| *k == v.id).map(|(_, r)| *r)))
+            .collect::<Vec<(usize, Option<usize>)>>();
+        let prefix = if self.scope == 0 { "global." } else { "self." };
+        ui.child_window("##variables").size([0., 120.]).build(|| {
+            for (i, rvalue) in &shown {
+                let variable = &self.variables[*i];
+                let value = rvalue.map(|r| unsafe { format_rvalue(r) }).unwrap_or("not set".to_string());
+                if ui.selectable_config(format!("{prefix}{} = {value}##vari{i}", variable.name)).selected(self.selected == Some(*i)).build() {
+                    self.selected = Some(*i);
+                    self.edit = value;
+                }
+            }
+        });
+        let Some((selected, Some(rvalue))) = self.selected.and_then(|s| shown.iter().find(|(i, _)| *i == s)) else {
+            return;
+        };
+        let name = format!("{prefix}{}", self.variables[*selected].name);
+        ui.set_next_item_width(200.);
+        if ui.input_text(format!("{name}##varedit"), &mut self.edit).enter_returns_true(true).build() {
+            let result = match is_writable(*rvalue, 16) {
+                true => unsafe { write_rvalue(*rvalue, &self.edit) },
+                false => Err("The value is in read only memory".to_string()),
+            };
+            match result {
+                Ok(()) => println!("========== Changed {name} to {} ==========", self.edit),
+                Err(e) => println!("Could not change {name}: {e}"),
+            }
+        }
+        ui.same_line();
+        if ui.button("Add to Watch##vari") {
+            // The RValue stays at the same place while the variable exists
+            let address = format!("{:X}", rvalue);
+            let watched = match unsafe { rvalue_kind(*rvalue) } {
+                KIND_REAL | KIND_BOOL => Ok((address, ValueType::F64, Format::Decimal)),
+                KIND_INT32 => Ok((address, ValueType::I32, Format::Decimal)),
+                KIND_INT64 => Ok((address, ValueType::I64, Format::Decimal)),
+                KIND_PTR => Ok((address, ValueType::I32, Format::Hex)),
+                // Through the RefString to the text, sized to the current text so edits stay in its buffer
+                KIND_STRING => match unsafe { string_text(*rvalue) } {
+                    Some((_, size)) => Ok((format!("[[{address}]]"), ValueType::String(size + 1), Format::Decimal)),
+                    None => Err("The string has no text".to_string()),
+                },
+                kind => Err(format!("Values of kind {kind} can't be watched")),
+            };
+            match watched {
+                Ok((address, value_type, format)) => watch.add(WatchEntry {
+                    name,
+                    address,
+                    value_type,
+                    format,
+                    frozen: None,
+                    hotkeys: Vec::new(),
+                }),
+                Err(e) => println!("Could not watch {name}: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rvalue(value: &[u8], kind: u32) -> Vec<u8> {
+        let mut bytes = [value, &vec![0; 12 - value.len()]].concat();
+        bytes.extend(kind.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn map_header() {
+        let words = [16u32, 3, 15, 12, 0x1234];
+        let bytes = words.iter().flat_map(|w| w.to_le_bytes()).collect::<Vec<u8>>();
+        let header = MapHeader::parse(&bytes);
+        assert!(header.is_valid());
+        assert_eq!((header.size, header.used, header.mask, header.elements), (16, 3, 15, 0x1234));
+        for (size, used, mask) in [(12, 3, 11), (16, 3, 7), (16, 17, 15), (4, 1, 3)] {
+            let header = MapHeader { size, used, mask, elements: 0 };
+            assert!(!header.is_valid(), "{size} {used} {mask}");
+        }
+    }
+
+    #[test]
+    fn elements() {
+        // Value, key and hash of each slot, slots without a hash or a value are free
+        let slots = [[0x1000u32, 7, 0x8000_0007], [0, 0, 0], [0x2000, 9, 0], [0, 4, 0x8000_0004], [0x3000, -2i32 as u32, 0x8000_1234]];
+        let bytes = slots.iter().flatten().flat_map(|w| w.to_le_bytes()).collect::<Vec<u8>>();
+        let entries = parse_elements(&bytes);
+        assert_eq!(entries, [(7, 0x1000), (-2, 0x3000)]);
+        assert_eq!(entries_score(&entries, &HashSet::from([7, 8])), 1);
+        assert_eq!(entries_score(&entries, &HashSet::from([7, -2])), 2);
+        assert_eq!(entries_score(&[(1, 1), (2, 1), (3, 1)], &HashSet::from([1])), 0);
+    }
+
+    #[test]
+    fn format_values() {
+        assert_eq!(format_value(&rvalue(&2.5f64.to_le_bytes(), KIND_REAL), || None), "2.5");
+        assert_eq!(format_value(&rvalue(&1f64.to_le_bytes(), KIND_BOOL), || None), "true");
+        assert_eq!(format_value(&rvalue(&(-3i32).to_le_bytes(), KIND_INT32), || None), "-3");
+        assert_eq!(format_value(&rvalue(&(-3i64).to_le_bytes(), KIND_INT64), || None), "-3");
+        assert_eq!(format_value(&rvalue(&[], KIND_STRING), || Some("hi".to_string())), "\"hi\"");
+        assert_eq!(format_value(&rvalue(&[], KIND_STRING), || None), "\"\"");
+        assert_eq!(format_value(&rvalue(&0xabcdu32.to_le_bytes(), KIND_PTR), || None), "<ptr 0xabcd>");
+        assert_eq!(format_value(&rvalue(&[], KIND_UNDEFINED), || None), "undefined");
+        // The flags above the kind are ignored
+        assert_eq!(format_value(&rvalue(&[], 0x4000_0000 | KIND_ARRAY), || None), "<array>");
+        assert_eq!(format_value(&rvalue(&[], 99), || None), "<kind 99>");
+    }
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(parse_value(KIND_REAL, " 2.5 "), Ok(2.5f64.to_le_bytes().to_vec()));
+        assert_eq!(parse_value(KIND_BOOL, "true"), Ok(1f64.to_le_bytes().to_vec()));
+        assert_eq!(parse_value(KIND_BOOL, "0"), Ok(0f64.to_le_bytes().to_vec()));
+        assert_eq!(parse_value(KIND_INT32, "-3"), Ok((-3i32).to_le_bytes().to_vec()));
+        assert_eq!(parse_value(KIND_INT64, "1099511627776"), Ok((1i64 << 40).to_le_bytes().to_vec()));
+        assert!(parse_value(KIND_INT32, "1.5").is_err());
+        assert!(parse_value(KIND_BOOL, "yes").is_err());
+        assert!(parse_value(KIND_STRING, "text").is_err());
+    }
+}
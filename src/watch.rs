@@ -0,0 +1,526 @@
+use std::{collections::HashMap, env, fs, io, path::{Path, PathBuf}};
+use hudhook::imgui;
+use mmap_rs::MemoryAreas;
+use windows::Win32::UI::Input::KeyboardAndMouse::GetAsyncKeyState;
+
+use crate::address::{Address, Modules, Resolver};
+use crate::cheat_table;
+use rfd::FileDialog;
+use crate::sigscan::SignatureDef;
+
+pub const VALUE_TYPES: [&str; 8] = ["i8", "i16", "i32", "i64", "f32", "f64", "bool", "string"];
+pub const FORMATS: [&str; 2] = ["Decimal", "Hex"];
+const STRING_SIZE: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Bool,
+    String(usize), // Maximum size, including the NUL terminator
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Decimal,
+    Hex,
+}
+
+#[derive(Clone)]
+pub struct WatchEntry {
+    pub name: String,
+    pub address: String,
+    pub value_type: ValueType,
+    pub format: Format,
+    pub frozen: Option<Vec<u8>>, // Bytes written back every frame
+    pub hotkeys: Vec<Hotkey>,
+}
+
+#[derive(Clone)]
+pub struct Hotkey {
+    pub keys: Vec<u16>, // Virtual key codes that must be held together
+    pub action: HotkeyAction,
+    pub down: bool,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum HotkeyAction {
+    ToggleFreeze,
+    Freeze,
+    Unfreeze,
+    Set(String),
+}
+
+pub struct WatchList {
+    pub entries: Vec<WatchEntry>,
+    pub symbols: HashMap<String, Address>,
+    pub modules: Modules,
+    new_name: String,
+    new_address: String,
+    new_type: usize,
+    new_format: usize,
+    unsupported: Vec<String>,
+}
+
+impl Hotkey {
+    /// `KEY+KEY:action` with hexadecimal virtual key codes, the action being `toggle`, `freeze`, `unfreeze` or `set=VALUE`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (keys, action) = text.split_once(':')?;
+        Some(Self {
+            keys: keys.split('+').map(|k| u16::from_str_radix(k, 16).ok()).collect::<Option<Vec<u16>>>()?,
+            action: match action {
+                "toggle" => HotkeyAction::ToggleFreeze,
+                "freeze" => HotkeyAction::Freeze,
+                "unfreeze" => HotkeyAction::Unfreeze,
+                _ => HotkeyAction::Set(action.strip_prefix("set=")?.to_string()),
+            },
+            down: false,
+        })
+    }
+}
+
+impl std::fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keys = self.keys.iter().map(|k| format!("{k:X}")).collect::<Vec<String>>().join("+");
+        match &self.action {
+            HotkeyAction::ToggleFreeze => write!(f, "{keys}:toggle"),
+            HotkeyAction::Freeze => write!(f, "{keys}:freeze"),
+            HotkeyAction::Unfreeze => write!(f, "{keys}:unfreeze"),
+            HotkeyAction::Set(value) => write!(f, "{keys}:set={value}"),
+        }
+    }
+}
+
+impl ValueType {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "i8" => Self::I8,
+            "i16" => Self::I16,
+            "i32" => Self::I32,
+            "i64" => Self::I64,
+            "f32" => Self::F32,
+            "f64" => Self::F64,
+            "bool" => Self::Bool,
+            _ => Self::String(name.strip_prefix("string")?.parse().unwrap_or(STRING_SIZE)),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::I8 => "i8".to_string(),
+            Self::I16 => "i16".to_string(),
+            Self::I32 => "i32".to_string(),
+            Self::I64 => "i64".to_string(),
+            Self::F32 => "f32".to_string(),
+            Self::F64 => "f64".to_string(),
+            Self::Bool => "bool".to_string(),
+            Self::String(size) => format!("string{size}"),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Self::I8 | Self::Bool => 1,
+            Self::I16 => 2,
+            Self::I32 | Self::F32 => 4,
+            Self::I64 | Self::F64 => 8,
+            Self::String(size) => *size,
+        }
+    }
+
+    /// Formats the bytes read from the game.
+    pub fn format(&self, bytes: &[u8], format: Format) -> String {
+        macro_rules! int {
+            ($t:ty) => {{
+                let v = <$t>::from_le_bytes(bytes.try_into().unwrap());
+                match format {
+                    Format::Decimal => v.to_string(),
+                    Format::Hex => format!("0x{v:x}"),
+                }
+            }};
+        }
+        match self {
+            Self::I8 => int!(i8),
+            Self::I16 => int!(i16),
+            Self::I32 => int!(i32),
+            Self::I64 => int!(i64),
+            Self::F32 => f32::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            Self::F64 => f64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            Self::Bool => (bytes[0] != 0).to_string(),
+            Self::String(_) => {
+                let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+                String::from_utf8_lossy(&bytes[..end]).to_string()
+            }
+        }
+    }
+
+    /// Parses a value typed by the user into the bytes to write.
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, String> {
+        let text = text.trim();
+        macro_rules! int {
+            ($t:ty) => {{
+                let v = match text.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16).map(|v| v as $t).map_err(|e| e.to_string())?,
+                    None => text.parse::<$t>().map_err(|e| e.to_string())?,
+                };
+                v.to_le_bytes().to_vec()
+            }};
+        }
+        Ok(match self {
+            Self::I8 => int!(i8),
+            Self::I16 => int!(i16),
+            Self::I32 => int!(i32),
+            Self::I64 => int!(i64),
+            Self::F32 => text.parse::<f32>().map_err(|e| e.to_string())?.to_le_bytes().to_vec(),
+            Self::F64 => text.parse::<f64>().map_err(|e| e.to_string())?.to_le_bytes().to_vec(),
+            Self::Bool => match text {
+                "true" | "1" => vec![1],
+                "false" | "0" => vec![0],
+                _ => return Err(format!("{text} is not a bool")),
+            },
+            Self::String(size) => {
+                if text.len() >= *size {
+                    return Err(format!("The string is longer than {} bytes", size - 1));
+                }
+                let mut bytes = text.as_bytes().to_vec();
+                bytes.push(0);
+                bytes
+            }
+        })
+    }
+}
+
+/// Checks that every page of the range is mapped before touching it.
+pub fn is_readable(addr: usize, size: usize) -> bool {
+    match MemoryAreas::query(addr) {
+        Ok(Some(area)) => area.protection().contains(mmap_rs::Protection::READ) && addr + size <= area.end(),
+        _ => false,
+    }
+}
+
+/// Like [`is_readable`], for ranges that get written. Code and constants are mapped read only.
+pub fn is_writable(addr: usize, size: usize) -> bool {
+    match MemoryAreas::query(addr) {
+        Ok(Some(area)) => area.protection().contains(mmap_rs::Protection::WRITE) && addr + size <= area.end(),
+        _ => false,
+    }
+}
+
+/// Whether `addr` is in code.
+pub fn is_executable(addr: usize) -> bool {
+    matches!(MemoryAreas::query(addr), Ok(Some(area)) if area.protection().contains(mmap_rs::Protection::EXECUTE))
+}
+
+/// # Safety
+/// The range must be checked with [`is_readable`] first.
+pub unsafe fn read_bytes(addr: usize, size: usize) -> Vec<u8> {
+    core::slice::from_raw_parts(addr as *const u8, size).to_vec()
+}
+
+/// # Safety
+/// The range must be checked with [`is_writable`] first.
+pub unsafe fn write_bytes(addr: usize, bytes: &[u8]) {
+    core::ptr::copy_nonoverlapping(bytes.as_ptr(), addr as *mut u8, bytes.len());
+}
+
+/// Per game version folder next to the executable, where the tool keeps its settings.
+pub fn profile_dir() -> PathBuf {
+    let exe = env::current_exe().unwrap();
+    let mut dir = exe.parent().unwrap().to_path_buf();
+    dir.push("dfmodtool");
+    dir.push(exe.file_stem().unwrap());
+    dir
+}
+
+impl Default for WatchList {
+    fn default() -> Self {
+        let mut symbols = HashMap::new();
+        // DF 2.7.9c stuff, 0xa4e5e0 with the executable at its preferred base
+        symbols.insert("game_id".to_string(), Address::parse("\"DF CONNECTED v2.7.9c.exe\"+64E5E0").unwrap());
+        Self {
+            entries: vec![WatchEntry {
+                name: "Game ID".to_string(),
+                address: "game_id".to_string(),
+                value_type: ValueType::I32,
+                format: Format::Decimal,
+                frozen: None,
+                hotkeys: Vec::new(),
+            }],
+            symbols,
+            modules: Modules::default(),
+            new_name: String::new(),
+            new_address: String::new(),
+            new_type: 2,
+            new_format: 0,
+            unsupported: Vec::new(),
+        }
+    }
+}
+
+impl WatchList {
+    pub fn resolve(&self, address: &str) -> Result<usize, String> {
+        Resolver {
+            symbols: &self.symbols,
+            modules: &self.modules,
+        }.resolve(&Address::parse(address)?)
+    }
+
+    pub fn load(&mut self) {
+        self.modules.refresh();
+        let mut file = profile_dir();
+        file.push("symbols.txt");
+        if let Ok(data) = fs::read_to_string(&file) {
+            for line in data.lines() {
+                let Some((name, address)) = line.split_once('\t') else {
+                    continue;
+                };
+                let address = match address.strip_prefix("sig\t") {
+                    Some(signature) => SignatureDef::parse(signature).and_then(|s| s.scan(&self.modules)).map(|addr| {
+                        println!("Found signature {name} at 0x{addr:x}");
+                        Address::Number(addr)
+                    }),
+                    None => Address::parse(address),
+                };
+                match address {
+                    Ok(address) => {
+                        self.symbols.insert(name.trim().to_string(), address);
+                    }
+                    Err(e) => println!("Invalid symbol {name}: {e}"),
+                }
+            }
+        }
+        let mut file = profile_dir();
+        file.push("watch.txt");
+        let Ok(data) = fs::read_to_string(&file) else {
+            return;
+        };
+        self.entries.clear();
+        for line in data.lines() {
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            if fields.len() < 4 {
+                continue;
+            }
+            let Some(value_type) = ValueType::parse(fields[2]) else {
+                println!("Unknown watch type {}", fields[2]);
+                continue;
+            };
+            self.entries.push(WatchEntry {
+                name: fields[0].to_string(),
+                address: fields[1].to_string(),
+                value_type,
+                format: if fields[3] == "hex" { Format::Hex } else { Format::Decimal },
+                frozen: fields.get(4).filter(|f| !f.is_empty()).and_then(|f| value_type.encode(f).ok()),
+                hotkeys: fields.get(5).map(|h| h.split(';').filter_map(Hotkey::parse).collect()).unwrap_or_default(),
+            });
+        }
+        println!("========== Loaded {} watch entries ==========", self.entries.len());
+    }
+
+    /// Reports the errors instead of failing, it runs inside the game.
+    pub fn save(&self) {
+        if let Err(e) = self.write_profile() {
+            println!("========== Could not save the watch list: {e} ==========");
+        }
+    }
+
+    fn write_profile(&self) -> io::Result<()> {
+        let mut file = profile_dir();
+        fs::create_dir_all(&file)?;
+        file.push("watch.txt");
+        let mut data = String::new();
+        for entry in &self.entries {
+            data += &format!("{}\t{}\t{}\t{}\t{}\t{}\r\n",
+                entry.name.replace('\t', " "),
+                entry.address,
+                entry.value_type.name(),
+                if entry.format == Format::Hex { "hex" } else { "dec" },
+                entry.frozen.as_ref().map(|f| entry.value_type.format(f, Format::Decimal)).unwrap_or_default(),
+                entry.hotkeys.iter().map(|h| h.to_string()).collect::<Vec<String>>().join(";"));
+        }
+        fs::write(file, data)
+    }
+
+    pub fn add(&mut self, entry: WatchEntry) {
+        self.entries.push(entry);
+        self.save();
+    }
+
+    /// Writes the frozen values back, call it once per frame.
+    pub fn apply_frozen(&self) {
+        for entry in &self.entries {
+            if let (Some(bytes), Ok(addr)) = (&entry.frozen, self.resolve(&entry.address)) {
+                if is_writable(addr, bytes.len()) {
+                    unsafe { write_bytes(addr, bytes) };
+                }
+            }
+        }
+    }
+
+    /// Runs the hotkey actions whose keys just got pressed.
+    pub fn apply_hotkeys(&mut self) -> bool {
+        let mut changed = false;
+        for i in 0..self.entries.len() {
+            let addr = self.resolve(&self.entries[i].address);
+            let entry = &mut self.entries[i];
+            for hotkey in &mut entry.hotkeys {
+                let down = hotkey.keys.iter().all(|&k| unsafe { GetAsyncKeyState(k as i32) } as u16 & 0x8000 != 0);
+                let pressed = down && !hotkey.down;
+                hotkey.down = down;
+                let Ok(addr) = addr else {
+                    continue;
+                };
+                if !pressed || !is_readable(addr, entry.value_type.size()) {
+                    continue;
+                }
+                match &hotkey.action {
+                    HotkeyAction::ToggleFreeze if entry.frozen.is_some() => entry.frozen = None,
+                    HotkeyAction::ToggleFreeze | HotkeyAction::Freeze => {
+                        entry.frozen = Some(unsafe { read_bytes(addr, entry.value_type.size()) });
+                    }
+                    HotkeyAction::Unfreeze => entry.frozen = None,
+                    HotkeyAction::Set(_) if !is_writable(addr, entry.value_type.size()) => {
+                        println!("{} is at read only memory 0x{addr:x}", entry.name);
+                        continue;
+                    }
+                    HotkeyAction::Set(value) => match entry.value_type.encode(value) {
+                        Ok(bytes) => {
+                            unsafe { write_bytes(addr, &bytes) };
+                            if entry.frozen.is_some() {
+                                entry.frozen = Some(bytes);
+                            }
+                        }
+                        Err(e) => println!("Invalid hotkey value for {}: {e}", entry.name),
+                    },
+                }
+                println!("========== Hotkey for {} ==========", entry.name);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Adds the entries and symbols of a Cheat Engine table to the profile.
+    pub fn import_cheat_table(&mut self, file: &Path) {
+        let table = match fs::read(file).map_err(|e| e.to_string()).and_then(|data| cheat_table::import(&data)) {
+            Ok(table) => table,
+            Err(e) => {
+                println!("========== Could not import {}: {e} ==========", file.display());
+                return;
+            }
+        };
+        println!("========== Imported {} watch entries and {} symbols ==========", table.entries.len(), table.symbols.len());
+        for unsupported in &table.unsupported {
+            println!("Not imported: {unsupported}");
+        }
+        if !table.symbols.is_empty() {
+            let mut file = profile_dir();
+            file.push("symbols.txt");
+            let mut data = fs::read_to_string(&file).unwrap_or_default();
+            for (name, address) in table.symbols {
+                data += &format!("{name}\t{address}\r\n");
+                self.symbols.insert(name, address);
+            }
+            if let Err(e) = fs::create_dir_all(profile_dir()).and_then(|_| fs::write(file, data)) {
+                println!("========== Could not save the symbols: {e} ==========");
+            }
+        }
+        self.unsupported = table.unsupported;
+        self.entries.extend(table.entries);
+        self.save();
+    }
+
+    pub fn render(&mut self, ui: &imgui::Ui) {
+        self.apply_frozen();
+        let mut changed = self.apply_hotkeys();
+        let mut remove = None;
+        for i in 0..self.entries.len() {
+            let addr = self.resolve(&self.entries[i].address);
+            let entry = &mut self.entries[i];
+            let size = entry.value_type.size();
+            match addr {
+                Ok(addr) if is_readable(addr, size) => {
+                    let mut value = entry.value_type.format(&unsafe { read_bytes(addr, size) }, entry.format);
+                    ui.set_next_item_width(150.);
+                    if ui.input_text(format!("{}##watch{i}", entry.name), &mut value).enter_returns_true(true).build() {
+                        match entry.value_type.encode(&value) {
+                            Ok(_) if !is_writable(addr, size) => println!("{} is at read only memory 0x{addr:x}", entry.name),
+                            Ok(bytes) => {
+                                println!("========== Changed {} to {value} ==========", entry.name);
+                                unsafe { write_bytes(addr, &bytes) };
+                                if entry.frozen.is_some() {
+                                    entry.frozen = Some(bytes);
+                                    changed = true;
+                                }
+                            }
+                            Err(e) => println!("Invalid value for {}: {e}", entry.name),
+                        }
+                    }
+                    ui.same_line();
+                    let mut frozen = entry.frozen.is_some();
+                    if ui.checkbox(format!("Freeze##watch{i}"), &mut frozen) {
+                        entry.frozen = frozen.then(|| unsafe { read_bytes(addr, size) });
+                        changed = true;
+                    }
+                }
+                Ok(addr) => ui.text_colored([1., 0.2, 0.2, 1.], format!("{}: 0x{addr:x} is not readable", entry.name)),
+                Err(e) => ui.text_colored([1., 0.2, 0.2, 1.], format!("{}: {e}", entry.name)),
+            }
+            ui.same_line();
+            ui.text_disabled(format!("{} {}", entry.address, entry.value_type.name()));
+            ui.same_line();
+            if ui.small_button(format!("X##watch{i}")) {
+                remove = Some(i);
+            }
+        }
+        if let Some(i) = remove {
+            self.entries.remove(i);
+            changed = true;
+        }
+        ui.set_next_item_width(100.);
+        ui.input_text("Name##watch", &mut self.new_name).build();
+        ui.same_line();
+        ui.set_next_item_width(100.);
+        ui.input_text("Address##watch", &mut self.new_address).build();
+        ui.set_next_item_width(80.);
+        ui.combo_simple_string("Type##watch", &mut self.new_type, &VALUE_TYPES);
+        ui.same_line();
+        ui.set_next_item_width(80.);
+        ui.combo_simple_string("Format##watch", &mut self.new_format, &FORMATS);
+        ui.same_line();
+        if ui.button("Add Watch") {
+            match self.resolve(&self.new_address) {
+                Ok(_) => {
+                    let name = if self.new_name.is_empty() { self.new_address.clone() } else { self.new_name.clone() };
+                    self.entries.push(WatchEntry {
+                        name,
+                        address: self.new_address.trim().to_string(),
+                        value_type: ValueType::parse(VALUE_TYPES[self.new_type]).unwrap(),
+                        format: if self.new_format == 1 { Format::Hex } else { Format::Decimal },
+                        frozen: None,
+                        hotkeys: Vec::new(),
+                    });
+                    self.new_name.clear();
+                    self.new_address.clear();
+                    changed = true;
+                }
+                Err(e) => println!("========== {e} =========="),
+            }
+        }
+        ui.same_line();
+        if ui.button("Import .CT") {
+            if let Some(file) = FileDialog::new().add_filter("Cheat Tables", &["ct", "CT"]).pick_file() {
+                self.import_cheat_table(&file);
+            }
+        }
+        if !self.unsupported.is_empty() {
+            ui.text_colored([1., 0.8, 0., 1.], format!("{} table features were not imported, see the console", self.unsupported.len()));
+        }
+        if changed {
+            self.save();
+        }
+    }
+}